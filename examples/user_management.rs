@@ -5,10 +5,14 @@ use std::process::exit;
 use roca::prelude::*;
 
 fn main() {
-    let client = ClientXMLRPC::new(
-        String::from("oneadmin:pDi4mFBHue"),
-        String::from("http://192.168.33.10:2633/RPC2"),
-    );
+    // credentials are read from ONE_AUTH or ~/.one/one_auth, the endpoint from ONE_XMLRPC
+    let client = match ClientXMLRPC::from_env() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("client configuration error: {}", e);
+            exit(1);
+        }
+    };
     let controller = Controller::new(client);
 
     // create an user
@@ -0,0 +1,506 @@
+//! The batch module queues several calls to send them in a single `system.multicall` request,
+//! saving a HTTP round-trip per call.
+//!
+//! ```ignore
+//! use roca::prelude::*;
+//!
+//! let controller = Controller::new(ClientXMLRPC::from_env().unwrap());
+//!
+//! let mut batch = controller.batch();
+//! for id in [12, 13, 14] {
+//!     batch.vm_info(VmId(id));
+//! }
+//! for body in batch.send_as::<String>() {
+//!     match body.and_then(|b| VirtualMachine::try_from(b.as_str())) {
+//!         Ok(vm) => println!("{}", vm.name().unwrap()),
+//!         Err(e) => println!("{}", e),
+//!     }
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use serde_xmlrpc::Value;
+#[cfg(feature = "async")]
+use tracing::Instrument;
+
+use crate::common::ids::VmId;
+use crate::common::operations::ResourceKind;
+use crate::common::response::{FromValue, Response};
+use crate::common::{args_summary, Errors, OpenNebulaError};
+#[cfg(feature = "async")]
+use crate::controller::{AsyncController, AsyncRPCCaller};
+use crate::controller::{Controller, RPCCaller};
+use crate::telemetry;
+use crate::vm::Action;
+
+/// Queues calls to send them with a single request, see `Controller::batch`
+#[derive(Debug)]
+pub struct Batch<C: RPCCaller> {
+    controller: Controller<C>,
+    calls: Vec<(String, Vec<Value>)>,
+}
+
+impl<C: RPCCaller> Batch<C> {
+    pub fn new(controller: Controller<C>) -> Self {
+        Batch {
+            controller,
+            calls: Vec::new(),
+        }
+    }
+
+    /// Queues a call, its result will be at the same position in the results
+    pub fn add(&mut self, method: &str, args: Vec<Value>) -> &mut Self {
+        self.calls.push((method.to_string(), args));
+        self
+    }
+
+    /// Queues the info call of a resource, its result is the body of the resource
+    pub fn info(&mut self, kind: ResourceKind, id: i32) -> &mut Self {
+        self.add(&kind.method("info"), vec![id.into()])
+    }
+
+    /// Queues `one.vm.info`, its result is the body of the VM
+    pub fn vm_info(&mut self, id: VmId) -> &mut Self {
+        self.info(ResourceKind::VirtualMachine, id.into())
+    }
+
+    /// Queues `one.vm.action`, its result is the ID of the VM
+    pub fn vm_action(&mut self, id: VmId, action: Action) -> &mut Self {
+        self.add("one.vm.action", vec![action.to_string().into(), id.into()])
+    }
+
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Sends the calls and returns the payload of each call, in order, each call failing on its own.
+    /// The calls are sent one by one if the client can't batch them or if the server
+    /// rejects `system.multicall`.
+    pub fn send(self) -> Vec<Result<Value, Errors>> {
+        if self.calls.is_empty() {
+            return Vec::new();
+        }
+
+        let span = telemetry::batch_span(&self.calls);
+        let _entered = span.enter();
+
+        let start = Instant::now();
+        let results = self.send_batch();
+        telemetry::record_results(&span, start, &results);
+        results
+    }
+
+    fn send_batch(self) -> Vec<Result<Value, Errors>> {
+        let raw = match self.controller.client.multicall(&self.calls) {
+            Some(raw) => raw,
+            None => return self.send_sequentially(),
+        };
+        match decode(raw, &self.calls) {
+            Outcome::Results(results) => results,
+            Outcome::Rejected => self.send_sequentially(),
+        }
+    }
+
+    /// Sends the calls like `send` and decodes the payloads, for a batch of calls of the same kind
+    pub fn send_as<T: FromValue>(self) -> Vec<Result<T, Errors>> {
+        self.send()
+            .into_iter()
+            .map(|result| result.and_then(T::from_value))
+            .collect()
+    }
+
+    fn send_sequentially(self) -> Vec<Result<Value, Errors>> {
+        self.calls
+            .into_iter()
+            .map(|(method, args)| self.controller.call::<Value>(&method, args))
+            .collect()
+    }
+}
+
+/// The asynchronous counterpart of Batch, see `AsyncController::batch`
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncBatch<C: AsyncRPCCaller> {
+    controller: AsyncController<C>,
+    calls: Vec<(String, Vec<Value>)>,
+}
+
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncBatch<C> {
+    pub fn new(controller: AsyncController<C>) -> Self {
+        AsyncBatch {
+            controller,
+            calls: Vec::new(),
+        }
+    }
+
+    /// Queues a call, its result will be at the same position in the results
+    pub fn add(&mut self, method: &str, args: Vec<Value>) -> &mut Self {
+        self.calls.push((method.to_string(), args));
+        self
+    }
+
+    /// Queues the info call of a resource, its result is the body of the resource
+    pub fn info(&mut self, kind: ResourceKind, id: i32) -> &mut Self {
+        self.add(&kind.method("info"), vec![id.into()])
+    }
+
+    /// Queues `one.vm.info`, its result is the body of the VM
+    pub fn vm_info(&mut self, id: VmId) -> &mut Self {
+        self.info(ResourceKind::VirtualMachine, id.into())
+    }
+
+    /// Queues `one.vm.action`, its result is the ID of the VM
+    pub fn vm_action(&mut self, id: VmId, action: Action) -> &mut Self {
+        self.add("one.vm.action", vec![action.to_string().into(), id.into()])
+    }
+
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Sends the calls and returns the payload of each call, in order, each call failing on its own.
+    /// The calls are sent one by one if the client can't batch them or if the server
+    /// rejects `system.multicall`.
+    pub async fn send(self) -> Vec<Result<Value, Errors>> {
+        if self.calls.is_empty() {
+            return Vec::new();
+        }
+
+        let span = telemetry::batch_span(&self.calls);
+
+        let start = Instant::now();
+        let results = self.send_batch().instrument(span.clone()).await;
+        telemetry::record_results(&span, start, &results);
+        results
+    }
+
+    async fn send_batch(self) -> Vec<Result<Value, Errors>> {
+        let raw = match self.controller.client.multicall(&self.calls).await {
+            Some(raw) => raw,
+            None => return self.send_sequentially().await,
+        };
+        match decode(raw, &self.calls) {
+            Outcome::Results(results) => results,
+            Outcome::Rejected => self.send_sequentially().await,
+        }
+    }
+
+    /// Sends the calls like `send` and decodes the payloads, for a batch of calls of the same kind
+    pub async fn send_as<T: FromValue>(self) -> Vec<Result<T, Errors>> {
+        self.send()
+            .await
+            .into_iter()
+            .map(|result| result.and_then(T::from_value))
+            .collect()
+    }
+
+    async fn send_sequentially(self) -> Vec<Result<Value, Errors>> {
+        let mut results = Vec::with_capacity(self.calls.len());
+        for (method, args) in self.calls {
+            results.push(self.controller.call::<Value>(&method, args).await);
+        }
+        results
+    }
+}
+
+enum Outcome {
+    Results(Vec<Result<Value, Errors>>),
+    // the server doesn't support system.multicall, nothing was called
+    Rejected,
+}
+
+fn decode(raw: Result<String, Errors>, calls: &[(String, Vec<Value>)]) -> Outcome {
+    let values = raw.and_then(|raw| Ok(serde_xmlrpc::response_from_str::<Vec<Value>>(&raw)?));
+
+    match values {
+        Ok(values) => Outcome::Results(match from_values(values, calls) {
+            Ok(results) => results,
+            Err(e) => calls.iter().map(|_| Err(copy_error(&e))).collect(),
+        }),
+        Err(e) if is_unsupported(&e) => Outcome::Rejected,
+        // the calls may have been run, it's not safe to send them again
+        Err(e) => Outcome::Results(calls.iter().map(|_| Err(copy_error(&e))).collect()),
+    }
+}
+
+// system.multicall is unknown to the server: a "method not found" fault or HTTP error
+fn is_unsupported(e: &Errors) -> bool {
+    match e {
+        // the xmlrpc-c and the specification codes
+        Errors::XMLRPC(serde_xmlrpc::Error::Fault(fault)) => {
+            matches!(fault.fault_code, -506 | -32601)
+        }
+        Errors::HTTPStatus(code, _) => matches!(code, 404 | 405 | 501),
+        _ => false,
+    }
+}
+
+/// Builds the results of the calls from the values of a `system.multicall` response:
+/// each successful call is wrapped in an array, a failed call is a fault structure
+pub fn from_values(
+    values: Vec<Value>,
+    calls: &[(String, Vec<Value>)],
+) -> Result<Vec<Result<Value, Errors>>, Errors> {
+    if values.len() != calls.len() {
+        return Err(Errors::Roca(format!(
+            "unexpected multicall response: {} results for {} calls",
+            values.len(),
+            calls.len()
+        )));
+    }
+
+    let results = values
+        .into_iter()
+        .zip(calls)
+        .map(|(value, (method, args))| {
+            let summary = args_summary(args);
+            match value {
+                Value::Array(mut wrapped) if wrapped.len() == 1 => match wrapped.pop() {
+                    Some(Value::Array(values)) => {
+                        Response::<Value>::from_values(values, method, &summary)
+                            .map(|resp| resp.payload)
+                    }
+                    v => Err(unexpected(&v)),
+                },
+                Value::Struct(fault) => Err(fault_error(fault, method, summary)),
+                v => Err(unexpected(&Some(v))),
+            }
+        })
+        .collect();
+
+    Ok(results)
+}
+
+fn fault_error(mut fault: BTreeMap<String, Value>, method: &str, args: String) -> Errors {
+    let code = match fault.remove("faultCode") {
+        Some(Value::Int(code)) => code,
+        _ => 0,
+    };
+    let message = match fault.remove("faultString") {
+        Some(Value::String(message)) => message,
+        _ => String::new(),
+    };
+
+    Errors::OpenNebula(OpenNebulaError {
+        code: code.into(),
+        message,
+        method: method.to_string(),
+        args,
+    })
+}
+
+fn unexpected(value: &Option<Value>) -> Errors {
+    Errors::Roca(format!(
+        "unexpected multicall response: expected a result, got {:?}",
+        value
+    ))
+}
+
+// the error of the whole request, reported for each call
+fn copy_error(e: &Errors) -> Errors {
+    match e {
+        Errors::HTTPReq(e) => Errors::HTTPReq(e.clone()),
+        Errors::Connect(e) => Errors::Connect(e.clone()),
+        Errors::HTTPRespHandling(e) => Errors::HTTPRespHandling(e.clone()),
+        Errors::HTTPStatus(code, e) => Errors::HTTPStatus(*code, e.clone()),
+        Errors::Roca(e) => Errors::Roca(e.clone()),
+        Errors::PolicyDenied(e) => Errors::PolicyDenied(e.clone()),
+        e => Errors::Roca(format!("multicall request failed: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(all(feature = "client", feature = "blocking"))]
+    use crate::client::ClientXMLRPC;
+    use crate::common::ErrorCode;
+    use crate::testing::cassette::{Cassette, Replayer};
+    #[cfg(all(feature = "client", feature = "blocking"))]
+    use crate::testing::server::{FakeOned, Reply};
+    use crate::testing::xmlrpc;
+
+    fn calls() -> Vec<(String, Vec<Value>)> {
+        vec![
+            ("one.vm.info".to_string(), vec![12.into()]),
+            ("one.vm.info".to_string(), vec![13.into()]),
+            (
+                "one.vm.chown".to_string(),
+                vec![12.into(), 2.into(), (-1).into()],
+            ),
+        ]
+    }
+
+    #[test]
+    fn multicall_values() {
+        let values = vec![
+            Value::Array(vec![xmlrpc::success_value("<VM><ID>12</ID></VM>")]),
+            Value::Array(vec![xmlrpc::failure_value(
+                "[one.vm.info] Error getting virtual machine [13].",
+                0x400,
+            )]),
+            xmlrpc::fault_value(-501, "Method not found"),
+        ];
+
+        let results = from_values(values, &calls()).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results[0].as_ref().unwrap(),
+            &Value::String("<VM><ID>12</ID></VM>".to_string())
+        );
+        match &results[1] {
+            Err(Errors::OpenNebula(e)) => {
+                assert_eq!(e.code, ErrorCode::NoExists);
+                assert_eq!(e.args, "13");
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match &results[2] {
+            Err(Errors::OpenNebula(e)) => {
+                assert_eq!(e.code, ErrorCode::Unknown(-501));
+                assert_eq!(e.method, "one.vm.chown");
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        // missing results
+        assert!(matches!(
+            from_values(vec![Value::Array(vec![])], &calls()),
+            Err(Errors::Roca(_))
+        ));
+    }
+
+    #[test]
+    fn batch_typed_calls() {
+        let controller = Controller::new(Replayer::new(Cassette::new()));
+
+        let mut batch = controller.batch();
+        batch
+            .vm_info(VmId(12))
+            .vm_action(VmId(12), Action::Stop)
+            .info(ResourceKind::Image, 5);
+        assert_eq!(
+            batch.calls,
+            vec![
+                ("one.vm.info".to_string(), vec![12.into()]),
+                ("one.vm.action".to_string(), vec!["stop".into(), 12.into()]),
+                ("one.image.info".to_string(), vec![5.into()]),
+            ]
+        );
+    }
+
+    #[cfg(all(feature = "client", feature = "blocking"))]
+    #[test]
+    fn batch_single_request() {
+        let server = FakeOned::start().unwrap();
+        server
+            .reply("one.vm.info", Reply::body("<VM><ID>12</ID></VM>"))
+            .reply_once(
+                "one.vm.info",
+                Reply::failure("[one.vm.info] locked", 0x8000),
+            )
+            .reply("one.vm.chown", Reply::id(12));
+
+        let client = ClientXMLRPC::new(String::from("oneadmin:pDi4mFBHue"), server.endpoint());
+        let controller = Controller::new(client);
+
+        let mut batch = controller.batch();
+        for (method, args) in calls() {
+            batch.add(&method, args);
+        }
+        assert_eq!(batch.len(), 3);
+
+        let results = batch.send();
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results[0].as_ref().unwrap_err().code(),
+            Some(ErrorCode::Locked)
+        );
+        assert!(results[1].is_ok());
+        assert_eq!(results[2].as_ref().unwrap(), &Value::Int(12));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "system.multicall");
+        // each call is authenticated
+        assert_eq!(requests[0].body.matches("oneadmin:pDi4mFBHue").count(), 3);
+    }
+
+    #[cfg(all(feature = "client", feature = "blocking"))]
+    #[test]
+    fn batch_fallback() {
+        let server = FakeOned::start().unwrap();
+        server
+            .reply(
+                "system.multicall",
+                Reply::HTTPStatus(404, "not found".to_string()),
+            )
+            .reply("one.vm.info", Reply::body("<VM><ID>12</ID></VM>"))
+            .reply("one.vm.chown", Reply::id(12));
+
+        let client = ClientXMLRPC::new(String::from("oneadmin:pDi4mFBHue"), server.endpoint());
+        let controller = Controller::new(client);
+
+        let mut batch = controller.batch();
+        for (method, args) in calls() {
+            batch.add(&method, args);
+        }
+        let results = batch.send_as::<String>();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), "<VM><ID>12</ID></VM>");
+        // the chown payload is an ID
+        assert!(matches!(results[2], Err(Errors::Roca(_))));
+
+        let methods: Vec<String> = server.requests().into_iter().map(|r| r.method).collect();
+        assert_eq!(
+            methods,
+            vec![
+                "system.multicall",
+                "one.vm.info",
+                "one.vm.info",
+                "one.vm.chown"
+            ]
+        );
+    }
+
+    #[cfg(all(feature = "client", feature = "blocking"))]
+    #[test]
+    fn batch_bad_gateway() {
+        let server = FakeOned::start().unwrap();
+        server
+            .reply(
+                "system.multicall",
+                Reply::HTTPStatus(502, "bad gateway".to_string()),
+            )
+            .reply("one.vm.chown", Reply::id(12));
+
+        let client = ClientXMLRPC::new(String::from("oneadmin:pDi4mFBHue"), server.endpoint());
+        let controller = Controller::new(client);
+
+        let mut batch = controller.batch();
+        for (method, args) in calls() {
+            batch.add(&method, args);
+        }
+        // oned may have run the calls behind the proxy, they're not sent again
+        let results = batch.send();
+        assert_eq!(results.len(), 3);
+        for result in results {
+            assert!(matches!(result, Err(Errors::HTTPStatus(502, _))));
+        }
+        assert_eq!(server.requests().len(), 1);
+
+        assert!(is_unsupported(&Errors::HTTPStatus(501, String::new())));
+        assert!(!is_unsupported(&Errors::HTTPStatus(503, String::new())));
+    }
+}
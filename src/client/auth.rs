@@ -0,0 +1,371 @@
+//! The auth module builds the session string sent as first parameter of each call.
+//!
+//! Besides the core `user:password` session, the `auth-ssh` and `auth-x509` features provide
+//! the login tokens expected by the OpenNebula `ssh` and `x509` authentication drivers,
+//! signed locally with the private key of the user like `oneuser login --ssh` does.
+
+use std::fmt::Debug;
+
+use crate::common::{Errors, Secret};
+
+/// AuthProvider returns the "user:secret" session string of the calls
+pub trait AuthProvider: Debug + Send + Sync {
+    fn session(&self) -> Result<Secret, Errors>;
+}
+
+/// The core session string, i.e. "user:password" or "user:token"
+impl AuthProvider for Secret {
+    fn session(&self) -> Result<Secret, Errors> {
+        Ok(self.clone())
+    }
+}
+
+#[cfg(feature = "auth-ssh")]
+pub use signed::SshAuth;
+#[cfg(feature = "auth-x509")]
+pub use signed::X509Auth;
+
+#[cfg(any(feature = "auth-ssh", feature = "auth-x509"))]
+mod signed {
+    use std::fmt::Debug;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Mutex;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use openssl::base64;
+    use openssl::pkey::Private;
+    use openssl::rsa::{Padding, Rsa};
+    #[cfg(feature = "auth-x509")]
+    use openssl::x509::X509;
+
+    use super::AuthProvider;
+    use crate::common::{Errors, Secret};
+
+    const DEFAULT_VALIDITY: Duration = Duration::from_secs(3600);
+
+    fn read_file(path: &Path) -> Result<Vec<u8>, Errors> {
+        fs::read(path).map_err(|e| Errors::Roca(format!("can't read {}: {}", path.display(), e)))
+    }
+
+    fn read_key(pem: &[u8]) -> Result<Rsa<Private>, Errors> {
+        Rsa::private_key_from_pem(pem)
+            .map_err(|e| Errors::Roca(format!("invalid RSA private key: {}", e)))
+    }
+
+    // Like the OpenNebula drivers: the text is encrypted with the private key (PKCS#1 padding)
+    // then base64 encoded on a single line
+    fn sign(key: &Rsa<Private>, text: &str) -> Result<String, Errors> {
+        let mut signed = vec![0; key.size() as usize];
+        let len = key
+            .private_encrypt(text.as_bytes(), &mut signed, Padding::PKCS1)
+            .map_err(|e| Errors::Roca(format!("can't sign the login token: {}", e)))?;
+
+        Ok(base64::encode_block(&signed[..len]))
+    }
+
+    fn expiration(validity: Duration) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        (now + validity).as_secs()
+    }
+
+    // Keeps a signed token until it's about to expire, so the key isn't used for each call
+    #[derive(Default)]
+    struct TokenCache {
+        token: Mutex<Option<(Secret, SystemTime)>>,
+    }
+
+    impl TokenCache {
+        // Returns the cached token, or a new one if it expires in less than a tenth of its validity
+        fn get<F>(&self, validity: Duration, generate: F) -> Result<Secret, Errors>
+        where
+            F: FnOnce() -> Result<Secret, Errors>,
+        {
+            let mut token = self.token.lock().unwrap_or_else(|e| e.into_inner());
+
+            if let Some((secret, renew_at)) = token.as_ref() {
+                if SystemTime::now() < *renew_at {
+                    return Ok(secret.clone());
+                }
+            }
+
+            let secret = generate()?;
+            let renew_at = SystemTime::now() + validity - validity / 10;
+            *token = Some((secret.clone(), renew_at));
+            Ok(secret)
+        }
+    }
+
+    impl Debug for TokenCache {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("TokenCache([REDACTED])")
+        }
+    }
+
+    /// SshAuth builds the login tokens of the `ssh` driver: "user:expiration" signed with
+    /// the RSA private key of the user, the public key being the password of the user.
+    /// The key should be in PEM format, convert it with `ssh-keygen -p -m PEM` if needed.
+    #[cfg(feature = "auth-ssh")]
+    pub struct SshAuth {
+        username: String,
+        key: Rsa<Private>,
+        validity: Duration,
+        cache: TokenCache,
+    }
+
+    #[cfg(feature = "auth-ssh")]
+    impl SshAuth {
+        pub fn new(username: &str, private_key_pem: &[u8]) -> Result<Self, Errors> {
+            Ok(SshAuth {
+                username: username.to_string(),
+                key: read_key(private_key_pem)?,
+                validity: DEFAULT_VALIDITY,
+                cache: TokenCache::default(),
+            })
+        }
+
+        pub fn from_file<P: AsRef<Path>>(username: &str, private_key: P) -> Result<Self, Errors> {
+            Self::new(username, &read_file(private_key.as_ref())?)
+        }
+
+        /// Sets the validity of the tokens, one hour by default
+        pub fn validity(mut self, validity: Duration) -> Self {
+            self.validity = validity;
+            self
+        }
+
+        /// Builds a new token, valid from now
+        pub fn login_token(&self) -> Result<Secret, Errors> {
+            let text = format!("{}:{}", self.username, expiration(self.validity));
+            let token = sign(&self.key, &text)?;
+
+            Ok(Secret::new(format!("{}:{}", self.username, token)))
+        }
+    }
+
+    #[cfg(feature = "auth-ssh")]
+    impl AuthProvider for SshAuth {
+        fn session(&self) -> Result<Secret, Errors> {
+            self.cache.get(self.validity, || self.login_token())
+        }
+    }
+
+    #[cfg(feature = "auth-ssh")]
+    impl Debug for SshAuth {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("SshAuth")
+                .field("username", &self.username)
+                .field("validity", &self.validity)
+                .finish()
+        }
+    }
+
+    /// X509Auth builds the login tokens of the `x509` driver: "user:DN:expiration" signed with
+    /// the private key of the certificate, followed by the certificate chain.
+    /// The DN of the certificate should be the password of the user.
+    #[cfg(feature = "auth-x509")]
+    pub struct X509Auth {
+        username: String,
+        key: Rsa<Private>,
+        chain: Vec<X509>,
+        dn: String,
+        validity: Duration,
+        cache: TokenCache,
+    }
+
+    #[cfg(feature = "auth-x509")]
+    impl X509Auth {
+        /// The certificates PEM starts with the user certificate,
+        /// optionally followed by the intermediate certificates
+        pub fn new(
+            username: &str,
+            certificates_pem: &[u8],
+            key_pem: &[u8],
+        ) -> Result<Self, Errors> {
+            let chain = X509::stack_from_pem(certificates_pem)
+                .map_err(|e| Errors::Roca(format!("invalid certificate: {}", e)))?;
+            let dn = match chain.first() {
+                Some(certificate) => distinguished_name(certificate),
+                None => return Err(Errors::Roca("no certificate found".to_string())),
+            };
+
+            Ok(X509Auth {
+                username: username.to_string(),
+                key: read_key(key_pem)?,
+                chain,
+                dn,
+                validity: DEFAULT_VALIDITY,
+                cache: TokenCache::default(),
+            })
+        }
+
+        pub fn from_files<P: AsRef<Path>, K: AsRef<Path>>(
+            username: &str,
+            certificates: P,
+            key: K,
+        ) -> Result<Self, Errors> {
+            Self::new(
+                username,
+                &read_file(certificates.as_ref())?,
+                &read_file(key.as_ref())?,
+            )
+        }
+
+        /// Sets the validity of the tokens, one hour by default
+        pub fn validity(mut self, validity: Duration) -> Self {
+            self.validity = validity;
+            self
+        }
+
+        /// The subject of the user certificate, i.e. "/C=ES/O=OpenNebula/CN=oneadmin"
+        pub fn dn(&self) -> &str {
+            &self.dn
+        }
+
+        /// Builds a new token, valid from now
+        pub fn login_token(&self) -> Result<Secret, Errors> {
+            let text = format!(
+                "{}:{}:{}",
+                self.username,
+                self.dn,
+                expiration(self.validity)
+            );
+            let mut token = sign(&self.key, &text)?;
+
+            for certificate in &self.chain {
+                let pem = certificate
+                    .to_pem()
+                    .map_err(|e| Errors::Roca(format!("invalid certificate: {}", e)))?;
+                token.push(':');
+                token.push_str(&String::from_utf8_lossy(&pem));
+            }
+
+            Ok(Secret::new(format!(
+                "{}:{}",
+                self.username,
+                base64::encode_block(token.as_bytes())
+            )))
+        }
+    }
+
+    // The one line format of the subject used by the driver, i.e. "/C=ES/CN=oneadmin"
+    #[cfg(feature = "auth-x509")]
+    fn distinguished_name(certificate: &X509) -> String {
+        certificate
+            .subject_name()
+            .entries()
+            .map(|entry| {
+                let name = entry.object().nid().short_name().unwrap_or("UNDEF");
+                let value = String::from_utf8_lossy(entry.data().as_slice());
+                format!("/{}={}", name, value)
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "auth-x509")]
+    impl AuthProvider for X509Auth {
+        fn session(&self) -> Result<Secret, Errors> {
+            self.cache.get(self.validity, || self.login_token())
+        }
+    }
+
+    #[cfg(feature = "auth-x509")]
+    impl Debug for X509Auth {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("X509Auth")
+                .field("username", &self.username)
+                .field("dn", &self.dn)
+                .field("validity", &self.validity)
+                .finish()
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        #[cfg(feature = "auth-x509")]
+        use openssl::{
+            asn1::Asn1Time,
+            bn::BigNum,
+            hash::MessageDigest,
+            pkey::PKey,
+            x509::{X509Builder, X509NameBuilder},
+        };
+
+        use super::*;
+
+        fn decrypt(key: &Rsa<Private>, token: &str) -> String {
+            let signed = base64::decode_block(token).unwrap();
+            let mut text = vec![0; key.size() as usize];
+            let len = key
+                .public_decrypt(&signed, &mut text, Padding::PKCS1)
+                .unwrap();
+            String::from_utf8(text[..len].to_vec()).unwrap()
+        }
+
+        #[cfg(feature = "auth-ssh")]
+        #[test]
+        fn ssh_login_token() {
+            let key = Rsa::generate(2048).unwrap();
+            let auth = SshAuth::new("oneadmin", &key.private_key_to_pem().unwrap()).unwrap();
+
+            let session = auth.session().unwrap();
+            let (username, token) = session.expose().split_once(':').unwrap();
+            assert_eq!(username, "oneadmin");
+
+            let text = decrypt(&key, token);
+            let (username, expiration) = text.split_once(':').unwrap();
+            assert_eq!(username, "oneadmin");
+            assert!(expiration.parse::<u64>().unwrap() > super::expiration(Duration::ZERO));
+
+            // the token is cached
+            assert_eq!(auth.session().unwrap(), session);
+            assert!(!format!("{:?}", auth).contains(token));
+
+            assert!(SshAuth::new("oneadmin", b"not a key").is_err());
+        }
+
+        #[cfg(feature = "auth-x509")]
+        #[test]
+        fn x509_login_token() {
+            let key = Rsa::generate(2048).unwrap();
+            let pkey = PKey::from_rsa(key.clone()).unwrap();
+
+            let mut name = X509NameBuilder::new().unwrap();
+            name.append_entry_by_text("C", "ES").unwrap();
+            name.append_entry_by_text("CN", "oneadmin").unwrap();
+            let name = name.build();
+
+            let mut builder = X509Builder::new().unwrap();
+            builder.set_version(2).unwrap();
+            builder
+                .set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+                .unwrap();
+            builder.set_subject_name(&name).unwrap();
+            builder.set_issuer_name(&name).unwrap();
+            builder.set_pubkey(&pkey).unwrap();
+            builder
+                .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+                .unwrap();
+            builder
+                .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+                .unwrap();
+            builder.sign(&pkey, MessageDigest::sha256()).unwrap();
+            let certificate = builder.build().to_pem().unwrap();
+
+            let auth = X509Auth::new("oneadmin", &certificate, &key.private_key_to_pem().unwrap())
+                .unwrap();
+            assert_eq!(auth.dn(), "/C=ES/CN=oneadmin");
+
+            let session = auth.login_token().unwrap();
+            let (username, token) = session.expose().split_once(':').unwrap();
+            assert_eq!(username, "oneadmin");
+
+            let token = String::from_utf8(base64::decode_block(token).unwrap()).unwrap();
+            let (signed, chain) = token.split_once(':').unwrap();
+            assert_eq!(chain.as_bytes(), certificate.as_slice());
+            assert!(decrypt(&key, signed).starts_with("oneadmin:/C=ES/CN=oneadmin:"));
+        }
+    }
+}
@@ -0,0 +1,366 @@
+//! The builder module allows to configure the HTTP client used by the XML-RPC clients
+
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+use std::{fs, path::PathBuf};
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::Proxy;
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+use reqwest::{Certificate, Identity};
+
+use crate::client::auth::AuthProvider;
+use crate::client::credentials::{self, DEFAULT_ENDPOINT};
+#[cfg(any(feature = "blocking", feature = "async"))]
+use crate::client::SessionOptions;
+#[cfg(feature = "async")]
+use crate::client::{AsyncClientXMLRPC, AsyncReqwestTransport, AsyncSessionClient};
+#[cfg(feature = "blocking")]
+use crate::client::{ClientXMLRPC, ReqwestTransport, SessionClient};
+use crate::common::{Errors, Secret};
+
+const DEFAULT_USER_AGENT: &str = concat!("roca/", env!("CARGO_PKG_VERSION"));
+
+/// Configures and builds a client owning a single long-lived HTTP client,
+/// so connections are reused between the XML-RPC calls.
+/// The TLS options require the `native-tls` or the `rustls` feature.
+pub struct ClientBuilder {
+    auth: Option<Arc<dyn AuthProvider>>,
+    endpoint: String,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    tls: TLSOptions,
+    proxy: Option<Proxy>,
+    no_proxy: bool,
+    user_agent: String,
+    headers: Vec<(String, String)>,
+}
+
+#[cfg(any(feature = "native-tls", feature = "rustls"))]
+#[derive(Default)]
+struct TLSOptions {
+    root_certificates: Vec<Certificate>,
+    ca_files: Vec<PathBuf>,
+    built_in_root_certs: bool,
+    identity: Option<Identity>,
+    accept_invalid_certs: bool,
+}
+
+// Both reqwest builders share the same configuration methods but have different types
+macro_rules! configure_http_builder {
+    ($self:ident, $http_builder:expr) => {{
+        let mut http_builder = $http_builder
+            .user_agent($self.user_agent.clone())
+            .default_headers($self.header_map()?);
+
+        #[cfg(any(feature = "native-tls", feature = "rustls"))]
+        {
+            http_builder = http_builder
+                .tls_built_in_root_certs($self.tls.built_in_root_certs)
+                .danger_accept_invalid_certs($self.tls.accept_invalid_certs);
+
+            for certificate in $self.certificates()? {
+                http_builder = http_builder.add_root_certificate(certificate);
+            }
+            if let Some(identity) = $self.tls.identity.clone() {
+                http_builder = http_builder.identity(identity);
+            }
+        }
+
+        if let Some(timeout) = $self.connect_timeout {
+            http_builder = http_builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = $self.timeout {
+            http_builder = http_builder.timeout(timeout);
+        }
+        if $self.no_proxy {
+            http_builder = http_builder.no_proxy();
+        } else if let Some(proxy) = $self.proxy.clone() {
+            http_builder = http_builder.proxy(proxy);
+        }
+
+        match http_builder.build() {
+            Ok(c) => c,
+            Err(e) => return Err(Errors::HTTPReq(e.to_string())),
+        }
+    }};
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        ClientBuilder {
+            auth: None,
+            endpoint: DEFAULT_ENDPOINT.to_string(),
+            connect_timeout: None,
+            timeout: None,
+            #[cfg(any(feature = "native-tls", feature = "rustls"))]
+            tls: TLSOptions {
+                built_in_root_certs: true,
+                ..Default::default()
+            },
+            proxy: None,
+            no_proxy: false,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            headers: Vec::new(),
+        }
+    }
+
+    /// Returns a builder initialized with the credentials and the endpoint resolved like the CLI tools:
+    /// ONE_AUTH or ~/.one/one_auth, then ONE_XMLRPC or the default endpoint
+    pub fn from_env() -> Result<Self, Errors> {
+        let auth = credentials::discover_auth()?;
+
+        Ok(Self::new()
+            .auth(auth)
+            .endpoint(credentials::discover_endpoint()))
+    }
+
+    /// Sets the session string, i.e. "user:password"
+    pub fn auth<S: Into<Secret>>(mut self, auth: S) -> Self {
+        self.auth = Some(Arc::new(auth.into()));
+        self
+    }
+
+    /// Sets the provider of the session string, i.e. `SshAuth` or `X509Auth`
+    pub fn auth_provider<P: AuthProvider + 'static>(mut self, provider: P) -> Self {
+        self.auth = Some(Arc::new(provider));
+        self
+    }
+
+    /// Sets the XML-RPC endpoint, defaults to http://localhost:2633/RPC2
+    pub fn endpoint<S: Into<String>>(mut self, endpoint: S) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    /// Sets the timeout to establish the connection
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the timeout of a whole request, from the connection up to the end of the response
+    /// reading. The HTTP client has no separate read timeout, this one bounds the reading of the
+    /// response of oned too: it should cover the slowest calls, i.e. a large pool info
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Adds a trusted root certificate, for instance a private CA
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    pub fn add_root_certificate(mut self, certificate: Certificate) -> Self {
+        self.tls.root_certificates.push(certificate);
+        self
+    }
+
+    /// Adds a trusted root certificate read from a PEM file when building the client
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    pub fn ca_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.tls.ca_files.push(path.into());
+        self
+    }
+
+    /// Controls the use of the system root certificates, enabled by default
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    pub fn tls_built_in_root_certs(mut self, enable: bool) -> Self {
+        self.tls.built_in_root_certs = enable;
+        self
+    }
+
+    /// Sets the client certificate sent to the frontend
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.tls.identity = Some(identity);
+        self
+    }
+
+    /// Disables the certificate validation.
+    /// Only use it with lab frontends having self-signed certificates.
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.tls.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Sends the requests through the proxy, the system proxy is used by default
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Disables any proxy, including the system one
+    pub fn no_proxy(mut self) -> Self {
+        self.no_proxy = true;
+        self
+    }
+
+    /// Sets the User-Agent header, defaults to roca/<version>
+    pub fn user_agent<S: Into<String>>(mut self, user_agent: S) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    /// Adds a header to each request
+    pub fn header<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    fn header_map(&self) -> Result<HeaderMap, Errors> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = match HeaderName::from_bytes(name.as_bytes()) {
+                Ok(n) => n,
+                Err(e) => return Err(Errors::Roca(format!("invalid header {}: {}", name, e))),
+            };
+            let value = match HeaderValue::from_str(value) {
+                Ok(v) => v,
+                Err(e) => return Err(Errors::Roca(format!("invalid header {}: {}", name, e))),
+            };
+            headers.append(name, value);
+        }
+        Ok(headers)
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    fn certificates(&self) -> Result<Vec<Certificate>, Errors> {
+        let mut certificates = self.tls.root_certificates.clone();
+        for path in &self.tls.ca_files {
+            let pem = match fs::read(path) {
+                Ok(p) => p,
+                Err(e) => {
+                    return Err(Errors::Roca(format!(
+                        "can't read CA file {}: {}",
+                        path.display(),
+                        e
+                    )))
+                }
+            };
+            match Certificate::from_pem(&pem) {
+                Ok(c) => certificates.push(c),
+                Err(e) => {
+                    return Err(Errors::Roca(format!(
+                        "invalid CA file {}: {}",
+                        path.display(),
+                        e
+                    )))
+                }
+            }
+        }
+        Ok(certificates)
+    }
+
+    fn required_auth(&self) -> Result<Arc<dyn AuthProvider>, Errors> {
+        match &self.auth {
+            Some(auth) => Ok(auth.clone()),
+            None => Err(Errors::Roca(
+                "client builder: missing authentication".to_string(),
+            )),
+        }
+    }
+
+    /// Builds the blocking client
+    #[cfg(feature = "blocking")]
+    pub fn build(self) -> Result<ClientXMLRPC, Errors> {
+        let auth = self.required_auth()?;
+        let http = configure_http_builder!(self, reqwest::blocking::Client::builder());
+
+        Ok(ClientXMLRPC {
+            auth,
+            endpoint: self.endpoint,
+            transport: ReqwestTransport::from(http),
+        })
+    }
+
+    /// Builds a blocking client exchanging the password for a login token, the password isn't kept
+    #[cfg(feature = "blocking")]
+    pub fn build_session(
+        self,
+        options: SessionOptions,
+    ) -> Result<SessionClient<ReqwestTransport>, Errors> {
+        let auth = self.required_auth()?.session()?;
+        let http = configure_http_builder!(self, reqwest::blocking::Client::builder());
+
+        SessionClient::login(self.endpoint, ReqwestTransport::from(http), auth, options)
+    }
+
+    /// Builds the asynchronous client
+    #[cfg(feature = "async")]
+    pub fn build_async(self) -> Result<AsyncClientXMLRPC, Errors> {
+        let auth = self.required_auth()?;
+        let http = configure_http_builder!(self, reqwest::Client::builder());
+
+        Ok(AsyncClientXMLRPC {
+            auth,
+            endpoint: self.endpoint,
+            transport: AsyncReqwestTransport::from(http),
+        })
+    }
+
+    /// Builds an asynchronous client exchanging the password for a login token
+    #[cfg(feature = "async")]
+    pub async fn build_async_session(
+        self,
+        options: SessionOptions,
+    ) -> Result<AsyncSessionClient<AsyncReqwestTransport>, Errors> {
+        let auth = self.required_auth()?.session()?;
+        let http = configure_http_builder!(self, reqwest::Client::builder());
+
+        AsyncSessionClient::login(
+            self.endpoint,
+            AsyncReqwestTransport::from(http),
+            auth,
+            options,
+        )
+        .await
+    }
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, feature = "blocking"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builder_requires_auth() {
+        let client = ClientXMLRPC::builder()
+            .endpoint("http://192.168.33.10:2633/RPC2")
+            .build();
+
+        assert!(client.is_err());
+    }
+
+    #[test]
+    fn builder_invalid_header() {
+        let client = ClientXMLRPC::builder()
+            .auth("oneadmin:pDi4mFBHue")
+            .header("invalid header", "value")
+            .build();
+
+        assert!(client.is_err());
+    }
+
+    #[cfg(any(feature = "native-tls", feature = "rustls"))]
+    #[test]
+    fn builder_options() {
+        let client = ClientXMLRPC::builder()
+            .auth("oneadmin:pDi4mFBHue")
+            .endpoint("https://192.168.33.10:2633/RPC2")
+            .connect_timeout(Duration::from_secs(5))
+            .timeout(Duration::from_secs(30))
+            .danger_accept_invalid_certs(true)
+            .user_agent("roca-test")
+            .header("X-Request-Origin", "roca")
+            .build();
+
+        assert!(client.is_ok());
+    }
+}
@@ -0,0 +1,100 @@
+//! The credentials module resolves the session and the endpoint the same way the `one*` CLI tools do
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::common::{Errors, Secret};
+
+pub const DEFAULT_ENDPOINT: &str = "http://localhost:2633/RPC2";
+
+/// Environment variable pointing to the file containing the session string
+pub const ONE_AUTH: &str = "ONE_AUTH";
+/// Environment variable containing the XML-RPC endpoint
+pub const ONE_XMLRPC: &str = "ONE_XMLRPC";
+
+/// Reads the session string ("user:password") from the file pointed by ONE_AUTH,
+/// or from ~/.one/one_auth
+pub fn discover_auth() -> Result<Secret, Errors> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+
+    match auth_file_path(env::var_os(ONE_AUTH).map(PathBuf::from), home) {
+        Some(path) => read_auth_file(path),
+        None => Err(Errors::Roca(format!(
+            "no authentication file found: {} is not set and HOME is unknown",
+            ONE_AUTH
+        ))),
+    }
+}
+
+/// Returns the endpoint from ONE_XMLRPC, or the default one: http://localhost:2633/RPC2
+pub fn discover_endpoint() -> String {
+    match env::var(ONE_XMLRPC) {
+        Ok(endpoint) if !endpoint.trim().is_empty() => endpoint.trim().to_string(),
+        _ => DEFAULT_ENDPOINT.to_string(),
+    }
+}
+
+/// Reads the session string from the first non empty line of the file
+pub fn read_auth_file<P: AsRef<Path>>(path: P) -> Result<Secret, Errors> {
+    let path = path.as_ref();
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => {
+            return Err(Errors::Roca(format!(
+                "can't read authentication file {}: {}",
+                path.display(),
+                e
+            )))
+        }
+    };
+
+    match content.lines().map(str::trim).find(|l| !l.is_empty()) {
+        Some(line) if line.contains(':') => Ok(Secret::new(line)),
+        _ => Err(Errors::Roca(format!(
+            "authentication file {} should contain a line like user:password",
+            path.display()
+        ))),
+    }
+}
+
+fn auth_file_path(one_auth: Option<PathBuf>, home: Option<PathBuf>) -> Option<PathBuf> {
+    match one_auth {
+        Some(path) if !path.as_os_str().is_empty() => Some(path),
+        _ => home.map(|h| h.join(".one").join("one_auth")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn auth_path_resolution() {
+        assert_eq!(
+            auth_file_path(Some("/etc/one/auth".into()), Some("/home/user".into())),
+            Some(PathBuf::from("/etc/one/auth"))
+        );
+        assert_eq!(
+            auth_file_path(Some("".into()), Some("/home/user".into())),
+            Some(PathBuf::from("/home/user/.one/one_auth"))
+        );
+        assert_eq!(auth_file_path(None, None), None);
+    }
+
+    #[test]
+    fn auth_file_reading() {
+        let path = env::temp_dir().join(format!("roca_one_auth_{}", std::process::id()));
+
+        fs::write(&path, "\n  oneadmin:pDi4mFBHue  \nignored:line\n").unwrap();
+        let auth = read_auth_file(&path);
+        assert!(auth.is_ok());
+        assert_eq!(auth.unwrap().expose(), "oneadmin:pDi4mFBHue");
+
+        fs::write(&path, "no_separator").unwrap();
+        assert!(read_auth_file(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+        assert!(read_auth_file(&path).is_err());
+    }
+}
@@ -0,0 +1,588 @@
+//! The failover module provides clients for HA deployments, sending the calls to the raft leader
+//! among several frontends.
+//!
+//! The calls are sent to the last known leader. On a connection failure or a follower rejecting
+//! the call because it has no leader, the other frontends are asked their raft state with
+//! `one.zone.raftstatus` and the call is sent again to the leader, or to the next frontend if no
+//! leader is found.
+//!
+//! A timeout, a 5xx HTTP status or an unreadable response only triggers a failover for the read
+//! only calls: oned may have applied a mutating call before the failure, it's not sent twice.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+use serde_xmlrpc::Value;
+
+use crate::client::auth::AuthProvider;
+#[cfg(feature = "async")]
+use crate::client::transport::AsyncTransport;
+use crate::client::transport::Transport;
+use crate::client::{call_request, multicall_request};
+use crate::common::methods;
+use crate::common::response::Response;
+use crate::common::xml::resource::{Resource, XMLDocGetters};
+use crate::common::xml::shared_getters::BaseGetters;
+use crate::common::{ErrorCode, Errors, Secret};
+#[cfg(feature = "async")]
+use crate::controller::AsyncRPCCaller;
+use crate::controller::RPCCaller;
+use crate::middleware::retry::is_transient;
+
+const RAFT_STATUS_METHOD: &str = "one.zone.raftstatus";
+
+/// The raft state of a frontend, from the STATE element of `one.zone.raftstatus`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaftState {
+    /// Not part of a HA deployment, it accepts all the calls
+    Solo,
+    Candidate,
+    Follower,
+    Leader,
+    Unknown(i64),
+}
+
+impl RaftState {
+    /// Tells if the frontend accepts the write calls
+    pub fn is_leader(self) -> bool {
+        matches!(self, RaftState::Solo | RaftState::Leader)
+    }
+}
+
+impl From<i64> for RaftState {
+    fn from(state: i64) -> Self {
+        match state {
+            0 => RaftState::Solo,
+            1 => RaftState::Candidate,
+            2 => RaftState::Follower,
+            3 => RaftState::Leader,
+            state => RaftState::Unknown(state),
+        }
+    }
+}
+
+/// Parses the body of `one.zone.raftstatus`
+pub fn parse_raft_state(body: &str) -> Result<RaftState, Errors> {
+    struct RaftStatus(Resource);
+
+    impl XMLDocGetters for RaftStatus {
+        fn get_internal(&self) -> (&xml_doc::Document, &xml_doc::Element) {
+            (&self.0.document, &self.0.root)
+        }
+    }
+
+    let status = RaftStatus(Resource::try_from(body)?);
+    Ok(status.get_i64("STATE")?.into())
+}
+
+/// Tells if the call should be sent to another frontend: the frontend is unreachable,
+/// or it's a follower which can't forward the call to a leader.
+/// The other transient errors (timeout, 5xx status, unreadable response) only apply to the read
+/// only calls.
+pub fn should_failover(result: &Result<String, Errors>, read_only: bool) -> bool {
+    match result {
+        // the call didn't reach oned
+        Err(Errors::Connect(_)) => true,
+        Err(e) => read_only && is_transient(e),
+        // only the failed calls are decoded
+        Ok(raw) if raw.contains("<boolean>0</boolean>") => {
+            match Response::<Value>::decode(raw, "", "") {
+                Err(Errors::OpenNebula(e)) => {
+                    e.code == ErrorCode::Internal && e.message.to_lowercase().contains("leader")
+                }
+                _ => false,
+            }
+        }
+        Ok(_) => false,
+    }
+}
+
+// a batch is read only if all its calls are
+fn read_only(calls: &[(String, Vec<Value>)]) -> bool {
+    calls.iter().all(|(name, _)| methods::is_read_only(name))
+}
+
+// The frontends shared by the blocking and the asynchronous clients
+#[derive(Debug)]
+struct Frontends {
+    endpoints: Vec<String>,
+    current: AtomicUsize,
+}
+
+impl Frontends {
+    fn new(endpoints: Vec<String>) -> Result<Self, Errors> {
+        if endpoints.is_empty() {
+            return Err(Errors::Roca(
+                "failover client: at least one endpoint is required".to_string(),
+            ));
+        }
+
+        Ok(Frontends {
+            endpoints,
+            current: AtomicUsize::new(0),
+        })
+    }
+
+    fn current(&self) -> usize {
+        self.current.load(Ordering::Relaxed)
+    }
+
+    fn select(&self, index: usize) {
+        self.current.store(index, Ordering::Relaxed);
+    }
+
+    // The other frontends, starting with the next one
+    fn others(&self, failed: usize) -> Vec<usize> {
+        let count = self.endpoints.len();
+        (1..count).map(|i| (failed + i) % count).collect()
+    }
+}
+
+/// FailoverClient is a XML-RPC client sending the calls to the leader of several HA frontends
+pub struct FailoverClient<T: Transport> {
+    auth: Arc<dyn AuthProvider>,
+    frontends: Frontends,
+    transport: T,
+}
+
+impl<T: Transport> FailoverClient<T> {
+    /// The calls are sent to the first endpoint until it fails
+    pub fn new(auth: String, endpoints: Vec<String>, transport: T) -> Result<Self, Errors> {
+        Self::with_auth_provider(Secret::from(auth), endpoints, transport)
+    }
+
+    pub fn with_auth_provider<P: AuthProvider + 'static>(
+        auth: P,
+        endpoints: Vec<String>,
+        transport: T,
+    ) -> Result<Self, Errors> {
+        Ok(FailoverClient {
+            auth: Arc::new(auth),
+            frontends: Frontends::new(endpoints)?,
+            transport,
+        })
+    }
+
+    pub fn endpoints(&self) -> &[String] {
+        &self.frontends.endpoints
+    }
+
+    /// The endpoint receiving the calls
+    pub fn current_endpoint(&self) -> &str {
+        &self.frontends.endpoints[self.frontends.current()]
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Asks the raft state of the frontend
+    pub fn raft_state(&self, endpoint: &str) -> Result<RaftState, Errors> {
+        let body = call_request(self.auth.session()?.expose(), RAFT_STATUS_METHOD, vec![])?;
+        let raw = self.transport.send(endpoint, body)?;
+        let body = Response::<String>::decode(&raw, RAFT_STATUS_METHOD, "")?.payload;
+
+        parse_raft_state(&body)
+    }
+
+    /// Asks each frontend its raft state and selects the leader
+    pub fn find_leader(&self) -> Result<&str, Errors> {
+        let current = self.frontends.current();
+        let candidates = std::iter::once(current).chain(self.frontends.others(current));
+
+        for index in candidates {
+            if self.is_leader(index) {
+                self.frontends.select(index);
+                return Ok(&self.frontends.endpoints[index]);
+            }
+        }
+
+        Err(Errors::Roca("failover client: no leader found".to_string()))
+    }
+
+    fn is_leader(&self, index: usize) -> bool {
+        matches!(self.raft_state(&self.frontends.endpoints[index]), Ok(s) if s.is_leader())
+    }
+
+    // Selects the leader among the other frontends, or the next one if none is the leader
+    fn failover(&self, failed: usize) -> usize {
+        let others = self.frontends.others(failed);
+        let next = others
+            .iter()
+            .copied()
+            .find(|&index| self.is_leader(index))
+            .unwrap_or(others[0]);
+
+        self.frontends.select(next);
+        next
+    }
+
+    fn send(&self, body: String, read_only: bool) -> Result<String, Errors> {
+        let mut index = self.frontends.current();
+        let mut result = self
+            .transport
+            .send(&self.frontends.endpoints[index], body.clone());
+
+        for _ in 1..self.frontends.endpoints.len() {
+            if !should_failover(&result, read_only) {
+                break;
+            }
+            index = self.failover(index);
+            result = self
+                .transport
+                .send(&self.frontends.endpoints[index], body.clone());
+        }
+
+        result
+    }
+}
+
+impl<T: Transport> RPCCaller for FailoverClient<T> {
+    fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        let body = call_request(self.auth.session()?.expose(), name, args)?;
+        self.send(body, methods::is_read_only(name))
+    }
+
+    fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        Some(
+            self.auth
+                .session()
+                .and_then(|auth| multicall_request(auth.expose(), calls))
+                .and_then(|body| self.send(body, read_only(calls))),
+        )
+    }
+}
+
+impl<T: Transport> std::fmt::Debug for FailoverClient<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FailoverClient")
+            .field("auth", &self.auth)
+            .field("frontends", &self.frontends)
+            .finish()
+    }
+}
+
+/// AsyncFailoverClient is the asynchronous counterpart of FailoverClient
+#[cfg(feature = "async")]
+pub struct AsyncFailoverClient<T: AsyncTransport> {
+    auth: Arc<dyn AuthProvider>,
+    frontends: Frontends,
+    transport: T,
+}
+
+#[cfg(feature = "async")]
+impl<T: AsyncTransport> AsyncFailoverClient<T> {
+    /// The calls are sent to the first endpoint until it fails
+    pub fn new(auth: String, endpoints: Vec<String>, transport: T) -> Result<Self, Errors> {
+        Self::with_auth_provider(Secret::from(auth), endpoints, transport)
+    }
+
+    pub fn with_auth_provider<P: AuthProvider + 'static>(
+        auth: P,
+        endpoints: Vec<String>,
+        transport: T,
+    ) -> Result<Self, Errors> {
+        Ok(AsyncFailoverClient {
+            auth: Arc::new(auth),
+            frontends: Frontends::new(endpoints)?,
+            transport,
+        })
+    }
+
+    pub fn endpoints(&self) -> &[String] {
+        &self.frontends.endpoints
+    }
+
+    /// The endpoint receiving the calls
+    pub fn current_endpoint(&self) -> &str {
+        &self.frontends.endpoints[self.frontends.current()]
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Asks the raft state of the frontend
+    pub async fn raft_state(&self, endpoint: &str) -> Result<RaftState, Errors> {
+        let body = call_request(self.auth.session()?.expose(), RAFT_STATUS_METHOD, vec![])?;
+        let raw = self.transport.send(endpoint, body).await?;
+        let body = Response::<String>::decode(&raw, RAFT_STATUS_METHOD, "")?.payload;
+
+        parse_raft_state(&body)
+    }
+
+    /// Asks each frontend its raft state and selects the leader
+    pub async fn find_leader(&self) -> Result<&str, Errors> {
+        let current = self.frontends.current();
+        let candidates = std::iter::once(current).chain(self.frontends.others(current));
+
+        for index in candidates {
+            if self.is_leader(index).await {
+                self.frontends.select(index);
+                return Ok(&self.frontends.endpoints[index]);
+            }
+        }
+
+        Err(Errors::Roca("failover client: no leader found".to_string()))
+    }
+
+    async fn is_leader(&self, index: usize) -> bool {
+        matches!(self.raft_state(&self.frontends.endpoints[index]).await, Ok(s) if s.is_leader())
+    }
+
+    async fn failover(&self, failed: usize) -> usize {
+        let others = self.frontends.others(failed);
+
+        let mut next = others[0];
+        for &index in &others {
+            if self.is_leader(index).await {
+                next = index;
+                break;
+            }
+        }
+
+        self.frontends.select(next);
+        next
+    }
+
+    async fn send(&self, body: String, read_only: bool) -> Result<String, Errors> {
+        let mut index = self.frontends.current();
+        let mut result = self
+            .transport
+            .send(&self.frontends.endpoints[index], body.clone())
+            .await;
+
+        for _ in 1..self.frontends.endpoints.len() {
+            if !should_failover(&result, read_only) {
+                break;
+            }
+            index = self.failover(index).await;
+            result = self
+                .transport
+                .send(&self.frontends.endpoints[index], body.clone())
+                .await;
+        }
+
+        result
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl<T: AsyncTransport> AsyncRPCCaller for AsyncFailoverClient<T> {
+    async fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        let body = call_request(self.auth.session()?.expose(), name, args)?;
+        self.send(body, methods::is_read_only(name)).await
+    }
+
+    async fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        let body = self
+            .auth
+            .session()
+            .and_then(|auth| multicall_request(auth.expose(), calls));
+        match body {
+            Ok(body) => Some(self.send(body, read_only(calls)).await),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T: AsyncTransport> std::fmt::Debug for AsyncFailoverClient<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncFailoverClient")
+            .field("auth", &self.auth)
+            .field("frontends", &self.frontends)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::testing::xmlrpc;
+
+    // Three frontends, the leader is the only one accepting the calls
+    struct Cluster {
+        leader: Mutex<Option<&'static str>>,
+        down: Vec<&'static str>,
+        requests: Mutex<Vec<String>>,
+    }
+
+    impl Cluster {
+        fn new(leader: &'static str, down: Vec<&'static str>) -> Arc<Self> {
+            Arc::new(Cluster {
+                leader: Mutex::new(Some(leader)),
+                down,
+                requests: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn transport(self: &Arc<Self>) -> impl Fn(&str, String) -> Result<String, Errors> {
+            let cluster = self.clone();
+            move |endpoint: &str, body: String| cluster.answer(endpoint, &body)
+        }
+
+        fn answer(&self, endpoint: &str, body: &str) -> Result<String, Errors> {
+            self.requests.lock().unwrap().push(endpoint.to_string());
+            if self.down.contains(&endpoint) {
+                return Err(Errors::Connect("connection refused".to_string()));
+            }
+
+            let leader = *self.leader.lock().unwrap();
+            if body.contains(RAFT_STATUS_METHOD) {
+                let state = if leader == Some(endpoint) { 3 } else { 2 };
+                return Ok(xmlrpc::success_response(format!(
+                    "<RAFT><SERVER_ID>0</SERVER_ID><STATE>{}</STATE></RAFT>",
+                    state
+                )));
+            }
+
+            // the followers forward the calls to the leader
+            match leader {
+                Some(_) => Ok(xmlrpc::success_response("<USER><ID>0</ID></USER>")),
+                None => Ok(xmlrpc::failure_response(
+                    "Cannot process request, no leader found",
+                    ErrorCode::Internal.value(),
+                )),
+            }
+        }
+
+        fn requests(&self) -> Vec<String> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    fn endpoints() -> Vec<String> {
+        vec!["one1".to_string(), "one2".to_string(), "one3".to_string()]
+    }
+
+    #[test]
+    fn raft_states() {
+        assert!(RaftState::from(0).is_leader());
+        assert!(RaftState::from(3).is_leader());
+        assert!(!RaftState::from(2).is_leader());
+        assert_eq!(RaftState::from(7), RaftState::Unknown(7));
+
+        assert!(
+            FailoverClient::new(String::from("oneadmin:pDi4mFBHue"), vec![], |_: &str, _| {
+                Ok(String::new())
+            })
+            .is_err()
+        );
+        assert!(should_failover(
+            &Err(Errors::Connect("refused".to_string())),
+            false
+        ));
+        // the request may have been received
+        assert!(!should_failover(
+            &Err(Errors::HTTPReq("operation timed out".to_string())),
+            false
+        ));
+        assert!(!should_failover(
+            &Err(Errors::HTTPStatus(404, String::new())),
+            true
+        ));
+        assert!(should_failover(
+            &Err(Errors::HTTPStatus(502, String::new())),
+            true
+        ));
+        assert!(!should_failover(
+            &Err(Errors::HTTPRespHandling("truncated".to_string())),
+            false
+        ));
+    }
+
+    #[test]
+    fn failover_on_connection_failure() {
+        let cluster = Cluster::new("one3", vec!["one1"]);
+        let client = FailoverClient::new(
+            String::from("oneadmin:pDi4mFBHue"),
+            endpoints(),
+            cluster.transport(),
+        )
+        .unwrap();
+
+        client.call("one.user.info", vec![0.into()]).unwrap();
+        assert_eq!(client.current_endpoint(), "one3");
+
+        // the leader is now known
+        client.call("one.user.info", vec![0.into()]).unwrap();
+        assert_eq!(cluster.requests().last().unwrap(), "one3");
+    }
+
+    #[test]
+    fn failover_without_leader() {
+        let cluster = Cluster::new("one2", vec![]);
+        let client = FailoverClient::new(
+            String::from("oneadmin:pDi4mFBHue"),
+            endpoints(),
+            cluster.transport(),
+        )
+        .unwrap();
+
+        assert_eq!(client.find_leader().unwrap(), "one2");
+
+        *cluster.leader.lock().unwrap() = None;
+        let raw = client.call("one.user.info", vec![0.into()]).unwrap();
+        assert!(should_failover(&Ok(raw), false));
+        assert!(client.find_leader().is_err());
+    }
+
+    // sends a mutating call to frontends failing with the error, returns the requested frontends
+    fn send_mutating_call(error: fn() -> Errors) -> (Result<String, Errors>, Vec<String>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let sent = requests.clone();
+        let client = FailoverClient::new(
+            String::from("oneadmin:pDi4mFBHue"),
+            endpoints(),
+            move |endpoint: &str, _body: String| {
+                sent.lock().unwrap().push(endpoint.to_string());
+                Err(error())
+            },
+        )
+        .unwrap();
+
+        let result = client.call("one.vm.action", vec!["terminate".into(), 12.into()]);
+        assert_eq!(client.current_endpoint(), "one1");
+        let requests = requests.lock().unwrap().clone();
+        (result, requests)
+    }
+
+    #[test]
+    fn no_failover_of_mutating_calls_on_bad_gateway() {
+        // the proxy may have forwarded the call to oned, it's not sent again
+        let (result, requests) =
+            send_mutating_call(|| Errors::HTTPStatus(502, "Bad Gateway".to_string()));
+        assert!(matches!(result, Err(Errors::HTTPStatus(502, _))));
+        assert_eq!(requests, vec!["one1".to_string()]);
+    }
+
+    #[test]
+    fn no_failover_of_mutating_calls_on_timeout() {
+        // oned may still be running the call
+        let (result, requests) =
+            send_mutating_call(|| Errors::HTTPReq("operation timed out".to_string()));
+        assert!(matches!(result, Err(Errors::HTTPReq(_))));
+        assert_eq!(requests, vec!["one1".to_string()]);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_failover() {
+        let cluster = Cluster::new("one2", vec!["one1"]);
+        let client = AsyncFailoverClient::new(
+            String::from("oneadmin:pDi4mFBHue"),
+            endpoints(),
+            cluster.transport(),
+        )
+        .unwrap();
+
+        client.call("one.user.info", vec![0.into()]).await.unwrap();
+        assert_eq!(client.current_endpoint(), "one2");
+    }
+}
@@ -0,0 +1,325 @@
+//! The client module acts as a wrapper of XML-RPC client to add OpenNebula related helpers
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+use serde_xmlrpc::Value;
+
+use crate::common::{Errors, Secret};
+#[cfg(feature = "async")]
+use crate::controller::AsyncRPCCaller;
+use crate::controller::RPCCaller;
+
+#[cfg(feature = "client")]
+mod builder;
+#[cfg(feature = "client")]
+pub use builder::ClientBuilder;
+
+pub mod auth;
+pub mod credentials;
+pub mod failover;
+#[cfg(feature = "client")]
+pub mod reqwest_transport;
+pub mod session;
+pub mod transport;
+
+pub use auth::AuthProvider;
+#[cfg(feature = "async")]
+pub use failover::AsyncFailoverClient;
+pub use failover::FailoverClient;
+#[cfg(all(feature = "client", feature = "async"))]
+pub use reqwest_transport::AsyncReqwestTransport;
+#[cfg(all(feature = "client", feature = "blocking"))]
+pub use reqwest_transport::ReqwestTransport;
+#[cfg(feature = "async")]
+pub use session::AsyncSessionClient;
+pub use session::{SessionClient, SessionOptions};
+#[cfg(feature = "async")]
+pub use transport::AsyncTransport;
+pub use transport::Transport;
+
+/// The Roca XML-RPC basic client, sending the requests with the transport T
+#[cfg(all(feature = "client", feature = "blocking"))]
+#[derive(Debug)]
+pub struct ClientXMLRPC<T: Transport = ReqwestTransport> {
+    auth: Arc<dyn AuthProvider>,
+    endpoint: String,
+    transport: T,
+}
+
+/// The Roca XML-RPC basic client, sending the requests with the transport T
+#[cfg(not(all(feature = "client", feature = "blocking")))]
+#[derive(Debug)]
+pub struct ClientXMLRPC<T: Transport> {
+    auth: Arc<dyn AuthProvider>,
+    endpoint: String,
+    transport: T,
+}
+
+#[cfg(all(feature = "client", feature = "blocking"))]
+impl ClientXMLRPC {
+    pub fn new(auth: String, endpoint: String) -> ClientXMLRPC {
+        Self::with_transport(auth, endpoint, ReqwestTransport::new())
+    }
+
+    /// Resolves the credentials and the endpoint like the CLI tools:
+    /// ONE_AUTH or ~/.one/one_auth, then ONE_XMLRPC or the default endpoint
+    pub fn from_env() -> Result<ClientXMLRPC, Errors> {
+        ClientBuilder::from_env()?.build()
+    }
+
+    /// Returns a builder allowing to configure the underlying HTTP client:
+    /// timeouts, TLS, proxy, user agent...
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+}
+
+impl<T: Transport> ClientXMLRPC<T> {
+    /// Creates a client sending the requests with another HTTP library than reqwest
+    pub fn with_transport(auth: String, endpoint: String, transport: T) -> Self {
+        ClientXMLRPC {
+            auth: Arc::new(Secret::from(auth)),
+            endpoint,
+            transport,
+        }
+    }
+
+    /// Creates a client getting the session string from the provider,
+    /// i.e. to use the login tokens of the ssh or x509 drivers
+    pub fn with_auth_provider<P: AuthProvider + 'static>(
+        auth: P,
+        endpoint: String,
+        transport: T,
+    ) -> Self {
+        Self {
+            auth: Arc::new(auth),
+            endpoint,
+            transport,
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+}
+
+impl<T: Transport> RPCCaller for ClientXMLRPC<T> {
+    //Try to import https://doc.rust-lang.org/cargo/reference/specifying-dependencies.html
+    // if works open a PR
+
+    fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        let body = call_request(self.auth.session()?.expose(), name, args)?;
+        self.transport.send(&self.endpoint, body)
+    }
+
+    fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        Some(
+            self.auth
+                .session()
+                .and_then(|auth| multicall_request(auth.expose(), calls))
+                .and_then(|body| self.transport.send(&self.endpoint, body)),
+        )
+    }
+}
+
+/// The Roca XML-RPC asynchronous client, built on the non-blocking reqwest client by default
+#[cfg(all(feature = "client", feature = "async"))]
+#[derive(Debug)]
+pub struct AsyncClientXMLRPC<T: AsyncTransport = AsyncReqwestTransport> {
+    auth: Arc<dyn AuthProvider>,
+    endpoint: String,
+    transport: T,
+}
+
+/// The Roca XML-RPC asynchronous client
+#[cfg(all(not(feature = "client"), feature = "async"))]
+#[derive(Debug)]
+pub struct AsyncClientXMLRPC<T: AsyncTransport> {
+    auth: Arc<dyn AuthProvider>,
+    endpoint: String,
+    transport: T,
+}
+
+#[cfg(all(feature = "client", feature = "async"))]
+impl AsyncClientXMLRPC {
+    pub fn new(auth: String, endpoint: String) -> AsyncClientXMLRPC {
+        Self::with_transport(auth, endpoint, AsyncReqwestTransport::new())
+    }
+
+    /// Resolves the credentials and the endpoint like the CLI tools:
+    /// ONE_AUTH or ~/.one/one_auth, then ONE_XMLRPC or the default endpoint
+    pub fn from_env() -> Result<AsyncClientXMLRPC, Errors> {
+        ClientBuilder::from_env()?.build_async()
+    }
+
+    /// Returns a builder allowing to configure the underlying HTTP client,
+    /// call `ClientBuilder::build_async` to get the client.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T: AsyncTransport> AsyncClientXMLRPC<T> {
+    /// Creates a client sending the requests with another HTTP library than reqwest
+    pub fn with_transport(auth: String, endpoint: String, transport: T) -> Self {
+        AsyncClientXMLRPC {
+            auth: Arc::new(Secret::from(auth)),
+            endpoint,
+            transport,
+        }
+    }
+
+    /// Creates a client getting the session string from the provider,
+    /// i.e. to use the login tokens of the ssh or x509 drivers
+    pub fn with_auth_provider<P: AuthProvider + 'static>(
+        auth: P,
+        endpoint: String,
+        transport: T,
+    ) -> Self {
+        Self {
+            auth: Arc::new(auth),
+            endpoint,
+            transport,
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl<T: AsyncTransport> AsyncRPCCaller for AsyncClientXMLRPC<T> {
+    async fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        let body = call_request(self.auth.session()?.expose(), name, args)?;
+        self.transport.send(&self.endpoint, body).await
+    }
+
+    async fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        let body = match self
+            .auth
+            .session()
+            .and_then(|auth| multicall_request(auth.expose(), calls))
+        {
+            Ok(body) => body,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(self.transport.send(&self.endpoint, body).await)
+    }
+}
+
+// Builds the request of a call, the session string is the first parameter
+fn call_request(auth: &str, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+    let mut full_args = vec![Value::String(auth.to_string())];
+    full_args.extend(args);
+
+    Ok(serde_xmlrpc::request_to_string(name, full_args)?)
+}
+
+// Builds the system.multicall request, the session string is added to the parameters of each call
+fn multicall_request(auth: &str, calls: &[(String, Vec<Value>)]) -> Result<String, Errors> {
+    let calls = calls
+        .iter()
+        .map(|(name, args)| {
+            let mut params = vec![Value::String(auth.to_string())];
+            params.extend(args.iter().cloned());
+
+            let mut call = BTreeMap::new();
+            call.insert("methodName".to_string(), Value::String(name.clone()));
+            call.insert("params".to_string(), Value::Array(params));
+            Value::Struct(call)
+        })
+        .collect();
+
+    Ok(serde_xmlrpc::request_to_string(
+        "system.multicall",
+        vec![Value::Array(calls)],
+    )?)
+}
+
+#[cfg(all(test, feature = "client", feature = "blocking"))]
+mod test {
+
+    use super::*;
+    use crate::testing::server::{FakeOned, Reply};
+
+    #[test]
+    fn one_client() {
+        let server = FakeOned::start().unwrap();
+        server.reply("one.vn.info", Reply::body("<VNET><ID>0</ID></VNET>"));
+
+        let client = ClientXMLRPC::new(String::from("oneadmin:pDi4mFBHue"), server.endpoint());
+
+        let resp_txt = client.call("one.vn.info", vec![0.into()]).unwrap();
+        let result = serde_xmlrpc::response_from_str::<(bool, String)>(&resp_txt);
+
+        assert_eq!(result.unwrap().0, true);
+    }
+
+    #[test]
+    fn one_rc() {
+        let server = FakeOned::start().unwrap();
+        server.reply("one.user.info", Reply::body("<USER><ID>0</ID></USER>"));
+
+        let client = ClientXMLRPC::new(String::from("oneadmin:pDi4mFBHue"), server.endpoint());
+
+        let resp_txt = client.call("one.user.info", vec![0.into()]).unwrap();
+        let result = serde_xmlrpc::response_from_str::<(bool, String)>(&resp_txt);
+
+        assert_eq!(result.unwrap().0, true);
+    }
+
+    #[test]
+    fn client_debug_redacts_auth() {
+        let client = ClientXMLRPC::new(
+            String::from("oneadmin:pDi4mFBHue"),
+            String::from("http://192.168.33.10:2633/RPC2"),
+        );
+
+        let debug = format!("{:?}", client);
+        assert!(!debug.contains("pDi4mFBHue"));
+        assert!(debug.contains("REDACTED"));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn one_async_client() {
+        let server = FakeOned::start().unwrap();
+        server.reply("one.user.info", Reply::body("<USER><ID>0</ID></USER>"));
+
+        let client = AsyncClientXMLRPC::new(String::from("oneadmin:pDi4mFBHue"), server.endpoint());
+
+        let resp_txt = client.call("one.user.info", vec![0.into()]).await.unwrap();
+        let result = serde_xmlrpc::response_from_str::<(bool, String)>(&resp_txt);
+
+        assert!(result.unwrap().0);
+        assert_eq!(server.requests()[0].method, "one.user.info");
+    }
+
+    #[test]
+    fn client_failure_tuple() {
+        let server = FakeOned::start().unwrap();
+        server.reply(
+            "one.user.info",
+            Reply::failure("[one.user.info] User couldn't be authenticated", 0x100),
+        );
+
+        let client = ClientXMLRPC::new(String::from("oneadmin:wrong"), server.endpoint());
+
+        let resp_txt = client.call("one.user.info", vec![0.into()]).unwrap();
+        let result = serde_xmlrpc::response_from_str::<(bool, String, i32)>(&resp_txt);
+
+        let (success, message, code) = result.unwrap();
+        assert!(!success);
+        assert!(message.contains("authenticated"));
+        assert_eq!(code, 0x100);
+
+        // the session string is sent as first parameter
+        assert!(server.requests()[0].body.contains("oneadmin:wrong"));
+    }
+}
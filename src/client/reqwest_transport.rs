@@ -0,0 +1,107 @@
+//! The reqwest_transport module provides the default transports, built on reqwest
+
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+
+#[cfg(feature = "async")]
+use crate::client::transport::AsyncTransport;
+#[cfg(feature = "blocking")]
+use crate::client::transport::Transport;
+use crate::common::Errors;
+
+/// The default blocking transport, configured with `ClientBuilder`
+#[cfg(feature = "blocking")]
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestTransport {
+    http: reqwest::blocking::Client,
+}
+
+#[cfg(feature = "blocking")]
+impl ReqwestTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl From<reqwest::blocking::Client> for ReqwestTransport {
+    fn from(http: reqwest::blocking::Client) -> Self {
+        ReqwestTransport { http }
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl Transport for ReqwestTransport {
+    fn send(&self, endpoint: &str, body: String) -> Result<String, Errors> {
+        let resp = match self.http.post(endpoint).body(body).send() {
+            Ok(r) => r,
+            Err(e) => return Err(send_error(e)),
+        };
+
+        let status = resp.status();
+        let text = match resp.text() {
+            Ok(t) => t,
+            Err(e) => return Err(Errors::HTTPRespHandling(e.to_string())),
+        };
+
+        if !status.is_success() {
+            return Err(Errors::HTTPStatus(status.as_u16(), text));
+        }
+
+        Ok(text)
+    }
+}
+
+// only the connection failures are sure not to have reached oned, a timeout may happen while
+// oned is still processing the request
+fn send_error(e: reqwest::Error) -> Errors {
+    if e.is_connect() {
+        Errors::Connect(e.to_string())
+    } else {
+        Errors::HTTPReq(e.to_string())
+    }
+}
+
+/// The default asynchronous transport, configured with `ClientBuilder`
+#[cfg(feature = "async")]
+#[derive(Debug, Clone, Default)]
+pub struct AsyncReqwestTransport {
+    http: reqwest::Client,
+}
+
+#[cfg(feature = "async")]
+impl AsyncReqwestTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "async")]
+impl From<reqwest::Client> for AsyncReqwestTransport {
+    fn from(http: reqwest::Client) -> Self {
+        AsyncReqwestTransport { http }
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl AsyncTransport for AsyncReqwestTransport {
+    async fn send(&self, endpoint: &str, body: String) -> Result<String, Errors> {
+        let resp = match self.http.post(endpoint).body(body).send().await {
+            Ok(r) => r,
+            Err(e) => return Err(send_error(e)),
+        };
+
+        let status = resp.status();
+        let text = match resp.text().await {
+            Ok(t) => t,
+            Err(e) => return Err(Errors::HTTPRespHandling(e.to_string())),
+        };
+
+        if !status.is_success() {
+            return Err(Errors::HTTPStatus(status.as_u16(), text));
+        }
+
+        Ok(text)
+    }
+}
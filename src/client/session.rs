@@ -0,0 +1,670 @@
+//! The session module provides clients authenticating with a login token instead of the password.
+//!
+//! The password is exchanged for a token with `one.user.login` then dropped, the token is used as
+//! the session secret and renewed before its expiry. When the token can't be renewed, i.e. it was
+//! revoked, the client logs in again with the optional credentials source.
+//!
+//! ```ignore
+//! let options = SessionOptions::default();
+//! let client = SessionClient::with_credentials(endpoint, ReqwestTransport::new(), options, || {
+//!     credentials::read_auth_file("/var/lib/service/one_auth")
+//! })?;
+//! let controller = Controller::new(client);
+//! ```
+
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+use serde_xmlrpc::Value;
+
+#[cfg(feature = "async")]
+use crate::client::transport::AsyncTransport;
+use crate::client::transport::Transport;
+use crate::client::{call_request, multicall_request};
+use crate::common::ids::{GroupId, Select};
+use crate::common::response::Response;
+use crate::common::{args_summary, ErrorCode, Errors, Secret};
+#[cfg(feature = "async")]
+use crate::controller::AsyncRPCCaller;
+use crate::controller::RPCCaller;
+
+const LOGIN_METHOD: &str = "one.user.login";
+
+/// Configures the tokens requested by the session clients
+#[derive(Debug, Clone)]
+pub struct SessionOptions {
+    /// Validity period of each token
+    pub period: Duration,
+    /// The token is renewed when it expires in less than this delay
+    pub renew_before: Duration,
+    /// Effective group of the session, Select::Any keeps all the groups of the user
+    pub gid: Select<GroupId>,
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        SessionOptions {
+            period: Duration::from_secs(3600),
+            renew_before: Duration::from_secs(300),
+            gid: Select::Any,
+        }
+    }
+}
+
+/// Source of the "user:password" session string, called to log in again
+/// when the token can't be renewed
+pub type Credentials = Box<dyn Fn() -> Result<Secret, Errors> + Send + Sync>;
+
+#[derive(Clone)]
+struct Token {
+    secret: Secret,
+    expires_at: Instant,
+}
+
+impl Token {
+    fn expires_in(&self) -> Duration {
+        self.expires_at.saturating_duration_since(Instant::now())
+    }
+}
+
+// The state shared by the blocking and the asynchronous clients
+struct Session {
+    username: String,
+    options: SessionOptions,
+    credentials: Option<Credentials>,
+    token: Mutex<Token>,
+}
+
+impl Session {
+    fn new(
+        username: String,
+        options: SessionOptions,
+        credentials: Option<Credentials>,
+        token: Token,
+    ) -> Self {
+        Session {
+            username,
+            options,
+            credentials,
+            token: Mutex::new(token),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Token> {
+        self.token.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn current(&self) -> Token {
+        self.lock().clone()
+    }
+
+    #[cfg(feature = "async")]
+    fn store(&self, token: Token) {
+        *self.lock() = token;
+    }
+
+    fn needs_renewal(&self, token: &Token) -> bool {
+        token.expires_in() <= self.options.renew_before
+    }
+
+    // The session string of the calls
+    fn auth(&self, token: &Token) -> String {
+        format!("{}:{}", self.username, token.secret.expose())
+    }
+
+    // Asks the credentials source for a new "user:password" session string
+    fn credentials(&self) -> Option<Result<Secret, Errors>> {
+        self.credentials.as_ref().map(|credentials| credentials())
+    }
+
+    fn login_request(&self, auth: &str) -> Result<String, Errors> {
+        login_request(auth, &self.username, &self.options)
+    }
+
+    fn token(&self, raw: &str, requested_at: Instant) -> Result<Token, Errors> {
+        decode_token(raw, &self.username, &self.options, requested_at)
+    }
+}
+
+// Returns the user of the "user:password" session string
+fn username(auth: &Secret) -> Result<String, Errors> {
+    match auth.expose().split_once(':') {
+        Some((username, _)) if !username.is_empty() => Ok(username.to_string()),
+        _ => Err(Errors::Roca(
+            "the session string should look like user:password".to_string(),
+        )),
+    }
+}
+
+// An empty token asks OpenNebula to generate a new one
+fn login_request(auth: &str, username: &str, options: &SessionOptions) -> Result<String, Errors> {
+    let period = i32::try_from(options.period.as_secs()).unwrap_or(i32::MAX);
+    if period <= 0 {
+        return Err(Errors::Roca(
+            "the validity period of the session tokens should be at least one second".to_string(),
+        ));
+    }
+
+    call_request(
+        auth,
+        LOGIN_METHOD,
+        vec![
+            username.into(),
+            "".into(),
+            period.into(),
+            options.gid.into(),
+        ],
+    )
+}
+
+// The expiry is computed from the time the request was sent, to stay on the safe side
+fn decode_token(
+    raw: &str,
+    username: &str,
+    options: &SessionOptions,
+    requested_at: Instant,
+) -> Result<Token, Errors> {
+    let args = args_summary(&[username.into()]);
+    let token = Response::<String>::decode(raw, LOGIN_METHOD, &args)?.payload;
+
+    Ok(Token {
+        secret: Secret::new(token),
+        expires_at: requested_at + options.period,
+    })
+}
+
+fn is_authentication_error(err: &Errors) -> bool {
+    err.code() == Some(ErrorCode::Authentication)
+}
+
+/// Tells if the call was rejected because the session isn't valid anymore
+fn is_authentication_failure(raw: &str) -> bool {
+    // only failed calls are decoded twice
+    if !raw.contains("<boolean>0</boolean>") {
+        return false;
+    }
+
+    match Response::<Value>::decode(raw, "", "") {
+        Err(e) => is_authentication_error(&e),
+        Ok(_) => false,
+    }
+}
+
+/// SessionClient is a XML-RPC client using a login token as session secret.
+/// The token is renewed before its expiry, and after an AUTHENTICATION error
+/// if a credentials source is set.
+pub struct SessionClient<T: Transport> {
+    endpoint: String,
+    transport: T,
+    session: Session,
+}
+
+impl<T: Transport> SessionClient<T> {
+    /// Exchanges the "user:password" session string for a token, the password isn't kept
+    pub fn login(
+        endpoint: String,
+        transport: T,
+        auth: Secret,
+        options: SessionOptions,
+    ) -> Result<Self, Errors> {
+        Self::start(endpoint, transport, options, auth, None)
+    }
+
+    /// Logs in with the session string returned by the credentials source,
+    /// which is called again each time the token can't be renewed.
+    /// The source may read a file, i.e. `credentials::read_auth_file`, or query a secret store.
+    pub fn with_credentials<F>(
+        endpoint: String,
+        transport: T,
+        options: SessionOptions,
+        credentials: F,
+    ) -> Result<Self, Errors>
+    where
+        F: Fn() -> Result<Secret, Errors> + Send + Sync + 'static,
+    {
+        let auth = credentials()?;
+        Self::start(
+            endpoint,
+            transport,
+            options,
+            auth,
+            Some(Box::new(credentials)),
+        )
+    }
+
+    fn start(
+        endpoint: String,
+        transport: T,
+        options: SessionOptions,
+        auth: Secret,
+        credentials: Option<Credentials>,
+    ) -> Result<Self, Errors> {
+        let username = username(&auth)?;
+
+        let requested_at = Instant::now();
+        let body = login_request(auth.expose(), &username, &options)?;
+        let raw = transport.send(&endpoint, body)?;
+        let token = decode_token(&raw, &username, &options, requested_at)?;
+
+        Ok(SessionClient {
+            endpoint,
+            transport,
+            session: Session::new(username, options, credentials, token),
+        })
+    }
+
+    pub fn username(&self) -> &str {
+        &self.session.username
+    }
+
+    /// Returns the remaining validity of the current token
+    pub fn expires_in(&self) -> Duration {
+        self.session.current().expires_in()
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Replaces the current token by a new one
+    pub fn renew(&self) -> Result<(), Errors> {
+        let mut token = self.session.lock();
+        *token = self.renewed(&token)?;
+        Ok(())
+    }
+
+    fn send_login(&self, auth: &str) -> Result<Token, Errors> {
+        let requested_at = Instant::now();
+        let raw = self
+            .transport
+            .send(&self.endpoint, self.session.login_request(auth)?)?;
+        self.session.token(&raw, requested_at)
+    }
+
+    // The current token is used to get the next one, the credentials are only asked
+    // if it's not valid anymore
+    fn renewed(&self, token: &Token) -> Result<Token, Errors> {
+        match self.send_login(&self.session.auth(token)) {
+            Err(e) if is_authentication_error(&e) => match self.session.credentials() {
+                Some(auth) => self.send_login(auth?.expose()),
+                None => Err(e),
+            },
+            result => result,
+        }
+    }
+
+    // Returns the session string, the lock is held during the renewal so it happens only once
+    fn auth(&self) -> Result<String, Errors> {
+        let mut token = self.session.lock();
+        if self.session.needs_renewal(&token) {
+            *token = self.renewed(&token)?;
+        }
+        Ok(self.session.auth(&token))
+    }
+
+    // Logs in again with the credentials, unless another call already replaced the rejected token
+    fn relogin(&self, rejected: &str) -> Result<String, Errors> {
+        let mut token = self.session.lock();
+        if self.session.auth(&token) == rejected {
+            match self.session.credentials() {
+                Some(auth) => *token = self.send_login(auth?.expose())?,
+                None => return Ok(rejected.to_string()),
+            }
+        }
+        Ok(self.session.auth(&token))
+    }
+}
+
+impl<T: Transport> RPCCaller for SessionClient<T> {
+    fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        let auth = self.auth()?;
+        let raw = self
+            .transport
+            .send(&self.endpoint, call_request(&auth, name, args.clone())?)?;
+
+        if self.session.credentials.is_none() || !is_authentication_failure(&raw) {
+            return Ok(raw);
+        }
+
+        // the token was revoked, the call is sent again once with a new token
+        let auth = self.relogin(&auth)?;
+        self.transport
+            .send(&self.endpoint, call_request(&auth, name, args)?)
+    }
+
+    fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        Some(
+            self.auth()
+                .and_then(|auth| multicall_request(&auth, calls))
+                .and_then(|body| self.transport.send(&self.endpoint, body)),
+        )
+    }
+}
+
+impl<T: Transport> std::fmt::Debug for SessionClient<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionClient")
+            .field("endpoint", &self.endpoint)
+            .field("username", &self.session.username)
+            .field("token", &self.session.current().secret)
+            .finish()
+    }
+}
+
+/// AsyncSessionClient is the asynchronous counterpart of SessionClient.
+/// Concurrent calls may renew the token several times, the last token is kept.
+#[cfg(feature = "async")]
+pub struct AsyncSessionClient<T: AsyncTransport> {
+    endpoint: String,
+    transport: T,
+    session: Session,
+}
+
+#[cfg(feature = "async")]
+impl<T: AsyncTransport> AsyncSessionClient<T> {
+    /// Exchanges the "user:password" session string for a token, the password isn't kept
+    pub async fn login(
+        endpoint: String,
+        transport: T,
+        auth: Secret,
+        options: SessionOptions,
+    ) -> Result<Self, Errors> {
+        Self::start(endpoint, transport, options, auth, None).await
+    }
+
+    /// Logs in with the session string returned by the credentials source,
+    /// which is called again each time the token can't be renewed
+    pub async fn with_credentials<F>(
+        endpoint: String,
+        transport: T,
+        options: SessionOptions,
+        credentials: F,
+    ) -> Result<Self, Errors>
+    where
+        F: Fn() -> Result<Secret, Errors> + Send + Sync + 'static,
+    {
+        let auth = credentials()?;
+        Self::start(
+            endpoint,
+            transport,
+            options,
+            auth,
+            Some(Box::new(credentials)),
+        )
+        .await
+    }
+
+    async fn start(
+        endpoint: String,
+        transport: T,
+        options: SessionOptions,
+        auth: Secret,
+        credentials: Option<Credentials>,
+    ) -> Result<Self, Errors> {
+        let username = username(&auth)?;
+
+        let requested_at = Instant::now();
+        let body = login_request(auth.expose(), &username, &options)?;
+        let raw = transport.send(&endpoint, body).await?;
+        let token = decode_token(&raw, &username, &options, requested_at)?;
+
+        Ok(AsyncSessionClient {
+            endpoint,
+            transport,
+            session: Session::new(username, options, credentials, token),
+        })
+    }
+
+    pub fn username(&self) -> &str {
+        &self.session.username
+    }
+
+    /// Returns the remaining validity of the current token
+    pub fn expires_in(&self) -> Duration {
+        self.session.current().expires_in()
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Replaces the current token by a new one
+    pub async fn renew(&self) -> Result<(), Errors> {
+        let token = self.renewed(&self.session.current()).await?;
+        self.session.store(token);
+        Ok(())
+    }
+
+    async fn send_login(&self, auth: &str) -> Result<Token, Errors> {
+        let requested_at = Instant::now();
+        let body = self.session.login_request(auth)?;
+        let raw = self.transport.send(&self.endpoint, body).await?;
+        self.session.token(&raw, requested_at)
+    }
+
+    async fn renewed(&self, token: &Token) -> Result<Token, Errors> {
+        match self.send_login(&self.session.auth(token)).await {
+            Err(e) if is_authentication_error(&e) => match self.session.credentials() {
+                Some(auth) => self.send_login(auth?.expose()).await,
+                None => Err(e),
+            },
+            result => result,
+        }
+    }
+
+    // the lock can't be held across an await, the token is renewed outside of it
+    async fn auth(&self) -> Result<String, Errors> {
+        let mut token = self.session.current();
+        if self.session.needs_renewal(&token) {
+            token = self.renewed(&token).await?;
+            self.session.store(token.clone());
+        }
+        Ok(self.session.auth(&token))
+    }
+
+    async fn relogin(&self) -> Result<String, Errors> {
+        let auth = match self.session.credentials() {
+            Some(auth) => auth?,
+            None => return Ok(self.session.auth(&self.session.current())),
+        };
+        let token = self.send_login(auth.expose()).await?;
+        self.session.store(token.clone());
+        Ok(self.session.auth(&token))
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl<T: AsyncTransport> AsyncRPCCaller for AsyncSessionClient<T> {
+    async fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        let auth = self.auth().await?;
+        let body = call_request(&auth, name, args.clone())?;
+        let raw = self.transport.send(&self.endpoint, body).await?;
+
+        if self.session.credentials.is_none() || !is_authentication_failure(&raw) {
+            return Ok(raw);
+        }
+
+        // the token was revoked, the call is sent again once with a new token
+        let auth = self.relogin().await?;
+        let body = call_request(&auth, name, args)?;
+        self.transport.send(&self.endpoint, body).await
+    }
+
+    async fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        let body = match self.auth().await {
+            Ok(auth) => multicall_request(&auth, calls),
+            Err(e) => Err(e),
+        };
+        match body {
+            Ok(body) => Some(self.transport.send(&self.endpoint, body).await),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T: AsyncTransport> std::fmt::Debug for AsyncSessionClient<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncSessionClient")
+            .field("endpoint", &self.endpoint)
+            .field("username", &self.session.username)
+            .field("token", &self.session.current().secret)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::testing::xmlrpc;
+
+    // A oned answering one.user.login with numbered tokens, revoked tokens are rejected
+    #[derive(Clone, Default)]
+    struct Oned {
+        logins: Arc<AtomicU32>,
+        revoked: Arc<AtomicU32>,
+    }
+
+    impl Oned {
+        fn transport(&self) -> impl Fn(&str, String) -> Result<String, Errors> {
+            let oned = self.clone();
+            move |_endpoint: &str, body: String| Ok(oned.answer(&body))
+        }
+
+        fn answer(&self, body: &str) -> String {
+            let revoked = self.revoked.load(Ordering::SeqCst);
+            let accepted = body.contains("oneadmin:pDi4mFBHue")
+                || (body.contains("oneadmin:token")
+                    && !(1..=revoked).any(|n| body.contains(&format!("oneadmin:token{}<", n))));
+            if !accepted {
+                return xmlrpc::failure_response(
+                    "[one.user.info] User couldn't be authenticated",
+                    0x0100,
+                );
+            }
+
+            if body.contains("<methodName>one.user.login</methodName>") {
+                let n = self.logins.fetch_add(1, Ordering::SeqCst) + 1;
+                return xmlrpc::success_response(format!("token{}", n));
+            }
+            xmlrpc::success_response("<USER><ID>0</ID></USER>")
+        }
+
+        fn logins(&self) -> u32 {
+            self.logins.load(Ordering::SeqCst)
+        }
+    }
+
+    fn options(period: u64, renew_before: u64) -> SessionOptions {
+        SessionOptions {
+            period: Duration::from_secs(period),
+            renew_before: Duration::from_secs(renew_before),
+            gid: Select::Any,
+        }
+    }
+
+    #[test]
+    fn session_string_parsing() {
+        assert_eq!(
+            username(&Secret::new("oneadmin:pass:word")).unwrap(),
+            "oneadmin"
+        );
+        assert!(username(&Secret::new("oneadmin")).is_err());
+        assert!(username(&Secret::new(":password")).is_err());
+        assert!(login_request("oneadmin:password", "oneadmin", &options(0, 0)).is_err());
+    }
+
+    #[test]
+    fn session_token() {
+        let oned = Oned::default();
+        let client = SessionClient::login(
+            String::from("mem://oned"),
+            oned.transport(),
+            Secret::new("oneadmin:pDi4mFBHue"),
+            options(3600, 300),
+        )
+        .unwrap();
+        assert_eq!(oned.logins(), 1);
+        assert!(client.expires_in() > Duration::from_secs(3500));
+        assert!(!format!("{:?}", client).contains("token1"));
+
+        client.call("one.user.info", vec![0.into()]).unwrap();
+        assert_eq!(oned.logins(), 1);
+
+        client.renew().unwrap();
+        assert_eq!(oned.logins(), 2);
+    }
+
+    #[test]
+    fn session_renewal() {
+        let oned = Oned::default();
+        // the token always expires soon
+        let client = SessionClient::login(
+            String::from("mem://oned"),
+            oned.transport(),
+            Secret::new("oneadmin:pDi4mFBHue"),
+            options(60, 300),
+        )
+        .unwrap();
+
+        client.call("one.user.info", vec![0.into()]).unwrap();
+        client.call("one.user.info", vec![0.into()]).unwrap();
+        assert_eq!(oned.logins(), 3);
+    }
+
+    #[test]
+    fn session_relogin() {
+        let oned = Oned::default();
+        let client = SessionClient::with_credentials(
+            String::from("mem://oned"),
+            oned.transport(),
+            options(3600, 300),
+            || Ok(Secret::new("oneadmin:pDi4mFBHue")),
+        )
+        .unwrap();
+
+        // the token is revoked, the call is sent again with a new one
+        oned.revoked.store(1, Ordering::SeqCst);
+        let raw = client.call("one.user.info", vec![0.into()]).unwrap();
+        assert!(!is_authentication_failure(&raw));
+        assert_eq!(oned.logins(), 2);
+
+        // without credentials, the failure is returned
+        let client = SessionClient::login(
+            String::from("mem://oned"),
+            oned.transport(),
+            Secret::new("oneadmin:pDi4mFBHue"),
+            options(3600, 300),
+        )
+        .unwrap();
+        oned.revoked.store(3, Ordering::SeqCst);
+        let raw = client.call("one.user.info", vec![0.into()]).unwrap();
+        assert!(is_authentication_failure(&raw));
+        assert!(matches!(client.renew(), Err(e) if e.code() == Some(ErrorCode::Authentication)));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_session_relogin() {
+        let oned = Oned::default();
+        let client = AsyncSessionClient::with_credentials(
+            String::from("mem://oned"),
+            oned.transport(),
+            options(3600, 300),
+            || Ok(Secret::new("oneadmin:pDi4mFBHue")),
+        )
+        .await
+        .unwrap();
+
+        oned.revoked.store(1, Ordering::SeqCst);
+        let raw = client.call("one.user.info", vec![0.into()]).await.unwrap();
+        assert!(!is_authentication_failure(&raw));
+        assert_eq!(oned.logins(), 2);
+    }
+}
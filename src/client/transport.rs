@@ -0,0 +1,91 @@
+//! The transport module abstracts the HTTP client used by the XML-RPC clients,
+//! allowing to replace reqwest by any other HTTP library or by an in-memory transport
+
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+
+use crate::common::Errors;
+
+/// Transport sends a XML-RPC request body to the endpoint and returns the response body.
+///
+/// A non successful HTTP status should be returned as `Errors::HTTPStatus`,
+/// a failure to connect to the endpoint as `Errors::Connect` and the other failures
+/// (i.e. a timeout, the request may have been received) as `Errors::HTTPReq`.
+pub trait Transport: Send + Sync {
+    fn send(&self, endpoint: &str, body: String) -> Result<String, Errors>;
+}
+
+/// AsyncTransport is the asynchronous counterpart of Transport
+#[cfg(feature = "async")]
+#[async_trait]
+pub trait AsyncTransport: Send + Sync {
+    async fn send(&self, endpoint: &str, body: String) -> Result<String, Errors>;
+}
+
+/// Any closure is a transport, i.e. to answer the requests in memory
+impl<F> Transport for F
+where
+    F: Fn(&str, String) -> Result<String, Errors> + Send + Sync,
+{
+    fn send(&self, endpoint: &str, body: String) -> Result<String, Errors> {
+        self(endpoint, body)
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl<F> AsyncTransport for F
+where
+    F: Fn(&str, String) -> Result<String, Errors> + Send + Sync,
+{
+    async fn send(&self, endpoint: &str, body: String) -> Result<String, Errors> {
+        self(endpoint, body)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::ClientXMLRPC;
+    use crate::controller::RPCCaller;
+    use crate::testing::xmlrpc;
+    #[cfg(feature = "async")]
+    use crate::{client::AsyncClientXMLRPC, controller::AsyncRPCCaller};
+
+    fn in_memory(endpoint: &str, body: String) -> Result<String, Errors> {
+        assert_eq!(endpoint, "mem://oned");
+        if !body.contains("<methodName>one.user.info</methodName>") {
+            return Err(Errors::HTTPStatus(404, "unknown method".to_string()));
+        }
+        Ok(xmlrpc::success_response("<USER><ID>0</ID></USER>"))
+    }
+
+    #[test]
+    fn in_memory_transport() {
+        let client = ClientXMLRPC::with_transport(
+            String::from("oneadmin:pDi4mFBHue"),
+            String::from("mem://oned"),
+            in_memory,
+        );
+
+        let resp = client.call("one.user.info", vec![0.into()]).unwrap();
+        assert!(resp.contains("&lt;USER&gt;"));
+        assert!(matches!(
+            client.call("one.vm.info", vec![0.into()]),
+            Err(Errors::HTTPStatus(404, _))
+        ));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn in_memory_async_transport() {
+        let client = AsyncClientXMLRPC::with_transport(
+            String::from("oneadmin:pDi4mFBHue"),
+            String::from("mem://oned"),
+            in_memory,
+        );
+
+        let resp = client.call("one.user.info", vec![0.into()]).await.unwrap();
+        assert!(resp.contains("&lt;USER&gt;"));
+    }
+}
@@ -1,5 +1,7 @@
 use std::{fmt::Display, num::ParseIntError};
 
+use serde_xmlrpc::Value;
+
 #[derive(Debug)]
 pub enum Errors {
     XMLDoc(xml_doc::Error),
@@ -8,11 +10,141 @@ pub enum Errors {
     //SeveralNodes(String),  find method returns only the first, should use find_all ?
     NotFound(String),
     XMLRPC(serde_xmlrpc::Error),
-    OpenNebula(String),
+    OpenNebula(OpenNebulaError),
     HTTPReq(String),
+    /// The connection to the endpoint failed, the request wasn't sent
+    Connect(String),
+    HTTPStatus(u16, String),
     HTTPRespHandling(String),
     Roca(String),
     Template(String),
+    TemplateLexer(String),
+    TemplateParser(String),
+    /// The call was rejected by a policy rule before reaching OpenNebula, see `middleware::policy`
+    PolicyDenied(String),
+}
+
+impl Errors {
+    /// Returns the OpenNebula error code if the call failed on OpenNebula side,
+    /// allowing to branch on "not found", "locked", "denied"...
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Self::OpenNebula(e) => Some(e.code),
+            _ => None,
+        }
+    }
+}
+
+/// OpenNebula error codes, the third value of the response of a failed call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Authentication,
+    Authorization,
+    NoExists,
+    Action,
+    XMLRPCAPI,
+    Internal,
+    Allocate,
+    Locked,
+    Unknown(i32),
+}
+
+impl ErrorCode {
+    pub fn value(self) -> i32 {
+        match self {
+            ErrorCode::Authentication => 0x0100,
+            ErrorCode::Authorization => 0x0200,
+            ErrorCode::NoExists => 0x0400,
+            ErrorCode::Action => 0x0800,
+            ErrorCode::XMLRPCAPI => 0x1000,
+            ErrorCode::Internal => 0x2000,
+            ErrorCode::Allocate => 0x4000,
+            ErrorCode::Locked => 0x8000,
+            ErrorCode::Unknown(code) => code,
+        }
+    }
+
+    /// Authentication or authorization failure
+    pub fn is_denied(self) -> bool {
+        matches!(self, ErrorCode::Authentication | ErrorCode::Authorization)
+    }
+}
+
+impl From<i32> for ErrorCode {
+    fn from(code: i32) -> Self {
+        match code {
+            0x0100 => ErrorCode::Authentication,
+            0x0200 => ErrorCode::Authorization,
+            0x0400 => ErrorCode::NoExists,
+            0x0800 => ErrorCode::Action,
+            0x1000 => ErrorCode::XMLRPCAPI,
+            0x2000 => ErrorCode::Internal,
+            0x4000 => ErrorCode::Allocate,
+            0x8000 => ErrorCode::Locked,
+            code => ErrorCode::Unknown(code),
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorCode::Authentication => f.write_str("AUTHENTICATION"),
+            ErrorCode::Authorization => f.write_str("AUTHORIZATION"),
+            ErrorCode::NoExists => f.write_str("NO_EXISTS"),
+            ErrorCode::Action => f.write_str("ACTION"),
+            ErrorCode::XMLRPCAPI => f.write_str("XML_RPC_API"),
+            ErrorCode::Internal => f.write_str("INTERNAL"),
+            ErrorCode::Allocate => f.write_str("ALLOCATE"),
+            ErrorCode::Locked => f.write_str("LOCKED"),
+            ErrorCode::Unknown(code) => write!(f, "UNKNOWN({:#x})", code),
+        }
+    }
+}
+
+/// A failed call reported by OpenNebula, with the context of the call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenNebulaError {
+    pub code: ErrorCode,
+    pub message: String,
+    /// The XML-RPC method, i.e. "one.vm.info"
+    pub method: String,
+    /// Summary of the arguments, strings are redacted, see `args_summary`
+    pub args: String,
+}
+
+impl Display for OpenNebulaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}({}): {}",
+            self.code, self.method, self.args, self.message
+        )
+    }
+}
+
+/// Summarizes the arguments of a call for the error messages.
+/// The strings are replaced by their length, they may contain passwords or tokens.
+pub fn args_summary(args: &[Value]) -> String {
+    args.iter()
+        .map(value_summary)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn value_summary(value: &Value) -> String {
+    match value {
+        Value::Int(v) => v.to_string(),
+        Value::Int64(v) => v.to_string(),
+        Value::Bool(v) => v.to_string(),
+        Value::Double(v) => v.to_string(),
+        Value::String(v) => format!("<string: {} bytes>", v.len()),
+        Value::Base64(v) => format!("<base64: {} bytes>", v.len()),
+        Value::DateTime(_) => "<datetime>".to_string(),
+        Value::Struct(members) => format!("<struct: {} members>", members.len()),
+        Value::Array(values) => format!("[{}]", args_summary(values)),
+        Value::Nil => "nil".to_string(),
+    }
 }
 
 impl From<xml_doc::Error> for Errors {
@@ -33,6 +165,12 @@ impl From<serde_xmlrpc::Error> for Errors {
     }
 }
 
+impl From<OpenNebulaError> for Errors {
+    fn from(err: OpenNebulaError) -> Self {
+        Self::OpenNebula(err)
+    }
+}
+
 impl Display for Errors {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -55,10 +193,80 @@ impl Display for Errors {
             Self::ParseInt(e) => write!(f, "roca: Failed to parse as integer: {}", e),
             Self::XMLRPC(e) => write!(f, "roca: XML-RPC error: {}", e),
             Self::HTTPReq(e) => write!(f, "roca: HTTP request error: {}", e),
+            Self::Connect(e) => write!(f, "roca: connection error: {}", e),
+            Self::HTTPStatus(code, body) => {
+                write!(f, "roca: HTTP error status {}: {}", code, body)
+            }
             Self::HTTPRespHandling(e) => write!(f, "roca: HTTP response handling error: {}", e),
             Self::OpenNebula(e) => write!(f, "roca: OpenNebula error: {}", e),
             Self::Roca(e) => write!(f, "roca library internal error: {}", e),
             Self::Template(e) => write!(f, "roca failed template operation: {}", e),
+            Self::TemplateLexer(e) => write!(f, "roca: template parsing: lexer error: {}", e),
+            Self::TemplateParser(e) => write!(f, "roca: template parsing: parser error: {}", e),
+            Self::PolicyDenied(e) => write!(f, "roca: call denied by policy: {}", e),
         }
     }
 }
+
+impl std::error::Error for Errors {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ParseInt(e) => Some(e),
+            Self::XMLRPC(e) => Some(e),
+            Self::OpenNebula(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for OpenNebulaError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn error_codes() {
+        for code in [
+            0x100, 0x200, 0x400, 0x800, 0x1000, 0x2000, 0x4000, 0x8000, 3,
+        ] {
+            assert_eq!(ErrorCode::from(code).value(), code);
+        }
+        assert_eq!(ErrorCode::from(0x8000), ErrorCode::Locked);
+        assert!(ErrorCode::from(0x200).is_denied());
+
+        let err = Errors::OpenNebula(OpenNebulaError {
+            code: ErrorCode::NoExists,
+            message: "[one.vm.info] Error getting virtual machine [12].".to_string(),
+            method: "one.vm.info".to_string(),
+            args: args_summary(&[12.into()]),
+        });
+        assert_eq!(err.code(), Some(ErrorCode::NoExists));
+        assert_eq!(
+            err.to_string(),
+            "roca: OpenNebula error: NO_EXISTS one.vm.info(12): [one.vm.info] Error getting virtual machine [12]."
+        );
+
+        // the OpenNebula error is reachable through the source chain
+        let source = std::error::Error::source(&err).unwrap();
+        assert_eq!(
+            source.downcast_ref::<OpenNebulaError>().map(|e| e.code),
+            Some(ErrorCode::NoExists)
+        );
+    }
+
+    #[test]
+    fn redacted_args() {
+        let summary = args_summary(&[
+            "oneadmin".into(),
+            "secret password".into(),
+            Value::Array(vec![1.into(), 100.into()]),
+            false.into(),
+        ]);
+        assert!(!summary.contains("secret"));
+        assert_eq!(
+            summary,
+            "<string: 8 bytes>, <string: 15 bytes>, [1, 100], false"
+        );
+    }
+}
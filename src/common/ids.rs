@@ -0,0 +1,192 @@
+//! The ids module defines a type per kind of resource ID, so that a host ID can't be passed
+//! where a datastore ID is expected.
+//!
+//! The special `-1` values of the API are explicit: `Change::Unchanged` keeps the current
+//! owner or group, `Select::Any` lets OpenNebula choose a host or a datastore.
+
+use std::fmt::Display;
+
+use serde_xmlrpc::Value;
+
+use crate::common::response::FromValue;
+use crate::common::Errors;
+
+/// ResourceId is implemented by the ID types, they wrap the ID sent to OpenNebula
+pub trait ResourceId: Copy + From<i32> + Into<i32> {}
+
+/// The ID of a resource whose kind is only known at runtime
+impl ResourceId for i32 {}
+
+macro_rules! define_id {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(pub i32);
+
+        impl ResourceId for $name {}
+
+        impl From<i32> for $name {
+            fn from(id: i32) -> Self {
+                $name(id)
+            }
+        }
+
+        impl From<$name> for i32 {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl From<$name> for Value {
+            fn from(id: $name) -> Self {
+                Value::Int(id.0)
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+
+        /// The ID returned by an allocate method
+        impl FromValue for $name {
+            fn from_value(value: Value) -> Result<Self, Errors> {
+                i32::from_value(value).map($name)
+            }
+        }
+    };
+}
+
+define_id!(
+    /// A virtual machine ID
+    VmId
+);
+define_id!(
+    /// A user ID
+    UserId
+);
+define_id!(
+    /// A group ID
+    GroupId
+);
+define_id!(
+    /// A host ID
+    HostId
+);
+define_id!(
+    /// A datastore ID
+    DatastoreId
+);
+define_id!(
+    /// An image ID
+    ImageId
+);
+define_id!(
+    /// A security group ID
+    SecurityGroupId
+);
+define_id!(
+    /// A zone ID
+    ZoneId
+);
+define_id!(
+    /// The ID of a disk of a virtual machine
+    DiskId
+);
+define_id!(
+    /// The ID of a NIC of a virtual machine
+    NicId
+);
+define_id!(
+    /// The ID of a snapshot of a disk
+    SnapshotId
+);
+
+/// A new owner, group... or -1 to keep the current one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change<I: ResourceId> {
+    Unchanged,
+    To(I),
+}
+
+impl<I: ResourceId> From<I> for Change<I> {
+    fn from(id: I) -> Self {
+        Change::To(id)
+    }
+}
+
+impl<I: ResourceId> From<Change<I>> for Value {
+    fn from(change: Change<I>) -> Self {
+        match change {
+            Change::Unchanged => Value::Int(-1),
+            Change::To(id) => Value::Int(id.into()),
+        }
+    }
+}
+
+/// A given host, datastore... or -1 to let OpenNebula choose
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Select<I: ResourceId> {
+    Any,
+    Id(I),
+}
+
+impl<I: ResourceId> From<I> for Select<I> {
+    fn from(id: I) -> Self {
+        Select::Id(id)
+    }
+}
+
+impl<I: ResourceId> From<Select<I>> for Value {
+    fn from(select: Select<I>) -> Self {
+        match select {
+            Select::Any => Value::Int(-1),
+            Select::Id(id) => Value::Int(id.into()),
+        }
+    }
+}
+
+/// The ownership filter of the pool methods
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolFilter {
+    /// All the resources, -2
+    All,
+    /// The resources of the user and of its groups, -1
+    MineAndGroups,
+    /// The resources of the user, -3
+    Mine,
+    /// The resources of the user
+    User(UserId),
+}
+
+impl From<PoolFilter> for Value {
+    fn from(filter: PoolFilter) -> Self {
+        match filter {
+            PoolFilter::All => Value::Int(-2),
+            PoolFilter::MineAndGroups => Value::Int(-1),
+            PoolFilter::Mine => Value::Int(-3),
+            PoolFilter::User(id) => id.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn id_values() {
+        assert_eq!(Value::from(VmId(12)), Value::Int(12));
+        assert_eq!(HostId::from_value(Value::Int(3)).unwrap(), HostId(3));
+        assert!(HostId::from_value(Value::String("3".to_string())).is_err());
+        assert_eq!(DatastoreId(100).to_string(), "100");
+
+        assert_eq!(Value::from(Change::<GroupId>::Unchanged), Value::Int(-1));
+        assert_eq!(Value::from(Change::from(GroupId(105))), Value::Int(105));
+        assert_eq!(Value::from(Select::<DatastoreId>::Any), Value::Int(-1));
+        assert_eq!(Value::from(Select::Id(DatastoreId(1))), Value::Int(1));
+        assert_eq!(Value::from(PoolFilter::All), Value::Int(-2));
+        assert_eq!(Value::from(PoolFilter::User(UserId(7))), Value::Int(7));
+    }
+}
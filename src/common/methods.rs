@@ -0,0 +1,127 @@
+//! The methods module classifies the OpenNebula XML-RPC methods
+
+use serde_xmlrpc::Value;
+
+/// Tells if a XML-RPC method only reads datas or modifies the OpenNebula state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MethodKind {
+    Read,
+    Mutate,
+}
+
+// last segment of the read only methods, i.e. one.vm.info, one.vmpool.infoextended...
+const READ_SUFFIXES: [&str; 9] = [
+    "info",
+    "infoextended",
+    "infoset",
+    "monitoring",
+    "accounting",
+    "showback",
+    "raftstatus",
+    "version",
+    "config",
+];
+
+/// Returns the kind of the method, unknown methods are considered as mutating
+pub fn kind(method: &str) -> MethodKind {
+    if !method.starts_with("one.") {
+        return MethodKind::Mutate;
+    }
+
+    match method.rsplit('.').next() {
+        Some(suffix) if READ_SUFFIXES.contains(&suffix) => MethodKind::Read,
+        _ => MethodKind::Mutate,
+    }
+}
+
+pub fn is_read_only(method: &str) -> bool {
+    kind(method) == MethodKind::Read
+}
+
+// resources replicated in all the zones of a federation, i.e. one.user.*, one.userpool.*
+const FEDERATED_RESOURCES: [&str; 9] = [
+    "user",
+    "group",
+    "acl",
+    "vdc",
+    "zone",
+    "market",
+    "marketapp",
+    "userquota",
+    "groupquota",
+];
+
+/// Tells if the method acts on a resource shared by all the zones of a federation,
+/// these resources are written on the master zone
+pub fn is_federated(method: &str) -> bool {
+    let resource = match method.strip_prefix("one.") {
+        Some(rest) => rest.split('.').next().unwrap_or_default(),
+        None => return false,
+    };
+    let resource = resource.strip_suffix("pool").unwrap_or(resource);
+
+    FEDERATED_RESOURCES.contains(&resource)
+}
+
+// methods of a resource which don't take the resource ID
+const NO_ID_METHODS: [&str; 2] = ["allocate", "login"];
+
+/// Returns the ID of the resource the method acts on: the first integer of the arguments,
+/// i.e. one.vm.action("resume", 12). The pool and allocate methods don't act on a resource.
+pub fn resource_id(method: &str, args: &[Value]) -> Option<i32> {
+    let mut segments = method.strip_prefix("one.")?.split('.');
+    let resource = segments.next()?;
+    let action = segments.next()?;
+    if resource.ends_with("pool") || NO_ID_METHODS.contains(&action) {
+        return None;
+    }
+
+    args.iter().find_map(|arg| match arg {
+        Value::Int(id) => Some(*id),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn methods_kind() {
+        assert_eq!(kind("one.vm.info"), MethodKind::Read);
+        assert_eq!(kind("one.vmpool.infoextended"), MethodKind::Read);
+        assert_eq!(kind("one.zone.raftstatus"), MethodKind::Read);
+        assert_eq!(kind("one.vm.allocate"), MethodKind::Mutate);
+        assert_eq!(kind("one.vm.action"), MethodKind::Mutate);
+        assert_eq!(kind("one.user.login"), MethodKind::Mutate);
+        assert_eq!(kind("system.multicall"), MethodKind::Mutate);
+    }
+
+    #[test]
+    fn federated_methods() {
+        assert!(is_federated("one.user.allocate"));
+        assert!(is_federated("one.userpool.info"));
+        assert!(is_federated("one.acl.addrule"));
+        assert!(is_federated("one.marketapp.update"));
+        assert!(!is_federated("one.vm.allocate"));
+        assert!(!is_federated("one.vmpool.info"));
+        assert!(!is_federated("one.hostpool.info"));
+        assert!(!is_federated("system.multicall"));
+    }
+
+    #[test]
+    fn methods_resource_id() {
+        assert_eq!(resource_id("one.vm.info", &[12.into()]), Some(12));
+        assert_eq!(
+            resource_id("one.vm.action", &["resume".into(), 12.into()]),
+            Some(12)
+        );
+        assert_eq!(resource_id("one.vmpool.info", &[(-2).into()]), None);
+        assert_eq!(
+            resource_id("one.vnet.allocate", &["NAME=x".into(), 100.into()]),
+            None
+        );
+        assert_eq!(resource_id("one.zone.raftstatus", &[]), None);
+        assert_eq!(resource_id("system.multicall", &[]), None);
+    }
+}
@@ -1,9 +1,16 @@
 mod errors;
-pub use errors::Errors;
+pub use errors::{args_summary, ErrorCode, Errors, OpenNebulaError};
+pub use secret::Secret;
 
+pub mod ids;
+pub mod methods;
+pub mod operations;
 pub mod parameters;
 pub mod permissions;
 pub mod resource_getters;
 pub mod resource_macros;
+pub mod response;
+pub mod secret;
 pub mod template;
+pub mod value;
 pub mod xml;
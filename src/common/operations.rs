@@ -0,0 +1,431 @@
+//! The operations module defines the operations shared by the resources (info, chown, chmod,
+//! rename, delete, update, lock) as traits implemented by the resource controllers.
+//!
+//! Generic code may then act on any resource:
+//!
+//! ```ignore
+//! fn retag<R: Update>(resource: &R) -> Result<(), Errors> {
+//!     resource.update("TEAM = \"core\"", UpdateType::Merge)
+//! }
+//!
+//! retag(&controller.virtual_machine(VmId(12)))?;
+//! retag(&controller.resource(ResourceKind::Image, 5))?;
+//! ```
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+
+use crate::chmod_args;
+use crate::common::ids::{Change, GroupId, UserId};
+use crate::common::parameters::{LockLevel, UpdateType};
+use crate::common::permissions::{Permissions, PermissionsBits};
+use crate::common::Errors;
+#[cfg(feature = "async")]
+use crate::controller::{AsyncController, AsyncRPCCaller};
+use crate::controller::{Controller, RPCCaller};
+
+/// The kind of a resource, it gives the prefix of its XML-RPC methods
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    VirtualMachine,
+    Host,
+    Cluster,
+    Datastore,
+    Image,
+    Template,
+    VirtualNetwork,
+    VnTemplate,
+    SecurityGroup,
+    VmGroup,
+    User,
+    Group,
+    Vdc,
+    Zone,
+    Document,
+    VirtualRouter,
+    MarketPlace,
+    MarketPlaceApp,
+    Hook,
+    BackupJob,
+}
+
+impl ResourceKind {
+    pub const ALL: [ResourceKind; 20] = [
+        ResourceKind::VirtualMachine,
+        ResourceKind::Host,
+        ResourceKind::Cluster,
+        ResourceKind::Datastore,
+        ResourceKind::Image,
+        ResourceKind::Template,
+        ResourceKind::VirtualNetwork,
+        ResourceKind::VnTemplate,
+        ResourceKind::SecurityGroup,
+        ResourceKind::VmGroup,
+        ResourceKind::User,
+        ResourceKind::Group,
+        ResourceKind::Vdc,
+        ResourceKind::Zone,
+        ResourceKind::Document,
+        ResourceKind::VirtualRouter,
+        ResourceKind::MarketPlace,
+        ResourceKind::MarketPlaceApp,
+        ResourceKind::Hook,
+        ResourceKind::BackupJob,
+    ];
+
+    /// The prefix of the methods, i.e. "vm" for `one.vm.*`
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            ResourceKind::VirtualMachine => "vm",
+            ResourceKind::Host => "host",
+            ResourceKind::Cluster => "cluster",
+            ResourceKind::Datastore => "datastore",
+            ResourceKind::Image => "image",
+            ResourceKind::Template => "template",
+            ResourceKind::VirtualNetwork => "vn",
+            ResourceKind::VnTemplate => "vntemplate",
+            ResourceKind::SecurityGroup => "secgroup",
+            ResourceKind::VmGroup => "vmgroup",
+            ResourceKind::User => "user",
+            ResourceKind::Group => "group",
+            ResourceKind::Vdc => "vdc",
+            ResourceKind::Zone => "zone",
+            ResourceKind::Document => "document",
+            ResourceKind::VirtualRouter => "vrouter",
+            ResourceKind::MarketPlace => "market",
+            ResourceKind::MarketPlaceApp => "marketapp",
+            ResourceKind::Hook => "hook",
+            ResourceKind::BackupJob => "backupjob",
+        }
+    }
+
+    /// The XML-RPC method of an operation, i.e. "one.image.chown"
+    pub fn method(&self, operation: &str) -> String {
+        format!("one.{}.{}", self.prefix(), operation)
+    }
+
+    /// Tells if the resources of this kind support a shared operation, i.e. "rename":
+    /// there's no `one.user.rename` or `one.vm.delete`
+    pub fn supports(&self, operation: &str) -> bool {
+        use ResourceKind::*;
+
+        match operation {
+            "info" | "update" => true,
+            "chown" | "chmod" => !matches!(self, Host | Cluster | User | Group | Vdc | Zone | Hook),
+            "rename" => !matches!(self, User | Group),
+            "delete" => *self != VirtualMachine,
+            "lock" | "unlock" => matches!(
+                self,
+                VirtualMachine
+                    | Image
+                    | Template
+                    | VirtualNetwork
+                    | VnTemplate
+                    | VmGroup
+                    | Document
+                    | VirtualRouter
+                    | MarketPlaceApp
+                    | Hook
+                    | BackupJob
+            ),
+            _ => false,
+        }
+    }
+
+    // the method of a shared operation, the unsupported ones fail before reaching OpenNebula
+    fn operation(&self, operation: &str) -> Result<String, Errors> {
+        if !self.supports(operation) {
+            return Err(Errors::Roca(format!(
+                "the {} resources don't support {}",
+                self, operation
+            )));
+        }
+        Ok(self.method(operation))
+    }
+}
+
+impl Display for ResourceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.prefix())
+    }
+}
+
+/// Parses the prefix of the methods, i.e. "image"
+impl FromStr for ResourceKind {
+    type Err = Errors;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ResourceKind::ALL
+            .into_iter()
+            .find(|kind| kind.prefix() == s)
+            .ok_or_else(|| Errors::Roca(format!("Unknown resource kind: {}", s)))
+    }
+}
+
+/// ResourceController is implemented by the controllers of a single resource,
+/// it gives the operations the controller, the kind and the ID of the resource
+pub trait ResourceController {
+    type Caller: RPCCaller;
+
+    fn controller(&self) -> &Controller<Self::Caller>;
+    fn kind(&self) -> ResourceKind;
+    fn resource_id(&self) -> i32;
+}
+
+/// Fetches the information of the resource
+pub trait Info: ResourceController {
+    type Output: for<'a> TryFrom<&'a str, Error = Errors>;
+
+    fn info(&self) -> Result<Self::Output, Errors> {
+        let body = self.controller().call::<String>(
+            &self.kind().operation("info")?,
+            vec![self.resource_id().into()],
+        )?;
+        parse_info(&body)
+    }
+}
+
+/// Changes the owner and the group of the resource
+pub trait Chown: ResourceController {
+    /// Change::Unchanged keeps the current owner or group
+    fn chown(&self, uid: Change<UserId>, gid: Change<GroupId>) -> Result<(), Errors> {
+        self.controller().call::<()>(
+            &self.kind().operation("chown")?,
+            vec![self.resource_id().into(), uid.into(), gid.into()],
+        )
+    }
+}
+
+/// Changes the permissions of the resource
+pub trait Chmod: ResourceController {
+    fn chmod(&self, perms_oct: Permissions) -> Result<(), Errors> {
+        self.chmod_raw(PermissionsBits::from(perms_oct))
+    }
+
+    /// Compared to chmod this method allow to pass -1 for each bit in order to
+    /// leave bit unchanged
+    fn chmod_raw(&self, perms_bits: PermissionsBits) -> Result<(), Errors> {
+        self.controller().call::<()>(
+            &self.kind().operation("chmod")?,
+            chmod_args!(self.resource_id(), perms_bits),
+        )
+    }
+}
+
+/// Changes the name of the resource
+pub trait Rename: ResourceController {
+    fn rename(&self, new_name: &str) -> Result<(), Errors> {
+        self.controller().call::<()>(
+            &self.kind().operation("rename")?,
+            vec![self.resource_id().into(), new_name.into()],
+        )
+    }
+}
+
+/// Deletes the resource
+pub trait Delete: ResourceController {
+    fn delete(&self) -> Result<(), Errors> {
+        self.controller().call::<()>(
+            &self.kind().operation("delete")?,
+            vec![self.resource_id().into()],
+        )
+    }
+}
+
+/// Updates the template of the resource
+pub trait Update: ResourceController {
+    /// * tpl: The new contents. Syntax can be the usual attribute=value or XML.
+    /// * policy: Replace the whole template or merge the new template with the existing one.
+    fn update<T: Display>(&self, tpl: T, policy: UpdateType) -> Result<(), Errors> {
+        self.controller().call::<()>(
+            &self.kind().operation("update")?,
+            vec![
+                self.resource_id().into(),
+                tpl.to_string().into(),
+                policy.value().into(),
+            ],
+        )
+    }
+}
+
+/// Locks the resource to block some operations
+pub trait Lock: ResourceController {
+    /// * test: fail if the resource is already locked, instead of changing the lock level
+    fn lock(&self, level: LockLevel, test: bool) -> Result<(), Errors> {
+        self.controller().call::<()>(
+            &self.kind().operation("lock")?,
+            vec![self.resource_id().into(), level.value().into(), test.into()],
+        )
+    }
+
+    fn unlock(&self) -> Result<(), Errors> {
+        self.controller().call::<()>(
+            &self.kind().operation("unlock")?,
+            vec![self.resource_id().into()],
+        )
+    }
+}
+
+fn parse_info<T: for<'a> TryFrom<&'a str, Error = Errors>>(body: &str) -> Result<T, Errors> {
+    T::try_from(body).map_err(|e| Errors::Roca(format!("Failed to parse the resource: {}", e)))
+}
+
+/// AsyncResourceController is the asynchronous counterpart of ResourceController
+#[cfg(feature = "async")]
+pub trait AsyncResourceController: Sync {
+    type Caller: AsyncRPCCaller;
+
+    fn controller(&self) -> &AsyncController<Self::Caller>;
+    fn kind(&self) -> ResourceKind;
+    fn resource_id(&self) -> i32;
+}
+
+/// Fetches the information of the resource
+#[cfg(feature = "async")]
+#[async_trait]
+pub trait AsyncInfo: AsyncResourceController {
+    type Output: for<'a> TryFrom<&'a str, Error = Errors> + Send;
+
+    async fn info(&self) -> Result<Self::Output, Errors> {
+        let body = self
+            .controller()
+            .call::<String>(
+                &self.kind().operation("info")?,
+                vec![self.resource_id().into()],
+            )
+            .await?;
+        parse_info(&body)
+    }
+}
+
+/// Changes the owner and the group of the resource
+#[cfg(feature = "async")]
+#[async_trait]
+pub trait AsyncChown: AsyncResourceController {
+    /// Change::Unchanged keeps the current owner or group
+    async fn chown(&self, uid: Change<UserId>, gid: Change<GroupId>) -> Result<(), Errors> {
+        self.controller()
+            .call::<()>(
+                &self.kind().operation("chown")?,
+                vec![self.resource_id().into(), uid.into(), gid.into()],
+            )
+            .await
+    }
+}
+
+/// Changes the permissions of the resource
+#[cfg(feature = "async")]
+#[async_trait]
+pub trait AsyncChmod: AsyncResourceController {
+    async fn chmod(&self, perms_oct: Permissions) -> Result<(), Errors> {
+        self.chmod_raw(PermissionsBits::from(perms_oct)).await
+    }
+
+    /// Compared to chmod this method allow to pass -1 for each bit in order to
+    /// leave bit unchanged
+    async fn chmod_raw(&self, perms_bits: PermissionsBits) -> Result<(), Errors> {
+        self.controller()
+            .call::<()>(
+                &self.kind().operation("chmod")?,
+                chmod_args!(self.resource_id(), perms_bits),
+            )
+            .await
+    }
+}
+
+/// Changes the name of the resource
+#[cfg(feature = "async")]
+#[async_trait]
+pub trait AsyncRename: AsyncResourceController {
+    async fn rename(&self, new_name: &str) -> Result<(), Errors> {
+        self.controller()
+            .call::<()>(
+                &self.kind().operation("rename")?,
+                vec![self.resource_id().into(), new_name.into()],
+            )
+            .await
+    }
+}
+
+/// Deletes the resource
+#[cfg(feature = "async")]
+#[async_trait]
+pub trait AsyncDelete: AsyncResourceController {
+    async fn delete(&self) -> Result<(), Errors> {
+        self.controller()
+            .call::<()>(
+                &self.kind().operation("delete")?,
+                vec![self.resource_id().into()],
+            )
+            .await
+    }
+}
+
+/// Updates the template of the resource
+#[cfg(feature = "async")]
+#[async_trait]
+pub trait AsyncUpdate: AsyncResourceController {
+    /// * tpl: The new contents. Syntax can be the usual attribute=value or XML.
+    /// * policy: Replace the whole template or merge the new template with the existing one.
+    async fn update<T: Display + Send>(&self, tpl: T, policy: UpdateType) -> Result<(), Errors> {
+        let args = vec![
+            self.resource_id().into(),
+            tpl.to_string().into(),
+            policy.value().into(),
+        ];
+        self.controller()
+            .call::<()>(&self.kind().operation("update")?, args)
+            .await
+    }
+}
+
+/// Locks the resource to block some operations
+#[cfg(feature = "async")]
+#[async_trait]
+pub trait AsyncLock: AsyncResourceController {
+    /// * test: fail if the resource is already locked, instead of changing the lock level
+    async fn lock(&self, level: LockLevel, test: bool) -> Result<(), Errors> {
+        let args = vec![self.resource_id().into(), level.value().into(), test.into()];
+        self.controller()
+            .call::<()>(&self.kind().operation("lock")?, args)
+            .await
+    }
+
+    async fn unlock(&self) -> Result<(), Errors> {
+        self.controller()
+            .call::<()>(
+                &self.kind().operation("unlock")?,
+                vec![self.resource_id().into()],
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resource_kinds() {
+        assert_eq!(ResourceKind::Image.method("chown"), "one.image.chown");
+        assert_eq!(ResourceKind::VirtualNetwork.to_string(), "vn");
+
+        for kind in ResourceKind::ALL {
+            assert_eq!(kind.prefix().parse::<ResourceKind>().unwrap(), kind);
+        }
+        assert!("vmpool".parse::<ResourceKind>().is_err());
+
+        assert!(ResourceKind::Image.supports("chown"));
+        assert!(ResourceKind::Zone.supports("rename"));
+        assert!(!ResourceKind::User.supports("rename"));
+        assert!(!ResourceKind::VirtualMachine.supports("delete"));
+        assert!(!ResourceKind::Host.supports("lock"));
+        assert!(matches!(
+            ResourceKind::Group.operation("chmod"),
+            Err(Errors::Roca(_))
+        ));
+    }
+}
@@ -8,3 +8,18 @@ impl UpdateType {
         self as i32
     }
 }
+
+/// The operations blocked by a lock, each level includes the previous ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockLevel {
+    Use = 1,
+    Manage = 2,
+    Admin = 3,
+    All = 4,
+}
+
+impl LockLevel {
+    pub fn value(self) -> i32 {
+        self as i32
+    }
+}
@@ -1,4 +1,5 @@
 use crate::common::errors::Errors;
+use crate::common::ids::{GroupId, ResourceId, UserId};
 use crate::common::permissions::{Permissions, PermissionsBits};
 
 use crate::common::xml::permissions::get_field;
@@ -8,8 +9,11 @@ use crate::common::xml::template::Template;
 
 /// Add default getters to retrieve generic resource attributes
 pub trait ResourceGetters: BaseGetters {
-    fn id(&self) -> Result<i64, Errors> {
-        self.get_i64("ID")
+    /// The ID type of the resource, i.e. VmId
+    type Id: ResourceId;
+
+    fn id(&self) -> Result<Self::Id, Errors> {
+        self.get_id("ID")
     }
 
     fn name(&self) -> Result<String, Errors> {
@@ -27,8 +31,8 @@ pub trait ResourceGetters: BaseGetters {
 
 /// Add user attributes getters
 pub trait GetOwner: ResourceGetters {
-    fn uid(&self) -> Result<i64, Errors> {
-        self.get_i64("UID")
+    fn uid(&self) -> Result<UserId, Errors> {
+        self.get_id("UID")
     }
     fn username(&self) -> Result<String, Errors> {
         self.get("UNAME")
@@ -37,8 +41,8 @@ pub trait GetOwner: ResourceGetters {
 
 // Add group attributes getters
 pub trait GetGroup: ResourceGetters {
-    fn gid(&self) -> Result<i64, Errors> {
-        self.get_i64("GID")
+    fn gid(&self) -> Result<GroupId, Errors> {
+        self.get_id("GID")
     }
     fn groupname(&self) -> Result<String, Errors> {
         self.get("GNAME")
@@ -1,51 +1,128 @@
+/// Implements ResourceController (and AsyncResourceController) for the controllers of a resource
 #[macro_export]
-macro_rules! rpc_noparam_method {
-    ($method_name:ident, $rpc_method:expr) => {
-        pub fn $method_name(&self) -> Result<(), Errors> {
-            let resp_txt = self
-                .controller
-                .client
-                .call($rpc_method, vec![self.id.into()])?;
+macro_rules! resource_controller {
+    (async $controller:ident, $kind:expr) => {
+        impl<C: $crate::controller::AsyncRPCCaller>
+            $crate::common::operations::AsyncResourceController for $controller<C>
+        {
+            type Caller = C;
 
-            self.controller.parse_id_resp(resp_txt)?;
+            fn controller(&self) -> &$crate::controller::AsyncController<C> {
+                &self.controller
+            }
+            fn kind(&self) -> $crate::common::operations::ResourceKind {
+                $kind
+            }
+            fn resource_id(&self) -> i32 {
+                self.id.into()
+            }
+        }
+    };
+    ($controller:ident, $kind:expr) => {
+        impl<C: $crate::controller::RPCCaller> $crate::common::operations::ResourceController
+            for $controller<C>
+        {
+            type Caller = C;
 
-            Ok(())
+            fn controller(&self) -> &$crate::controller::Controller<C> {
+                &self.controller
+            }
+            fn kind(&self) -> $crate::common::operations::ResourceKind {
+                $kind
+            }
+            fn resource_id(&self) -> i32 {
+                self.id.into()
+            }
         }
     };
 }
 
+/// Generates the methods of a controller and of its asynchronous counterpart from a single
+/// definition: each method calls an XML-RPC method of the controller, the values of the
+/// arguments are converted with `into`.
+///
+/// ```ignore
+/// rpc_methods! {
+///     UserController, AsyncUserController;
+///
+///     /// Changes the password
+///     fn passwd(&self, new_passwd: &str)
+///         => call(self.controller, "one.user.passwd", [self.id, new_passwd]);
+///
+///     /// The payload can be parsed by a function taking a &str
+///     fn raftstatus(&self) -> RaftState
+///         => call(self.controller, "one.zone.raftstatus", []) | parse_raft_state;
+/// }
+/// ```
+///
+/// `call` returns the payload, `call_response` the whole response. The return type is `()` if
+/// omitted.
 #[macro_export]
-macro_rules! rpc_chmod_method {
-    ($rpc_method:expr) => {
-        /// Changes the permissions of the resource
-        pub fn chmod(&self, perms_oct: Permissions) -> Result<(), Errors> {
-            let permissions_bits = PermissionsBits::from(perms_oct);
-
-            self.chmod_raw(permissions_bits)
+macro_rules! rpc_methods {
+    (@ret) => { () };
+    (@ret $ret:ty) => { $ret };
+    (@sync $call:ident, $caller:expr, $method:expr, [$($value:expr),*]) => {
+        $caller.$call($method, vec![$($value.into()),*])
+    };
+    (@sync $call:ident, $caller:expr, $method:expr, [$($value:expr),*], $parse:path) => {
+        $parse(&$caller.$call::<String>($method, vec![$($value.into()),*])?)
+    };
+    (@async $call:ident, $caller:expr, $method:expr, [$($value:expr),*]) => {
+        $caller.$call($method, vec![$($value.into()),*]).await
+    };
+    (@async $call:ident, $caller:expr, $method:expr, [$($value:expr),*], $parse:path) => {
+        $parse(&$caller.$call::<String>($method, vec![$($value.into()),*]).await?)
+    };
+    (
+        $controller:ident, $async_controller:ident;
+        $(
+            $(#[$attr:meta])*
+            fn $name:ident $(<$g:ident : $bound:ident $(+ $bounds:ident)*>)?
+                (&$self:ident $(, $arg:ident : $ty:ty)* $(,)?) $(-> $ret:ty)?
+                => $call:ident($caller:expr, $method:expr, [$($value:expr),* $(,)?])
+                $(| $parse:path)?;
+        )*
+    ) => {
+        impl<C: $crate::controller::RPCCaller> $controller<C> {
+            $(
+                $(#[$attr])*
+                pub fn $name $(<$g: $bound $(+ $bounds)*>)? (&$self $(, $arg: $ty)*)
+                    -> Result<$crate::rpc_methods!(@ret $($ret)?), $crate::common::Errors>
+                {
+                    $crate::rpc_methods!(@sync $call, $caller, $method, [$($value),*] $(, $parse)?)
+                }
+            )*
         }
 
-        /// Compared to chmod this method allow to pass -1 for each bit in order to
-        /// leave bit unchanged
-        pub fn chmod_raw(&self, perms_bits: PermissionsBits) -> Result<(), Errors> {
-            let resp_txt = self.controller.client.call(
-                $rpc_method,
-                vec![
-                    self.id.into(),
-                    (perms_bits.0 as i32).into(),
-                    (perms_bits.1 as i32).into(),
-                    (perms_bits.2 as i32).into(),
-                    (perms_bits.3 as i32).into(),
-                    (perms_bits.4 as i32).into(),
-                    (perms_bits.5 as i32).into(),
-                    (perms_bits.6 as i32).into(),
-                    (perms_bits.7 as i32).into(),
-                    (perms_bits.8 as i32).into(),
-                ],
-            )?;
-
-            self.controller.parse_id_resp(resp_txt)?;
-
-            Ok(())
+        #[cfg(feature = "async")]
+        impl<C: $crate::controller::AsyncRPCCaller> $async_controller<C> {
+            $(
+                $(#[$attr])*
+                pub async fn $name $(<$g: $bound $(+ $bounds)*>)? (&$self $(, $arg: $ty)*)
+                    -> Result<$crate::rpc_methods!(@ret $($ret)?), $crate::common::Errors>
+                {
+                    $crate::rpc_methods!(@async $call, $caller, $method, [$($value),*] $(, $parse)?)
+                }
+            )*
         }
     };
 }
+
+/// Builds the arguments list of the chmod XML-RPC methods
+#[macro_export]
+macro_rules! chmod_args {
+    ($id:expr, $perms_bits:expr) => {
+        vec![
+            $id.into(),
+            ($perms_bits.0 as i32).into(),
+            ($perms_bits.1 as i32).into(),
+            ($perms_bits.2 as i32).into(),
+            ($perms_bits.3 as i32).into(),
+            ($perms_bits.4 as i32).into(),
+            ($perms_bits.5 as i32).into(),
+            ($perms_bits.6 as i32).into(),
+            ($perms_bits.7 as i32).into(),
+            ($perms_bits.8 as i32).into(),
+        ]
+    };
+}
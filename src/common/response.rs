@@ -0,0 +1,263 @@
+//! The response module decodes the OpenNebula responses: an array containing the success flag,
+//! the payload, the error code, then optional extra values
+
+use std::collections::BTreeMap;
+
+use serde_xmlrpc::Value;
+
+use crate::common::{Errors, OpenNebulaError};
+
+/// FromValue converts the payload of a response into the type expected by the caller
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self, Errors>;
+}
+
+fn unexpected(expected: &str, value: &Value) -> Errors {
+    Errors::Roca(format!(
+        "unexpected response payload: expected {}, got {:?}",
+        expected, value
+    ))
+}
+
+/// The payload is ignored, only the success matters
+impl FromValue for () {
+    fn from_value(_value: Value) -> Result<Self, Errors> {
+        Ok(())
+    }
+}
+
+/// An ID
+impl FromValue for i32 {
+    fn from_value(value: Value) -> Result<Self, Errors> {
+        match value {
+            Value::Int(v) => Ok(v),
+            v => Err(unexpected("an int", &v)),
+        }
+    }
+}
+
+/// A XML body or a string like a token
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self, Errors> {
+        match value {
+            Value::String(v) => Ok(v),
+            v => Err(unexpected("a string", &v)),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<Self, Errors> {
+        match value {
+            Value::Bool(v) => Ok(v),
+            v => Err(unexpected("a boolean", &v)),
+        }
+    }
+}
+
+/// A 64 bits integer, i.e. a size, also accepts 32 bits integers
+impl FromValue for i64 {
+    fn from_value(value: Value) -> Result<Self, Errors> {
+        match value {
+            Value::Int64(v) => Ok(v),
+            Value::Int(v) => Ok(v.into()),
+            v => Err(unexpected("an int", &v)),
+        }
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value) -> Result<Self, Errors> {
+        match value {
+            Value::Double(v) => Ok(v),
+            v => Err(unexpected("a double", &v)),
+        }
+    }
+}
+
+/// A base64 binary content
+impl FromValue for Vec<u8> {
+    fn from_value(value: Value) -> Result<Self, Errors> {
+        match value {
+            Value::Base64(v) => Ok(v),
+            v => Err(unexpected("a base64 content", &v)),
+        }
+    }
+}
+
+/// An array, i.e. a list of IDs
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Self, Errors> {
+        match value {
+            Value::Array(values) => values.into_iter().map(T::from_value).collect(),
+            v => Err(unexpected("an array", &v)),
+        }
+    }
+}
+
+/// A struct, by member name
+impl FromValue for BTreeMap<String, Value> {
+    fn from_value(value: Value) -> Result<Self, Errors> {
+        match value {
+            Value::Struct(members) => Ok(members),
+            v => Err(unexpected("a struct", &v)),
+        }
+    }
+}
+
+/// A nil value is None
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Self, Errors> {
+        match value {
+            Value::Nil => Ok(None),
+            v => Ok(Some(T::from_value(v)?)),
+        }
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self, Errors> {
+        Ok(value)
+    }
+}
+
+/// A successful response
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response<T> {
+    pub payload: T,
+    /// Values following the error code, only sent by some methods
+    pub extra: Vec<Value>,
+}
+
+impl<T> Response<T> {
+    /// Decodes the extra value at the index, the first extra value follows the error code
+    pub fn extra_value<V: FromValue>(&self, index: usize) -> Result<V, Errors> {
+        match self.extra.get(index) {
+            Some(value) => V::from_value(value.clone()),
+            None => Err(Errors::Roca(format!(
+                "unexpected response: missing extra value {}",
+                index
+            ))),
+        }
+    }
+}
+
+impl<T: FromValue> Response<T> {
+    /// Decodes the response in a single pass, a failure is returned with the context of the call
+    pub fn decode(raw: &str, method: &str, args: &str) -> Result<Self, Errors> {
+        let values = serde_xmlrpc::response_from_str::<Vec<Value>>(raw)?;
+        Self::from_values(values, method, args)
+    }
+
+    /// Builds the response from the values of the response array
+    pub fn from_values(values: Vec<Value>, method: &str, args: &str) -> Result<Self, Errors> {
+        let mut values = values.into_iter();
+
+        let success = match values.next() {
+            Some(Value::Bool(s)) => s,
+            v => {
+                return Err(Errors::Roca(format!(
+                    "unexpected response: expected a success flag, got {:?}",
+                    v
+                )))
+            }
+        };
+        let payload = values.next().unwrap_or(Value::Nil);
+        let code = match values.next() {
+            Some(Value::Int(c)) => c,
+            _ => 0,
+        };
+
+        if !success {
+            let message = match payload {
+                Value::String(m) => m,
+                v => format!("{:?}", v),
+            };
+            return Err(Errors::OpenNebula(OpenNebulaError {
+                code: code.into(),
+                message,
+                method: method.to_string(),
+                args: args.to_string(),
+            }));
+        }
+
+        Ok(Response {
+            payload: T::from_value(payload)?,
+            extra: values.collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::ErrorCode;
+
+    #[test]
+    fn decode_values() {
+        let resp = Response::<i32>::from_values(
+            vec![true.into(), 12.into(), 0.into()],
+            "one.vm.allocate",
+            "",
+        );
+        assert_eq!(resp.unwrap().payload, 12);
+
+        let resp = Response::<String>::from_values(
+            vec![true.into(), "<VM></VM>".into(), 0.into()],
+            "one.vm.info",
+            "12",
+        );
+        assert_eq!(resp.unwrap().payload, "<VM></VM>");
+
+        // extra values
+        let resp = Response::<i32>::from_values(
+            vec![true.into(), 3.into(), 0.into(), 4.into()],
+            "one.vm.disksnapshotcreate",
+            "",
+        )
+        .unwrap();
+        assert_eq!(resp.payload, 3);
+        assert_eq!(resp.extra, vec![Value::Int(4)]);
+        assert_eq!(resp.extra_value::<i64>(0).unwrap(), 4);
+        assert!(resp.extra_value::<i32>(1).is_err());
+
+        // wrong payload type
+        let resp = Response::<i32>::from_values(
+            vec![true.into(), "<VM></VM>".into(), 0.into()],
+            "one.vm.info",
+            "12",
+        );
+        assert!(matches!(resp, Err(Errors::Roca(_))));
+
+        let resp = Response::<()>::from_values(
+            vec![false.into(), "[one.vm.lock] locked".into(), 0x8000.into()],
+            "one.vm.lock",
+            "12, 4",
+        );
+        match resp {
+            Err(Errors::OpenNebula(e)) => {
+                assert_eq!(e.code, ErrorCode::Locked);
+                assert_eq!(e.method, "one.vm.lock");
+                assert_eq!(e.args, "12, 4");
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn decode_payloads() {
+        assert_eq!(i64::from_value(Value::Int64(1 << 40)).unwrap(), 1 << 40);
+        assert_eq!(i64::from_value(Value::Int(12)).unwrap(), 12);
+        assert_eq!(
+            Vec::<i32>::from_value(Value::Array(vec![1.into(), 100.into()])).unwrap(),
+            vec![1, 100]
+        );
+        assert!(Vec::<i32>::from_value(Value::Array(vec!["1".into()])).is_err());
+        assert_eq!(
+            Vec::<u8>::from_value(Value::Base64(b"roca".to_vec())).unwrap(),
+            b"roca"
+        );
+        assert_eq!(Option::<String>::from_value(Value::Nil).unwrap(), None);
+        assert_eq!(Option::<i32>::from_value(Value::Int(3)).unwrap(), Some(3));
+    }
+}
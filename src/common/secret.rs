@@ -0,0 +1,47 @@
+use std::fmt::Debug;
+
+/// Secret holds a sensitive string (password, token, session...) and redacts it from the Debug output
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new<S: Into<String>>(secret: S) -> Self {
+        Secret(secret.into())
+    }
+
+    /// Gives access to the secret content, don't log it.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(secret: String) -> Self {
+        Secret(secret)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(secret: &str) -> Self {
+        Secret(secret.to_string())
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn secret_redacted() {
+        let secret = Secret::new("oneadmin:pDi4mFBHue");
+
+        assert_eq!(format!("{:?}", secret), "Secret([REDACTED])");
+        assert_eq!(secret.expose(), "oneadmin:pDi4mFBHue");
+    }
+}
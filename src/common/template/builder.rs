@@ -56,6 +56,16 @@ impl Builder {
         ))
     }
 
+    /// Returns the pairs, in insertion order
+    pub fn pairs(&self) -> &[Pair] {
+        &self.pairs
+    }
+
+    /// Returns the vectors, in insertion order
+    pub fn vectors(&self) -> &[Vector] {
+        &self.vectors
+    }
+
     pub fn put_str(&mut self, key: &str, value: &str) {
         self.pairs.push(Pair::new(key, value))
     }
@@ -1,6 +1,5 @@
 pub mod builder;
 pub mod elements;
-pub mod errors;
 pub mod lexer;
 pub mod parser;
 pub mod token;
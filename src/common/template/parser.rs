@@ -1,8 +1,8 @@
 use crate::common::template::builder::Builder;
 use crate::common::template::elements::Vector;
-use crate::common::template::errors::Errors;
 use crate::common::template::lexer::Lexer;
 use crate::common::template::token::Tokens;
+use crate::common::Errors;
 
 // syntax: https://github.com/OpenNebula/one/blob/2eb07ee1c16140f1aca4d778b2cc2d38ea291159/src/parsers/template_syntax.y
 
@@ -29,7 +29,7 @@ impl<'a> Parser<'a> {
                 Tokens::Ident(i) => i,
                 Tokens::Eof => break,
                 _ => {
-                    return Err(Errors::Parser(format!(
+                    return Err(Errors::TemplateParser(format!(
                         "unexpected token {:?}, expect ident",
                         self.token
                     )))
@@ -40,7 +40,7 @@ impl<'a> Parser<'a> {
             match self.token {
                 Tokens::Assign => {}
                 _ => {
-                    return Err(Errors::Parser(format!(
+                    return Err(Errors::TemplateParser(format!(
                         "unexpected token {:?}, expect assignment",
                         self.token
                     )))
@@ -67,7 +67,7 @@ impl<'a> Parser<'a> {
                         let key = match self.token.clone() {
                             Tokens::Ident(i) => i,
                             _ => {
-                                return Err(Errors::Parser(format!(
+                                return Err(Errors::TemplateParser(format!(
                                     "unexpected token {:?}, expect ident",
                                     self.token
                                 )))
@@ -78,7 +78,7 @@ impl<'a> Parser<'a> {
                         match self.token {
                             Tokens::Assign => {}
                             _ => {
-                                return Err(Errors::Parser(format!(
+                                return Err(Errors::TemplateParser(format!(
                                     "unexpected token {:?}, expect assignment",
                                     self.token
                                 )))
@@ -95,7 +95,7 @@ impl<'a> Parser<'a> {
                                 vec.put_str(&key, &value)
                             }
                             _ => {
-                                return Err(Errors::Parser(format!(
+                                return Err(Errors::TemplateParser(format!(
                                     "unexpected token {:?}, expect ident or string or number",
                                     self.token
                                 )))
@@ -109,7 +109,7 @@ impl<'a> Parser<'a> {
                             }
                             Tokens::RSBracket => {}
                             _ => {
-                                return Err(Errors::Parser(format!(
+                                return Err(Errors::TemplateParser(format!(
                                     "unexpected token {:?}, expect comma",
                                     self.token
                                 )))
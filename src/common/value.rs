@@ -0,0 +1,64 @@
+//! The value module builds the XML-RPC parameters that can't be built from a single Rust value,
+//! i.e. the array of group IDs of one.user.allocate
+
+use std::collections::BTreeMap;
+
+use serde_xmlrpc::Value;
+
+/// An `<array>`, i.e. a list of IDs
+pub fn array<T: Into<Value>, I: IntoIterator<Item = T>>(values: I) -> Value {
+    Value::Array(values.into_iter().map(Into::into).collect())
+}
+
+/// A `<struct>`, the members are sorted by name
+pub fn structure<K, V, I>(members: I) -> Value
+where
+    K: Into<String>,
+    V: Into<Value>,
+    I: IntoIterator<Item = (K, V)>,
+{
+    Value::Struct(
+        members
+            .into_iter()
+            .map(|(k, v)| (k.into(), v.into()))
+            .collect::<BTreeMap<String, Value>>(),
+    )
+}
+
+/// An `<i8>`, a 64 bits integer used for the sizes
+pub fn i8(value: i64) -> Value {
+    Value::Int64(value)
+}
+
+/// A `<base64>` binary content
+pub fn base64<B: Into<Vec<u8>>>(bytes: B) -> Value {
+    Value::Base64(bytes.into())
+}
+
+/// A `<nil/>`
+pub fn nil() -> Value {
+    Value::Nil
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn build_values() {
+        assert_eq!(
+            array([1, 100]),
+            Value::Array(vec![Value::Int(1), Value::Int(100)])
+        );
+        assert_eq!(array(Vec::<i32>::new()), Value::Array(vec![]));
+        assert_eq!(i8(1 << 40), Value::Int64(1 << 40));
+        assert_eq!(base64("roca"), Value::Base64(b"roca".to_vec()));
+
+        match structure([("NAME", "roca"), ("DESC", "")]) {
+            Value::Struct(members) => {
+                assert_eq!(members.keys().collect::<Vec<_>>(), vec!["DESC", "NAME"]);
+            }
+            v => panic!("unexpected value: {:?}", v),
+        }
+    }
+}
@@ -1,15 +1,19 @@
+use std::sync::Arc;
+
 use xml_doc::{Document, Element, ReadOptions};
 
+use crate::common::resource_getters::ResourceGetters;
 use crate::common::Errors;
 
 // TODO: make a macro declaring a resource
 #[macro_export]
 macro_rules! define_resource {
-    ($resource_name:ident) => {
+    ($resource_name:ident, $id:ty) => {
         use xml_doc::{Document, Element};
         use $crate::common::resource_getters::ResourceGetters;
         use $crate::common::xml::resource::XMLDocGetters;
 
+        #[derive(Clone)]
         pub struct $resource_name {
             resource: Resource,
         }
@@ -21,7 +25,20 @@ macro_rules! define_resource {
             }
         }
 
-        impl ResourceGetters for $resource_name {}
+        impl ResourceGetters for $resource_name {
+            type Id = $id;
+        }
+
+        /// Parses the body returned by the info method, i.e. in the results of a batch
+        impl TryFrom<&str> for $resource_name {
+            type Error = $crate::common::Errors;
+
+            fn try_from(raw_xml: &str) -> Result<Self, Self::Error> {
+                Ok($resource_name {
+                    resource: Resource::try_from(raw_xml)?,
+                })
+            }
+        }
 
         impl Display for $resource_name {
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -37,12 +54,25 @@ pub trait XMLDocGetters {
     fn get_internal(&self) -> (&Document, &Element);
 }
 
-// IS IT STILL USEFUL ?
+/// A parsed resource, the document is shared by the clones: the resource is cheap to clone
+/// and may be sent to other threads
+#[derive(Clone)]
 pub struct Resource {
-    pub document: Document,
+    pub document: Arc<Document>,
     pub root: Element,
 }
 
+/// The resource of any kind returned by a ResourceHandle
+impl XMLDocGetters for Resource {
+    fn get_internal(&self) -> (&Document, &Element) {
+        (&self.document, &self.root)
+    }
+}
+
+impl ResourceGetters for Resource {
+    type Id = i32;
+}
+
 impl TryFrom<&str> for Resource {
     type Error = Errors;
 
@@ -56,6 +86,38 @@ impl TryFrom<&str> for Resource {
         };
         let root = document.root_element().unwrap();
 
-        Ok(Resource { document, root })
+        Ok(Resource {
+            document: Arc::new(document),
+            root,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use crate::common::xml::resource_pool::ResourcePool;
+    use crate::user::{DefaultQuotas, User};
+    use crate::vm::VirtualMachine;
+    use crate::vm_pool::{HistoryRecord, VirtualMachinePool};
+    use crate::zone::{Zone, ZonePool};
+
+    fn shared<T: Send + Sync + Clone>() {}
+
+    #[test]
+    fn shared_resources() {
+        shared::<ResourcePool>();
+        shared::<VirtualMachine>();
+        shared::<VirtualMachinePool>();
+        shared::<HistoryRecord>();
+        shared::<User>();
+        shared::<DefaultQuotas>();
+        shared::<Zone>();
+        shared::<ZonePool>();
+
+        // i.e. a cache of the VMs shared by the workers
+        shared::<std::sync::Arc<Mutex<HashMap<i32, VirtualMachine>>>>();
     }
 }
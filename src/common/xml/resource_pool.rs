@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use xml_doc::{Document, Element, ReadOptions};
 
@@ -6,8 +6,10 @@ use crate::common::Errors;
 
 // TODO: implement methods ?
 
+/// A resource of a pool, the document of the pool is shared by its resources
+#[derive(Clone)]
 pub struct ResourcePool {
-    pub document: Rc<Document>,
+    pub document: Arc<Document>,
     pub root: Element,
 }
 
@@ -17,7 +19,7 @@ pub fn build_pool(raw_xml: &str, resource_type: &str) -> Result<Vec<ResourcePool
     opts.require_decl = false;
 
     let document = match Document::parse_reader_with_opts(raw_xml.as_bytes(), opts) {
-        Ok(p) => Rc::new(p),
+        Ok(p) => Arc::new(p),
         Err(e) => return Err(e.into()),
     };
 
@@ -1,4 +1,5 @@
 use crate::common::errors::Errors;
+use crate::common::ids::ResourceId;
 use crate::common::template::elements::{Pair, Vector};
 use crate::common::xml::resource::XMLDocGetters;
 
@@ -29,6 +30,13 @@ pub trait BaseGetters: XMLDocGetters {
         Ok(i_str.parse::<i64>()?)
     }
 
+    /// Returns the ID of a resource, i.e. the UID or the HOST_ID
+    fn get_id<I: ResourceId>(&self, key: &str) -> Result<I, Errors> {
+        let i_str = self.get(key)?;
+
+        Ok(I::from(i_str.parse::<i32>()?))
+    }
+
     fn get_vector(&self, key: &str) -> Result<Vector, Errors> {
         let (document, element) = self.get_internal();
 
@@ -1,98 +1,317 @@
 //use crate::client::Response;
-use crate::common::Errors;
+#[cfg(feature = "async")]
+use crate::batch::AsyncBatch;
+use crate::batch::Batch;
+use crate::common::ids::{UserId, VmId, ZoneId};
+use crate::common::operations::ResourceKind;
+use crate::common::response::{FromValue, Response};
+use crate::common::{args_summary, Errors};
+use crate::handle::ResourceHandle;
+use crate::telemetry;
 use crate::user::{UserController, UsersController};
 use crate::vm::VirtualMachineController;
 use crate::vm_pool::VirtualMachinesController;
+use crate::zone::{ZoneController, ZonesController};
+#[cfg(feature = "async")]
+use crate::{
+    handle::AsyncResourceHandle,
+    user::{AsyncUserController, AsyncUsersController},
+    vm::AsyncVirtualMachineController,
+    vm_pool::AsyncVirtualMachinesController,
+    zone::{AsyncZoneController, AsyncZonesController},
+};
 
+use std::sync::Arc;
+use std::time::Instant;
+
+#[cfg(feature = "async")]
+use async_trait::async_trait;
 use serde_xmlrpc::Value;
+#[cfg(feature = "async")]
+use tracing::Instrument;
 
-/// RPCCaller is a trait that a XML-RPC client should satisfy to allow the Roca controller to drive it
-pub trait RPCCaller {
+/// RPCCaller is a trait that a XML-RPC client should satisfy to allow the Roca controller to drive it.
+/// The client is shared by the controllers, it may be used from several threads.
+pub trait RPCCaller: Send + Sync {
     fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors>;
+
+    /// Sends the calls in a single `system.multicall` request and returns the raw response.
+    /// Returns None if the client can't batch calls, they are then sent one by one.
+    fn multicall(&self, _calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        None
+    }
 }
 
-/// The Roca controller allow resource access in a hierachical way
+/// AsyncRPCCaller is the asynchronous counterpart of RPCCaller, it allows the Roca async controller
+/// to drive a non-blocking XML-RPC client
+#[cfg(feature = "async")]
+#[async_trait]
+pub trait AsyncRPCCaller: Send + Sync {
+    async fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors>;
+
+    /// Sends the calls in a single `system.multicall` request and returns the raw response.
+    /// Returns None if the client can't batch calls, they are then sent one by one.
+    async fn multicall(&self, _calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        None
+    }
+}
+
+/// The Roca controller allow resource access in a hierachical way.
+///
+/// The client is shared behind an Arc: cloning the controller is cheap, and the resource
+/// controllers own a clone, so they can be stored, returned or moved to another thread.
 #[derive(Debug)]
 pub struct Controller<C: RPCCaller> {
-    pub client: C,
+    pub client: Arc<C>,
+}
+
+impl<C: RPCCaller> Clone for Controller<C> {
+    fn clone(&self) -> Self {
+        Controller {
+            client: self.client.clone(),
+        }
+    }
 }
 
 impl<C: RPCCaller> Controller<C> {
     pub fn new(client: C) -> Self {
+        Controller {
+            client: Arc::new(client),
+        }
+    }
+
+    /// Builds a controller on a client already shared
+    pub fn from_shared(client: Arc<C>) -> Self {
         Controller { client }
     }
 
-    pub fn parse_id_resp(&self, raw: String) -> Result<i32, Errors> {
-        let result = serde_xmlrpc::response_from_str::<(bool, i32)>(&raw);
-        if let Err(e) = result {
-            return Err(Errors::XMLRPC(e));
+    /// Calls the method and decodes the payload of the response: an ID, a body, unit...
+    pub fn call<T: FromValue>(&self, method: &str, args: Vec<Value>) -> Result<T, Errors> {
+        Ok(self.call_response(method, args)?.payload)
+    }
+
+    /// Calls the method and returns the whole response, for the methods sending extra values
+    pub fn call_response<T: FromValue>(
+        &self,
+        method: &str,
+        args: Vec<Value>,
+    ) -> Result<Response<T>, Errors> {
+        let summary = args_summary(&args);
+        let span = telemetry::call_span(method, &args, &summary);
+        let _entered = span.enter();
+
+        let start = Instant::now();
+        let response = self
+            .client
+            .call(method, args)
+            .and_then(|raw| Response::decode(&raw, method, &summary));
+        telemetry::record_result(&span, start, &response);
+        response
+    }
+
+    /// Returns a batch allowing to send several calls in a single request
+    pub fn batch(&self) -> Batch<C> {
+        Batch::new(self.clone())
+    }
+
+    pub fn user(&self, id: UserId) -> UserController<C> {
+        UserController::<C> {
+            controller: self.clone(),
+            id,
         }
+    }
 
-        let (success, id) = result.unwrap();
-        if success {
-            Ok(id)
-        } else {
-            //TODO: handle last parameter error code
+    pub fn users(&self) -> UsersController<C> {
+        UsersController::<C> {
+            controller: self.clone(),
+        }
+    }
 
-            let result = serde_xmlrpc::response_from_str::<(bool, String)>(&raw);
-            if let Err(e) = result {
-                return Err(Errors::XMLRPC(e));
-            }
-            let err = result.unwrap().1;
+    pub fn virtual_machine(&self, id: VmId) -> VirtualMachineController<C> {
+        VirtualMachineController::<C> {
+            controller: self.clone(),
+            id,
+        }
+    }
 
-            Err(Errors::OpenNebula(err))
+    pub fn virtual_machines(&self) -> VirtualMachinesController<C> {
+        VirtualMachinesController::<C> {
+            controller: self.clone(),
         }
     }
 
-    pub fn parse_body_resp(&self, raw: String) -> Result<String, Errors> {
-        let result = serde_xmlrpc::response_from_str::<(bool, String)>(&raw);
-        if let Err(e) = result {
-            return Err(Errors::XMLRPC(e));
+    pub fn zone(&self, id: ZoneId) -> ZoneController<C> {
+        ZoneController::<C> {
+            controller: self.clone(),
+            id,
         }
+    }
 
-        //TODO: handle last parameter error code
-        let (success, ret) = result.unwrap();
-        if success {
-            Ok(ret)
-        } else {
-            Err(Errors::OpenNebula(ret))
+    pub fn zones(&self) -> ZonesController<C> {
+        ZonesController::<C> {
+            controller: self.clone(),
         }
     }
 
-    pub fn parse_resp(&self, raw: String) -> Result<(), Errors> {
-        let result = serde_xmlrpc::response_from_str::<(bool, String)>(&raw);
-        if let Err(e) = result {
-            return Err(Errors::XMLRPC(e));
+    /// Returns the controller of a resource whose kind is only known at runtime
+    pub fn resource(&self, kind: ResourceKind, id: impl Into<i32>) -> ResourceHandle<C> {
+        ResourceHandle::<C> {
+            controller: self.clone(),
+            kind,
+            id: id.into(),
         }
+    }
+}
 
-        //TODO: handle last parameter error code
-        let (success, ret) = result.unwrap();
-        if success {
-            Ok(())
-        } else {
-            Err(Errors::OpenNebula(ret))
+/// The Roca async controller is the asynchronous counterpart of Controller,
+/// each resource method returns a future
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncController<C: AsyncRPCCaller> {
+    pub client: Arc<C>,
+}
+
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> Clone for AsyncController<C> {
+    fn clone(&self) -> Self {
+        AsyncController {
+            client: self.client.clone(),
         }
     }
+}
 
-    pub fn user(&self, id: i32) -> UserController<C> {
-        UserController::<C> {
-            controller: self,
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncController<C> {
+    pub fn new(client: C) -> Self {
+        AsyncController {
+            client: Arc::new(client),
+        }
+    }
+
+    /// Builds a controller on a client already shared
+    pub fn from_shared(client: Arc<C>) -> Self {
+        AsyncController { client }
+    }
+
+    /// Calls the method and decodes the payload of the response: an ID, a body, unit...
+    pub async fn call<T: FromValue>(&self, method: &str, args: Vec<Value>) -> Result<T, Errors> {
+        Ok(self.call_response(method, args).await?.payload)
+    }
+
+    /// Calls the method and returns the whole response, for the methods sending extra values
+    pub async fn call_response<T: FromValue>(
+        &self,
+        method: &str,
+        args: Vec<Value>,
+    ) -> Result<Response<T>, Errors> {
+        let summary = args_summary(&args);
+        let span = telemetry::call_span(method, &args, &summary);
+
+        let start = Instant::now();
+        let response = self
+            .client
+            .call(method, args)
+            .instrument(span.clone())
+            .await
+            .and_then(|raw| Response::decode(&raw, method, &summary));
+        telemetry::record_result(&span, start, &response);
+        response
+    }
+
+    /// Returns a batch allowing to send several calls in a single request
+    pub fn batch(&self) -> AsyncBatch<C> {
+        AsyncBatch::new(self.clone())
+    }
+
+    pub fn user(&self, id: UserId) -> AsyncUserController<C> {
+        AsyncUserController::<C> {
+            controller: self.clone(),
             id,
         }
     }
 
-    pub fn users(&self) -> UsersController<C> {
-        UsersController::<C> { controller: self }
+    pub fn users(&self) -> AsyncUsersController<C> {
+        AsyncUsersController::<C> {
+            controller: self.clone(),
+        }
     }
 
-    pub fn virtual_machine(&self, id: i32) -> VirtualMachineController<C> {
-        VirtualMachineController::<C> {
-            controller: self,
+    pub fn virtual_machine(&self, id: VmId) -> AsyncVirtualMachineController<C> {
+        AsyncVirtualMachineController::<C> {
+            controller: self.clone(),
             id,
         }
     }
 
-    pub fn virtual_machines(&self) -> VirtualMachinesController<C> {
-        VirtualMachinesController::<C> { controller: self }
+    pub fn virtual_machines(&self) -> AsyncVirtualMachinesController<C> {
+        AsyncVirtualMachinesController::<C> {
+            controller: self.clone(),
+        }
+    }
+
+    pub fn zone(&self, id: ZoneId) -> AsyncZoneController<C> {
+        AsyncZoneController::<C> {
+            controller: self.clone(),
+            id,
+        }
+    }
+
+    pub fn zones(&self) -> AsyncZonesController<C> {
+        AsyncZonesController::<C> {
+            controller: self.clone(),
+        }
+    }
+
+    /// Returns the controller of a resource whose kind is only known at runtime
+    pub fn resource(&self, kind: ResourceKind, id: impl Into<i32>) -> AsyncResourceHandle<C> {
+        AsyncResourceHandle::<C> {
+            controller: self.clone(),
+            kind,
+            id: id.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use super::*;
+    use crate::vm::{Action, VirtualMachineController};
+
+    // counts the calls, oned is unreachable
+    #[derive(Debug, Default)]
+    struct Unreachable {
+        calls: AtomicUsize,
+    }
+
+    impl RPCCaller for Unreachable {
+        fn call(&self, _name: &str, _args: Vec<Value>) -> Result<String, Errors> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Err(Errors::HTTPReq("unreachable".to_string()))
+        }
+    }
+
+    #[test]
+    fn owned_controllers() {
+        let controller = Controller::new(Unreachable::default());
+
+        // the VM controllers outlive the scope of the controller and move to the threads
+        let vms: Vec<VirtualMachineController<Unreachable>> = {
+            let controller = controller.clone();
+            (0..4)
+                .map(|id| controller.virtual_machine(VmId(id)))
+                .collect()
+        };
+        let workers = vms
+            .into_iter()
+            .map(|vm| thread::spawn(move || vm.action(Action::Resume)))
+            .collect::<Vec<_>>();
+        for worker in workers {
+            assert!(worker.join().unwrap().is_err());
+        }
+
+        assert_eq!(controller.client.calls.load(Ordering::SeqCst), 4);
     }
 }
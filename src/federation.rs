@@ -0,0 +1,340 @@
+//! The federation module drives the zones of an OpenNebula federation with a single controller.
+//!
+//! The zones and their endpoints are listed with `one.zonepool.info` on the master zone.
+//! The calls of a zone are sent to its endpoint, except the writes on the resources shared by
+//! the federation (users, groups, ACLs, VDCs...) which are sent to the master zone.
+//!
+//! ```ignore
+//! let federation = Federation::discover(ZoneId(0), master_client, |endpoint| {
+//!     Ok(ClientXMLRPC::new(auth.clone(), endpoint.to_string()))
+//! })?;
+//! let vms = federation.zone(ZoneId(101)).virtual_machines().info()?;
+//! let id = federation.master().users().allocate("user", "password", "core")?;
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+use serde_xmlrpc::Value;
+
+use crate::common::ids::ZoneId;
+use crate::common::methods;
+use crate::common::resource_getters::ResourceGetters;
+use crate::common::Errors;
+#[cfg(feature = "async")]
+use crate::controller::{AsyncController, AsyncRPCCaller};
+use crate::controller::{Controller, RPCCaller};
+
+/// Federation holds a client per zone, the calls are routed by the controller returned by `zone`
+#[derive(Debug)]
+pub struct Federation<C: RPCCaller> {
+    master: ZoneId,
+    zones: BTreeMap<ZoneId, Arc<C>>,
+}
+
+impl<C: RPCCaller> Federation<C> {
+    /// Builds the federation from a client per zone, the master zone should be in the zones
+    pub fn new(master: ZoneId, zones: BTreeMap<ZoneId, C>) -> Result<Self, Errors> {
+        if !zones.contains_key(&master) {
+            return Err(Errors::Roca(format!(
+                "federation: no client for the master zone {}",
+                master
+            )));
+        }
+
+        let zones = zones
+            .into_iter()
+            .map(|(id, client)| (id, Arc::new(client)))
+            .collect();
+        Ok(Federation { master, zones })
+    }
+
+    /// Lists the zones on the master zone, then connects to the endpoint of each other zone
+    pub fn discover<F>(master: ZoneId, client: C, connect: F) -> Result<Self, Errors>
+    where
+        F: Fn(&str) -> Result<C, Errors>,
+    {
+        let controller = Controller::new(client);
+
+        let mut zones = BTreeMap::new();
+        for zone in controller.zones().info()? {
+            let id = zone.id()?;
+            if id != master {
+                zones.insert(id, Arc::new(connect(&zone.endpoint()?)?));
+            }
+        }
+        zones.insert(master, controller.client);
+
+        Ok(Federation { master, zones })
+    }
+
+    pub fn master_id(&self) -> ZoneId {
+        self.master
+    }
+
+    pub fn zone_ids(&self) -> Vec<ZoneId> {
+        self.zones.keys().copied().collect()
+    }
+
+    /// Returns the controller of the zone, the calls fail if the zone is unknown
+    pub fn zone(&self, id: ZoneId) -> Controller<ZoneCaller<C>> {
+        Controller::new(ZoneCaller {
+            id,
+            zone: self.zones.get(&id).cloned(),
+            master: self.zones[&self.master].clone(),
+        })
+    }
+
+    /// Returns the controller of the master zone
+    pub fn master(&self) -> Controller<ZoneCaller<C>> {
+        self.zone(self.master)
+    }
+}
+
+/// Tells if the call should be sent to the master zone instead of the local zone
+pub fn goes_to_master(method: &str) -> bool {
+    methods::is_federated(method) && !methods::is_read_only(method)
+}
+
+fn unknown_zone(id: ZoneId) -> Errors {
+    Errors::Roca(format!("federation: unknown zone {}", id))
+}
+
+/// ZoneCaller routes the calls of a zone to the zone or to the master zone
+#[derive(Debug)]
+pub struct ZoneCaller<C: RPCCaller> {
+    id: ZoneId,
+    zone: Option<Arc<C>>,
+    master: Arc<C>,
+}
+
+impl<C: RPCCaller> ZoneCaller<C> {
+    pub fn zone_id(&self) -> ZoneId {
+        self.id
+    }
+
+    fn route(&self, method: &str) -> Result<&C, Errors> {
+        match &self.zone {
+            _ if goes_to_master(method) => Ok(&self.master),
+            Some(zone) => Ok(zone),
+            None => Err(unknown_zone(self.id)),
+        }
+    }
+}
+
+impl<C: RPCCaller> RPCCaller for ZoneCaller<C> {
+    fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        self.route(name)?.call(name, args)
+    }
+
+    // a batch mixing both zones is sent call by call
+    fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        let to_master = calls
+            .iter()
+            .filter(|(name, _)| goes_to_master(name))
+            .count();
+
+        if to_master == calls.len() {
+            self.master.multicall(calls)
+        } else if to_master == 0 {
+            match &self.zone {
+                Some(zone) => zone.multicall(calls),
+                None => Some(Err(unknown_zone(self.id))),
+            }
+        } else {
+            None
+        }
+    }
+}
+
+/// AsyncFederation is the asynchronous counterpart of Federation
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncFederation<C: AsyncRPCCaller> {
+    master: ZoneId,
+    zones: BTreeMap<ZoneId, Arc<C>>,
+}
+
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncFederation<C> {
+    /// Builds the federation from a client per zone, the master zone should be in the zones
+    pub fn new(master: ZoneId, zones: BTreeMap<ZoneId, C>) -> Result<Self, Errors> {
+        if !zones.contains_key(&master) {
+            return Err(Errors::Roca(format!(
+                "federation: no client for the master zone {}",
+                master
+            )));
+        }
+
+        let zones = zones
+            .into_iter()
+            .map(|(id, client)| (id, Arc::new(client)))
+            .collect();
+        Ok(AsyncFederation { master, zones })
+    }
+
+    /// Lists the zones on the master zone, then connects to the endpoint of each other zone
+    pub async fn discover<F>(master: ZoneId, client: C, connect: F) -> Result<Self, Errors>
+    where
+        F: Fn(&str) -> Result<C, Errors>,
+    {
+        let controller = AsyncController::new(client);
+
+        let mut zones = BTreeMap::new();
+        for zone in controller.zones().info().await? {
+            let id = zone.id()?;
+            if id != master {
+                zones.insert(id, Arc::new(connect(&zone.endpoint()?)?));
+            }
+        }
+        zones.insert(master, controller.client);
+
+        Ok(AsyncFederation { master, zones })
+    }
+
+    pub fn master_id(&self) -> ZoneId {
+        self.master
+    }
+
+    pub fn zone_ids(&self) -> Vec<ZoneId> {
+        self.zones.keys().copied().collect()
+    }
+
+    /// Returns the controller of the zone, the calls fail if the zone is unknown
+    pub fn zone(&self, id: ZoneId) -> AsyncController<AsyncZoneCaller<C>> {
+        AsyncController::new(AsyncZoneCaller {
+            id,
+            zone: self.zones.get(&id).cloned(),
+            master: self.zones[&self.master].clone(),
+        })
+    }
+
+    /// Returns the controller of the master zone
+    pub fn master(&self) -> AsyncController<AsyncZoneCaller<C>> {
+        self.zone(self.master)
+    }
+}
+
+/// AsyncZoneCaller is the asynchronous counterpart of ZoneCaller
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncZoneCaller<C: AsyncRPCCaller> {
+    id: ZoneId,
+    zone: Option<Arc<C>>,
+    master: Arc<C>,
+}
+
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncZoneCaller<C> {
+    pub fn zone_id(&self) -> ZoneId {
+        self.id
+    }
+
+    fn route(&self, method: &str) -> Result<&C, Errors> {
+        match &self.zone {
+            _ if goes_to_master(method) => Ok(&self.master),
+            Some(zone) => Ok(zone),
+            None => Err(unknown_zone(self.id)),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl<C: AsyncRPCCaller> AsyncRPCCaller for AsyncZoneCaller<C> {
+    async fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        self.route(name)?.call(name, args).await
+    }
+
+    async fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        let to_master = calls
+            .iter()
+            .filter(|(name, _)| goes_to_master(name))
+            .count();
+
+        if to_master == calls.len() {
+            self.master.multicall(calls).await
+        } else if to_master == 0 {
+            match &self.zone {
+                Some(zone) => zone.multicall(calls).await,
+                None => Some(Err(unknown_zone(self.id))),
+            }
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::fake::FakeCaller;
+
+    // answers the name of the zone
+    fn zone_client(name: &'static str) -> FakeCaller {
+        FakeCaller::new(move |_, _| Ok(name.to_string()))
+    }
+
+    fn federation() -> Federation<FakeCaller> {
+        let mut zones = BTreeMap::new();
+        zones.insert(ZoneId(0), zone_client("master"));
+        zones.insert(ZoneId(101), zone_client("slave"));
+        Federation::new(ZoneId(0), zones).unwrap()
+    }
+
+    #[test]
+    fn federation_routing() {
+        let federation = federation();
+        assert_eq!(federation.zone_ids(), vec![ZoneId(0), ZoneId(101)]);
+
+        let zone = federation.zone(ZoneId(101));
+        assert_eq!(
+            zone.client.call("one.vmpool.info", vec![]).unwrap(),
+            "slave"
+        );
+        assert_eq!(
+            zone.client.call("one.vm.allocate", vec![]).unwrap(),
+            "slave"
+        );
+        assert_eq!(
+            zone.client.call("one.userpool.info", vec![]).unwrap(),
+            "slave"
+        );
+        assert_eq!(
+            zone.client.call("one.user.allocate", vec![]).unwrap(),
+            "master"
+        );
+        assert_eq!(
+            zone.client.call("one.acl.addrule", vec![]).unwrap(),
+            "master"
+        );
+
+        // the mixed batches are sent call by call
+        let calls = vec![
+            ("one.vm.action".to_string(), vec![]),
+            ("one.user.chgrp".to_string(), vec![]),
+        ];
+        assert!(zone.client.multicall(&calls).is_none());
+
+        assert!(federation
+            .zone(ZoneId(102))
+            .client
+            .call("one.vmpool.info", vec![])
+            .is_err());
+        assert_eq!(
+            federation
+                .zone(ZoneId(102))
+                .client
+                .call("one.user.passwd", vec![])
+                .unwrap(),
+            "master"
+        );
+        assert_eq!(federation.master().client.zone_id(), ZoneId(0));
+
+        let mut zones = BTreeMap::new();
+        zones.insert(ZoneId(101), zone_client("slave"));
+        assert!(Federation::new(ZoneId(0), zones).is_err());
+    }
+}
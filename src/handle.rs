@@ -0,0 +1,164 @@
+//! The handle module allows to run the shared operations on a resource whose kind is only known
+//! at runtime, i.e. read from a configuration file.
+//!
+//! ```ignore
+//! let image = controller.resource(ResourceKind::Image, ImageId(5));
+//! image.chown(Change::To(UserId(2)), Change::Unchanged)?;
+//! println!("{}", image.info()?.name()?);
+//! ```
+//!
+//! The handle implements all the operations: the ones not supported by the kind of resource,
+//! i.e. `one.user.rename`, fail with `Errors::Roca` without calling OpenNebula,
+//! see `ResourceKind::supports`.
+
+#[cfg(feature = "async")]
+use crate::common::operations::{
+    AsyncChmod, AsyncChown, AsyncDelete, AsyncInfo, AsyncLock, AsyncRename,
+    AsyncResourceController, AsyncUpdate,
+};
+use crate::common::operations::{
+    Chmod, Chown, Delete, Info, Lock, Rename, ResourceController, ResourceKind, Update,
+};
+use crate::common::xml::resource::Resource;
+#[cfg(feature = "async")]
+use crate::controller::{AsyncController, AsyncRPCCaller};
+use crate::controller::{Controller, RPCCaller};
+
+/// ResourceHandle is the controller of a resource of any kind
+#[derive(Debug)]
+pub struct ResourceHandle<C: RPCCaller> {
+    pub controller: Controller<C>,
+    pub kind: ResourceKind,
+    pub id: i32,
+}
+
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncResourceHandle<C: AsyncRPCCaller> {
+    pub controller: AsyncController<C>,
+    pub kind: ResourceKind,
+    pub id: i32,
+}
+
+impl<C: RPCCaller> ResourceController for ResourceHandle<C> {
+    type Caller = C;
+
+    fn controller(&self) -> &Controller<C> {
+        &self.controller
+    }
+    fn kind(&self) -> ResourceKind {
+        self.kind
+    }
+    fn resource_id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl<C: RPCCaller> Info for ResourceHandle<C> {
+    type Output = Resource;
+}
+impl<C: RPCCaller> Chown for ResourceHandle<C> {}
+impl<C: RPCCaller> Chmod for ResourceHandle<C> {}
+impl<C: RPCCaller> Rename for ResourceHandle<C> {}
+impl<C: RPCCaller> Delete for ResourceHandle<C> {}
+impl<C: RPCCaller> Update for ResourceHandle<C> {}
+impl<C: RPCCaller> Lock for ResourceHandle<C> {}
+
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncResourceController for AsyncResourceHandle<C> {
+    type Caller = C;
+
+    fn controller(&self) -> &AsyncController<C> {
+        &self.controller
+    }
+    fn kind(&self) -> ResourceKind {
+        self.kind
+    }
+    fn resource_id(&self) -> i32 {
+        self.id
+    }
+}
+
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncInfo for AsyncResourceHandle<C> {
+    type Output = Resource;
+}
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncChown for AsyncResourceHandle<C> {}
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncChmod for AsyncResourceHandle<C> {}
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncRename for AsyncResourceHandle<C> {}
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncDelete for AsyncResourceHandle<C> {}
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncUpdate for AsyncResourceHandle<C> {}
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncLock for AsyncResourceHandle<C> {}
+
+#[cfg(test)]
+mod test {
+    use serde_xmlrpc::Value;
+
+    use super::*;
+    use crate::common::ids::{Change, GroupId, ImageId, VmId};
+    use crate::common::parameters::{LockLevel, UpdateType};
+    use crate::common::Errors;
+    use crate::testing::fake::FakeCaller;
+
+    fn retag<R: Update>(resource: &R) -> Result<(), Errors> {
+        resource.update("TEAM = \"core\"", UpdateType::Merge)
+    }
+
+    #[test]
+    fn resource_handle() {
+        // records the calls, without answering them
+        let controller = Controller::new(FakeCaller::new(|_, _| {
+            Err(Errors::HTTPReq("unreachable".to_string()))
+        }));
+
+        let image = controller.resource(ResourceKind::Image, ImageId(5));
+        assert!(image
+            .chown(Change::Unchanged, Change::To(GroupId(105)))
+            .is_err());
+        assert!(image.lock(LockLevel::Manage, false).is_err());
+        assert!(retag(&image).is_err());
+        let vm = controller.virtual_machine(VmId(12));
+        assert!(retag(&vm).is_err());
+        assert!(vm.rename("web").is_err());
+
+        // not sent to OpenNebula
+        let user = controller.resource(ResourceKind::User, 3);
+        assert!(matches!(user.rename("bob"), Err(Errors::Roca(_))));
+        assert!(matches!(
+            user.lock(LockLevel::Use, true),
+            Err(Errors::Roca(_))
+        ));
+
+        assert_eq!(
+            controller.client.calls(),
+            vec![
+                (
+                    "one.image.chown".to_string(),
+                    vec![Value::Int(5), Value::Int(-1), Value::Int(105)]
+                ),
+                (
+                    "one.image.lock".to_string(),
+                    vec![Value::Int(5), Value::Int(2), Value::Bool(false)]
+                ),
+                (
+                    "one.image.update".to_string(),
+                    vec![Value::Int(5), "TEAM = \"core\"".into(), Value::Int(1)]
+                ),
+                (
+                    "one.vm.update".to_string(),
+                    vec![Value::Int(12), "TEAM = \"core\"".into(), Value::Int(1)]
+                ),
+                (
+                    "one.vm.rename".to_string(),
+                    vec![Value::Int(12), "web".into()]
+                ),
+            ]
+        );
+    }
+}
@@ -4,21 +4,43 @@
 //!
 //! [OpenNebula XML-RPC API]: http://docs.opennebula.org/stable/integration/system_interfaces/api.html
 
+pub mod batch;
 pub mod client;
 pub mod common;
 pub mod controller;
+pub mod federation;
+pub mod handle;
+pub mod middleware;
+#[cfg(any(test, feature = "testing"))]
+pub mod sim;
+pub mod telemetry;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod user;
 pub mod vm;
 pub mod vm_pool;
+pub mod zone;
 
 pub mod prelude {
+    #[cfg(feature = "async")]
+    pub use crate::client::AsyncClientXMLRPC;
     pub use crate::client::ClientXMLRPC;
+    pub use crate::common::ids::*;
+    #[cfg(feature = "async")]
+    pub use crate::common::operations::{
+        AsyncChmod, AsyncChown, AsyncDelete, AsyncInfo, AsyncLock, AsyncRename, AsyncUpdate,
+    };
+    pub use crate::common::operations::{
+        Chmod, Chown, Delete, Info, Lock, Rename, ResourceKind, Update,
+    };
     pub use crate::common::parameters;
     pub use crate::common::resource_getters::{
         GetGroup, GetOwner, GetPermissions, ResourceGetters,
     };
     pub use crate::common::template::builder as template;
     pub use crate::common::template::elements::Vector;
+    #[cfg(feature = "async")]
+    pub use crate::controller::AsyncController;
     pub use crate::controller::Controller;
 
     pub use crate::common::xml::resource::Resource;
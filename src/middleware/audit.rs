@@ -0,0 +1,462 @@
+//! The audit module wraps a RPCCaller to record the changes made to OpenNebula.
+//!
+//! Each mutating call (allocate, delete, action, chmod, update...) appends a JSON line to a
+//! writer, with the time, the actor, the method, the resource ID, the arguments and the outcome:
+//!
+//! ```text
+//! {"timestamp":"2024-05-02T09:12:44.051Z","actor":"deployer","method":"one.vm.action","kind":"mutate","resource_id":12,"args":["terminate",12],"outcome":"success"}
+//! ```
+//!
+//! The passwords and tokens are redacted from the arguments: the password of `one.user.allocate`,
+//! `one.user.passwd`... and the template attributes like `PASSWORD` or `SSH_PRIVATE_KEY`.
+
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+use serde_json::{json, Value as JsonValue};
+use serde_xmlrpc::Value;
+
+use crate::common::methods::{self, MethodKind};
+use crate::common::Errors;
+#[cfg(feature = "async")]
+use crate::controller::AsyncRPCCaller;
+use crate::controller::RPCCaller;
+
+const REDACTED: &str = "<redacted>";
+
+// arguments holding a password or a token, by position
+const SECRET_ARGS: [(&str, usize); 4] = [
+    ("one.user.allocate", 1),
+    ("one.user.passwd", 1),
+    ("one.user.chauth", 2),
+    ("one.user.login", 1),
+];
+
+// template attributes whose name contains one of these words are redacted
+const SECRET_ATTRIBUTES: [&str; 5] = ["PASSWORD", "PASSWD", "SECRET", "TOKEN", "PRIVATE_KEY"];
+
+/// Audit wraps any RPCCaller (or AsyncRPCCaller) and appends a JSON line per mutating call to
+/// the writer. The read only calls are recorded too with `with_reads`.
+///
+/// A failed write doesn't fail the call, the change is already done: it's reported with a
+/// `tracing` error event.
+#[derive(Debug)]
+pub struct Audit<C, W: Write> {
+    inner: C,
+    writer: Mutex<W>,
+    actor: String,
+    reads: bool,
+}
+
+impl<C, W: Write> Audit<C, W> {
+    /// The actor is recorded with each call, i.e. the name of the service or of the end user
+    pub fn new(inner: C, writer: W, actor: &str) -> Self {
+        Audit {
+            inner,
+            writer: Mutex::new(writer),
+            actor: actor.to_string(),
+            reads: false,
+        }
+    }
+
+    /// Records the read only calls too
+    pub fn with_reads(mut self, reads: bool) -> Self {
+        self.reads = reads;
+        self
+    }
+
+    pub fn actor(&self) -> &str {
+        &self.actor
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    fn audited(&self, method: &str) -> bool {
+        self.reads || !methods::is_read_only(method)
+    }
+
+    fn record(&self, method: &str, args: &[Value], result: &Result<String, Errors>) {
+        let line = audit_record(SystemTime::now(), &self.actor, method, args, result);
+
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Err(e) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+            tracing::error!(method, error = %e, "failed to write the audit record");
+        }
+    }
+
+    // a batch with audited calls is sent call by call, each call gets its own outcome
+    fn batchable(&self, calls: &[(String, Vec<Value>)]) -> bool {
+        !calls.iter().any(|(method, _)| self.audited(method))
+    }
+}
+
+impl<C: RPCCaller, W: Write + Send> RPCCaller for Audit<C, W> {
+    fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        if !self.audited(name) {
+            return self.inner.call(name, args);
+        }
+
+        let recorded = args.clone();
+        let result = self.inner.call(name, args);
+        self.record(name, &recorded, &result);
+        result
+    }
+
+    fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        if self.batchable(calls) {
+            self.inner.multicall(calls)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl<C: AsyncRPCCaller, W: Write + Send> AsyncRPCCaller for Audit<C, W> {
+    async fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        if !self.audited(name) {
+            return self.inner.call(name, args).await;
+        }
+
+        let recorded = args.clone();
+        let result = self.inner.call(name, args).await;
+        self.record(name, &recorded, &result);
+        result
+    }
+
+    async fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        if self.batchable(calls) {
+            self.inner.multicall(calls).await
+        } else {
+            None
+        }
+    }
+}
+
+/// Builds the JSON line recording the call, the secrets are redacted from the arguments
+pub fn audit_record(
+    time: SystemTime,
+    actor: &str,
+    method: &str,
+    args: &[Value],
+    result: &Result<String, Errors>,
+) -> String {
+    let kind = match methods::kind(method) {
+        MethodKind::Read => "read",
+        MethodKind::Mutate => "mutate",
+    };
+
+    let mut record = json!({
+        "timestamp": humantime::format_rfc3339_millis(time).to_string(),
+        "actor": actor,
+        "method": method,
+        "kind": kind,
+        "resource_id": methods::resource_id(method, args),
+        "args": redact_args(method, args).iter().map(json_value).collect::<Vec<_>>(),
+    });
+    match result {
+        Ok(_) => record["outcome"] = json!("success"),
+        Err(e) => {
+            record["outcome"] = json!("failure");
+            record["error_code"] = json!(e.code().map(|code| code.to_string()));
+            record["error"] = json!(e.to_string());
+        }
+    }
+    record.to_string()
+}
+
+/// Returns the arguments with the passwords, tokens and secret template attributes redacted
+pub fn redact_args(method: &str, args: &[Value]) -> Vec<Value> {
+    args.iter()
+        .enumerate()
+        .map(|(position, arg)| {
+            if SECRET_ARGS.contains(&(method, position)) {
+                return Value::String(REDACTED.to_string());
+            }
+            redact_value(arg)
+        })
+        .collect()
+}
+
+fn redact_value(value: &Value) -> Value {
+    match value {
+        Value::String(s) => Value::String(redact_template(s)),
+        Value::Array(values) => Value::Array(values.iter().map(redact_value).collect()),
+        Value::Struct(members) => Value::Struct(
+            members
+                .iter()
+                .map(|(name, value)| {
+                    let value = if is_secret_attribute(name) {
+                        Value::String(REDACTED.to_string())
+                    } else {
+                        redact_value(value)
+                    };
+                    (name.clone(), value)
+                })
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+fn is_secret_attribute(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    SECRET_ATTRIBUTES.iter().any(|word| name.contains(word))
+}
+
+/// Redacts the values of the secret attributes of a template, i.e. `PASSWORD = "..."`,
+/// including the attributes of the vectors like `CONTEXT = [ PASSWORD = "..." ]`.
+/// The XML templates are supported too, i.e. `<PASSWORD><![CDATA[...]]></PASSWORD>`
+pub fn redact_template(template: &str) -> String {
+    if template.trim_start().starts_with('<') {
+        return redact_xml_template(template);
+    }
+
+    let bytes = template.as_bytes();
+    let is_name = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+
+    let mut redacted = String::with_capacity(template.len());
+    let mut copied = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if !is_name(bytes[i]) {
+            i += 1;
+            continue;
+        }
+
+        // attribute name followed by '='
+        let start = i;
+        while i < bytes.len() && is_name(bytes[i]) {
+            i += 1;
+        }
+        let name = &template[start..i];
+        let mut j = i;
+        while j < bytes.len() && (bytes[j] == b' ' || bytes[j] == b'\t') {
+            j += 1;
+        }
+        if j >= bytes.len() || bytes[j] != b'=' || !is_secret_attribute(name) {
+            continue;
+        }
+        j += 1;
+        while j < bytes.len() && (bytes[j] == b' ' || bytes[j] == b'\t') {
+            j += 1;
+        }
+
+        // the value is quoted or ends with the line, the vector or the attribute
+        let (value_start, value_end) = if j < bytes.len() && bytes[j] == b'"' {
+            let mut k = j + 1;
+            while k < bytes.len() && bytes[k] != b'"' {
+                k += if bytes[k] == b'\\' { 2 } else { 1 };
+            }
+            (j + 1, k.min(bytes.len()))
+        } else {
+            let mut k = j;
+            while k < bytes.len() && !matches!(bytes[k], b'\n' | b',' | b']') {
+                k += 1;
+            }
+            while k > j && bytes[k - 1].is_ascii_whitespace() {
+                k -= 1;
+            }
+            (j, k)
+        };
+
+        redacted.push_str(&template[copied..value_start]);
+        redacted.push_str(REDACTED);
+        copied = value_end;
+        i = value_end;
+    }
+    redacted.push_str(&template[copied..]);
+    redacted
+}
+
+// blanks the text of the secret elements, CDATA included
+fn redact_xml_template(template: &str) -> String {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | ':');
+
+    let mut redacted = String::with_capacity(template.len());
+    let mut copied = 0;
+    let mut i = 0;
+    while let Some(pos) = template[i..].find('<') {
+        let start = i + pos;
+        let rest = &template[start + 1..];
+
+        // the CDATA sections of the other elements are skipped, they may contain '<'
+        if rest.starts_with("![CDATA[") {
+            i = match rest.find("]]>") {
+                Some(end) => start + 1 + end + 3,
+                None => template.len(),
+            };
+            continue;
+        }
+
+        let name_len = rest.find(|c| !is_name(c)).unwrap_or(rest.len());
+        let tag_end = match rest.find('>') {
+            Some(end) => start + 1 + end,
+            None => break,
+        };
+        let name = &rest[..name_len];
+        if name.is_empty() || template[..tag_end].ends_with('/') || !is_secret_attribute(name) {
+            i = start + 1;
+            continue;
+        }
+
+        let close = format!("</{}>", name);
+        let value_end = match template[tag_end..].find(&close) {
+            Some(end) => tag_end + end,
+            None => template.len(),
+        };
+        redacted.push_str(&template[copied..tag_end + 1]);
+        redacted.push_str(REDACTED);
+        copied = value_end;
+        i = value_end;
+    }
+    redacted.push_str(&template[copied..]);
+    redacted
+}
+
+// the XML-RPC value as JSON, the binary values are only described
+pub(crate) fn json_value(value: &Value) -> JsonValue {
+    match value {
+        Value::Int(v) => json!(v),
+        Value::Int64(v) => json!(v),
+        Value::Bool(v) => json!(v),
+        Value::Double(v) => json!(v),
+        Value::String(v) => json!(v),
+        Value::Array(values) => JsonValue::Array(values.iter().map(json_value).collect()),
+        Value::Struct(members) => JsonValue::Object(
+            members
+                .iter()
+                .map(|(name, value)| (name.clone(), json_value(value)))
+                .collect(),
+        ),
+        Value::Base64(v) => json!(format!("<base64: {} bytes>", v.len())),
+        Value::DateTime(_) => json!("<datetime>"),
+        _ => JsonValue::Null,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use serde_xmlrpc::Value;
+
+    use super::{audit_record, redact_args, redact_template, Audit, REDACTED};
+    use crate::common::{ErrorCode, Errors, OpenNebulaError};
+    use crate::controller::RPCCaller;
+    use crate::testing::fake::FakeCaller;
+
+    // accepts every call but the VM deletes, the batches too
+    fn oned() -> FakeCaller {
+        FakeCaller::new(|name, _| match name {
+            "one.vm.delete" => Err(Errors::OpenNebula(OpenNebulaError {
+                code: ErrorCode::Locked,
+                message: "locked".to_string(),
+                method: name.to_string(),
+                args: String::new(),
+            })),
+            _ => Ok("response".to_string()),
+        })
+        .with_batches()
+    }
+
+    fn lines(client: &Audit<FakeCaller, Vec<u8>>) -> Vec<String> {
+        let writer = client.writer.lock().unwrap();
+        String::from_utf8(writer.clone())
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn audit_mutating_calls() {
+        let client = Audit::new(oned(), Vec::new(), "deployer");
+
+        client.call("one.vm.info", vec![12.into()]).unwrap();
+        client
+            .call("one.vm.action", vec!["terminate".into(), 12.into()])
+            .unwrap();
+        assert!(client.call("one.vm.delete", vec![13.into()]).is_err());
+
+        let records = lines(&client);
+        assert_eq!(records.len(), 2);
+        assert!(records[0].contains("\"actor\":\"deployer\""));
+        assert!(records[0].contains("\"method\":\"one.vm.action\""));
+        assert!(records[0].contains("\"resource_id\":12"));
+        assert!(records[0].contains("\"args\":[\"terminate\",12]"));
+        assert!(records[0].ends_with("\"outcome\":\"success\"}"));
+        assert!(records[1].contains("\"outcome\":\"failure\",\"error_code\":\"LOCKED\""));
+
+        // the batches with mutating calls are sent call by call
+        let reads = vec![("one.vm.info".to_string(), vec![12.into()])];
+        let mixed = vec![
+            ("one.vm.info".to_string(), vec![12.into()]),
+            ("one.vm.rename".to_string(), vec![12.into(), "new".into()]),
+        ];
+        assert!(client.multicall(&reads).is_some());
+        assert!(client.multicall(&mixed).is_none());
+
+        let client = Audit::new(oned(), Vec::new(), "reader").with_reads(true);
+        client.call("one.vm.info", vec![12.into()]).unwrap();
+        assert!(lines(&client)[0].contains("\"kind\":\"read\""));
+    }
+
+    #[test]
+    fn audit_redacted_secrets() {
+        let template = "NAME = \"vm\"\nCONTEXT = [ ROOT_PASSWORD = \"s3cr\\\"et\", TOKEN = YES ]\nPASSWORD=hunter2\nNIC = [ NETWORK = \"private\" ]";
+        assert_eq!(
+            redact_template(template),
+            "NAME = \"vm\"\nCONTEXT = [ ROOT_PASSWORD = \"<redacted>\", TOKEN = <redacted> ]\nPASSWORD=<redacted>\nNIC = [ NETWORK = \"private\" ]"
+        );
+
+        let template = "<TEMPLATE><NAME>vm</NAME><CONTEXT><ROOT_PASSWORD>s3cret</ROOT_PASSWORD>\
+                        <SSH_PUBLIC_KEY><![CDATA[ssh-ed25519 AAAA]]></SSH_PUBLIC_KEY></CONTEXT>\
+                        <PASSWORD><![CDATA[hun<ter>2]]></PASSWORD><TOKEN/></TEMPLATE>";
+        assert_eq!(
+            redact_template(template),
+            "<TEMPLATE><NAME>vm</NAME><CONTEXT><ROOT_PASSWORD><redacted></ROOT_PASSWORD>\
+             <SSH_PUBLIC_KEY><![CDATA[ssh-ed25519 AAAA]]></SSH_PUBLIC_KEY></CONTEXT>\
+             <PASSWORD><redacted></PASSWORD><TOKEN/></TEMPLATE>"
+        );
+        assert_eq!(
+            redact_template("  <SECRET_KEY><![CDATA[a]]></SECRET_KEY>"),
+            "  <SECRET_KEY><redacted></SECRET_KEY>"
+        );
+
+        let args = redact_args(
+            "one.user.allocate",
+            &["alice".into(), "hunter2".into(), "core".into()],
+        );
+        assert_eq!(
+            args,
+            vec!["alice".into(), REDACTED.into(), "core".into()] as Vec<Value>
+        );
+
+        let line = audit_record(
+            UNIX_EPOCH + Duration::from_millis(1_714_641_164_051),
+            "ops \"team\"",
+            "one.vm.update",
+            &[12.into(), "PASSWORD = hunter2".into(), 1.into()],
+            &Ok(String::new()),
+        );
+        assert!(!line.contains("hunter2"));
+        assert_eq!(
+            line,
+            "{\"timestamp\":\"2024-05-02T09:12:44.051Z\",\"actor\":\"ops \\\"team\\\"\",\"method\":\"one.vm.update\",\"kind\":\"mutate\",\"resource_id\":12,\"args\":[12,\"PASSWORD = <redacted>\",1],\"outcome\":\"success\"}"
+        );
+    }
+}
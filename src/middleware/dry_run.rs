@@ -0,0 +1,366 @@
+//! The dry_run module wraps a RPCCaller to plan the changes instead of making them.
+//!
+//! The read only calls are sent to the backend, the mutating calls are recorded in a plan
+//! and answered without reaching oned: the allocations get a synthetic negative ID, the calls
+//! on a resource return its ID, and the default quota updates return empty quotas.
+//!
+//! ```ignore
+//! let controller = Controller::new(DryRun::new(client));
+//! provision(&controller)?;
+//!
+//! let plan = controller.client.plan();
+//! println!("{}", plan);
+//! ```
+//!
+//! The later calls on a synthetic ID (i.e. `one.vm.info` on a planned VM) are still sent to oned,
+//! and fail as the resource doesn't exist.
+//!
+//! The module is built with the `audit` feature, which also serializes the plan as JSON.
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+use serde_json::{json, Value as JsonValue};
+use serde_xmlrpc::Value;
+
+use crate::common::methods;
+use crate::common::Errors;
+#[cfg(feature = "async")]
+use crate::controller::AsyncRPCCaller;
+use crate::controller::RPCCaller;
+use crate::middleware::audit::{json_value, redact_args};
+
+// mutating methods which don't change the resources, they're sent to the backend
+const FORWARDED: [&str; 1] = ["one.user.login"];
+
+// last segment of the methods creating a resource, they return its new ID
+const CREATE_SUFFIXES: [&str; 4] = ["allocate", "clone", "instantiate", "addrule"];
+
+// methods returning a body instead of an ID, and the root of their empty body
+const BODY_METHODS: [(&str, &str); 2] = [
+    ("one.userquota.update", "DEFAULT_USER_QUOTAS"),
+    ("one.groupquota.update", "DEFAULT_GROUP_QUOTAS"),
+];
+
+/// The first synthetic ID, the next ones count down
+pub const FIRST_SYNTHETIC_ID: i32 = -100;
+
+/// A mutating call recorded instead of being sent
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedCall {
+    pub method: String,
+    /// The arguments, with the passwords and secret template attributes redacted
+    pub args: Vec<Value>,
+    /// The ID of the resource changed by the call, None for the allocations
+    /// and the calls on no resource (i.e. `one.userquota.update`)
+    pub resource_id: Option<i32>,
+    /// The ID returned to the caller, synthetic for the allocations, None if no ID is returned
+    pub result_id: Option<i32>,
+}
+
+/// The mutating calls recorded by a DryRun, in order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Plan {
+    pub calls: Vec<PlannedCall>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    /// Serializes the plan as a JSON array, one object per call
+    pub fn to_json(&self) -> String {
+        let calls = self
+            .calls
+            .iter()
+            .map(|call| {
+                json!({
+                    "method": call.method,
+                    "resource_id": call.resource_id,
+                    "args": call.args.iter().map(json_value).collect::<Vec<_>>(),
+                    "result_id": call.result_id,
+                })
+            })
+            .collect::<Vec<_>>();
+        JsonValue::Array(calls).to_string()
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.calls.is_empty() {
+            return writeln!(f, "no changes");
+        }
+
+        for (i, call) in self.calls.iter().enumerate() {
+            let args = call
+                .args
+                .iter()
+                .map(|arg| json_value(arg).to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            write!(f, "{}. {}({})", i + 1, call.method, args)?;
+            match (call.resource_id, call.result_id) {
+                (None, Some(id)) => writeln!(f, " -> new ID {}", id)?,
+                _ => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// DryRun wraps any RPCCaller (or AsyncRPCCaller), sends the read only calls and records the
+/// mutating calls in a plan
+#[derive(Debug)]
+pub struct DryRun<C> {
+    inner: C,
+    plan: Mutex<Plan>,
+    next_id: AtomicI32,
+}
+
+impl<C> DryRun<C> {
+    pub fn new(inner: C) -> Self {
+        DryRun {
+            inner,
+            plan: Mutex::new(Plan::default()),
+            next_id: AtomicI32::new(FIRST_SYNTHETIC_ID),
+        }
+    }
+
+    /// Returns a copy of the plan recorded so far
+    pub fn plan(&self) -> Plan {
+        self.lock().clone()
+    }
+
+    /// Returns the plan recorded so far and starts a new one
+    pub fn take_plan(&self) -> Plan {
+        std::mem::take(&mut *self.lock())
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Plan> {
+        match self.plan.lock() {
+            Ok(plan) => plan,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn planned(method: &str) -> bool {
+        !methods::is_read_only(method) && !FORWARDED.contains(&method)
+    }
+
+    // records the call and builds its synthetic response
+    fn record(&self, method: &str, args: &[Value]) -> String {
+        let creates =
+            matches!(method.rsplit('.').next(), Some(suffix) if CREATE_SUFFIXES.contains(&suffix));
+        let resource_id = match creates {
+            true => None,
+            false => methods::resource_id(method, args),
+        };
+
+        let result_id = match creates {
+            true => Some(self.next_id.fetch_sub(1, Ordering::SeqCst)),
+            false => resource_id,
+        };
+        self.lock().calls.push(PlannedCall {
+            method: method.to_string(),
+            args: redact_args(method, args),
+            resource_id,
+            result_id,
+        });
+
+        match (result_id, BODY_METHODS.iter().find(|(m, _)| *m == method)) {
+            (Some(id), _) => success_response(&format!("<i4>{}</i4>", id)),
+            (None, Some((_, root))) => {
+                success_response(&format!("<string>&lt;{0}&gt;&lt;/{0}&gt;</string>", root))
+            }
+            (None, None) => success_response("<string></string>"),
+        }
+    }
+}
+
+// the (true, payload, 0) response of oned, the payload is a XML-RPC value
+fn success_response(payload: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\"?><methodResponse><params><param><value><array><data>\
+         <value><boolean>1</boolean></value><value>{}</value><value><i4>0</i4></value>\
+         </data></array></value></param></params></methodResponse>",
+        payload
+    )
+}
+
+impl<C: RPCCaller> RPCCaller for DryRun<C> {
+    fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        if Self::planned(name) {
+            return Ok(self.record(name, &args));
+        }
+        self.inner.call(name, args)
+    }
+
+    // the batches are sent call by call, the mutating calls are planned one by one
+    fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        if calls.iter().any(|(method, _)| Self::planned(method)) {
+            return None;
+        }
+        self.inner.multicall(calls)
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl<C: AsyncRPCCaller> AsyncRPCCaller for DryRun<C> {
+    async fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        if Self::planned(name) {
+            return Ok(self.record(name, &args));
+        }
+        self.inner.call(name, args).await
+    }
+
+    async fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        if calls.iter().any(|(method, _)| Self::planned(method)) {
+            return None;
+        }
+        self.inner.multicall(calls).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_xmlrpc::Value;
+
+    use super::DryRun;
+    use crate::common::methods;
+    use crate::common::template::builder as template;
+    use crate::common::template::elements::Vector;
+    use crate::common::xml::shared_getters::BaseGetters;
+    use crate::common::Errors;
+    use crate::controller::{Controller, RPCCaller};
+    use crate::testing::fake::FakeCaller;
+
+    // answers the read calls, fails the others
+    fn oned() -> FakeCaller {
+        FakeCaller::new(|name, _| {
+            if methods::is_read_only(name) || name == "one.user.login" {
+                Ok("response".to_string())
+            } else {
+                Err(Errors::Roca(format!("{} reached oned", name)))
+            }
+        })
+    }
+
+    #[test]
+    fn dry_run_plan() {
+        let client = DryRun::new(oned());
+
+        assert_eq!(
+            client.call("one.vm.info", vec![12.into()]).unwrap(),
+            "response"
+        );
+        assert!(client
+            .call("one.user.login", vec!["alice".into(), "".into()])
+            .is_ok());
+
+        let resp = client
+            .call(
+                "one.vm.allocate",
+                vec![
+                    "NAME = web\nCONTEXT = [ PASSWORD = \"hunter2\" ]".into(),
+                    false.into(),
+                ],
+            )
+            .unwrap();
+        assert!(resp.contains("<i4>-100</i4>"));
+        let resp = client
+            .call("one.vm.chmod", vec![12.into(), 1.into()])
+            .unwrap();
+        assert!(resp.contains("<i4>12</i4>"));
+        client
+            .call(
+                "one.user.allocate",
+                vec!["bob".into(), "secret".into(), "core".into()],
+            )
+            .unwrap();
+
+        let plan = client.plan();
+        assert_eq!(plan.len(), 3);
+        assert_eq!(plan.calls[0].resource_id, None);
+        assert_eq!(plan.calls[1].resource_id, Some(12));
+        assert_eq!(plan.calls[2].result_id, Some(-101));
+        assert_eq!(
+            plan.to_string(),
+            "1. one.vm.allocate(\"NAME = web\\nCONTEXT = [ PASSWORD = \\\"<redacted>\\\" ]\", false) -> new ID -100\n\
+             2. one.vm.chmod(12, 1)\n\
+             3. one.user.allocate(\"bob\", \"<redacted>\", \"core\") -> new ID -101\n"
+        );
+        assert!(plan
+            .to_json()
+            .starts_with("[{\"method\":\"one.vm.allocate\",\"resource_id\":null,\"args\":["));
+        assert!(!plan.to_json().contains("hunter2"));
+
+        client
+            .call(
+                "one.user.update",
+                vec![
+                    7.into(),
+                    "<TEMPLATE><EMAIL>bob@example.com</EMAIL>\
+                     <PASSWORD><![CDATA[hunter2]]></PASSWORD></TEMPLATE>"
+                        .into(),
+                    1.into(),
+                ],
+            )
+            .unwrap();
+        let plan = client.plan();
+        assert_eq!(
+            plan.calls[3].args[1],
+            Value::String(
+                "<TEMPLATE><EMAIL>bob@example.com</EMAIL><PASSWORD><redacted></PASSWORD></TEMPLATE>"
+                    .to_string()
+            )
+        );
+        assert!(!plan.to_string().contains("hunter2"));
+        assert!(!plan.to_json().contains("hunter2"));
+
+        let batch = vec![
+            ("one.vm.info".to_string(), vec![12.into()]),
+            ("one.vm.delete".to_string(), vec![12.into()]),
+        ];
+        assert!(client.multicall(&batch).is_none());
+
+        assert_eq!(client.take_plan().len(), 4);
+        assert!(client.plan().is_empty());
+    }
+
+    #[test]
+    fn dry_run_default_quotas() {
+        let controller = Controller::new(DryRun::new(oned()));
+
+        let mut quota = Vector::new("VM");
+        quota.put_str("CPU", "4");
+        let mut tpl = template::Builder::new();
+        tpl.put_vector(quota);
+        let quotas = controller.users().update_default_quota(tpl);
+        assert!(quotas.is_ok());
+        assert!(quotas.unwrap().get("VM_QUOTA").is_err());
+
+        let plan = controller.client.plan();
+        assert_eq!(plan.calls[0].method, "one.userquota.update");
+        assert_eq!(plan.calls[0].resource_id, None);
+        assert_eq!(plan.calls[0].result_id, None);
+        assert!(!plan.to_string().contains("new ID"));
+    }
+}
@@ -0,0 +1,8 @@
+//! The middleware module provides RPCCaller wrappers adding behavior around any other RPCCaller
+
+#[cfg(feature = "audit")]
+pub mod audit;
+#[cfg(feature = "audit")]
+pub mod dry_run;
+pub mod policy;
+pub mod retry;
@@ -0,0 +1,393 @@
+//! The policy module wraps a RPCCaller to reject the calls breaking the rules of a team,
+//! before they reach oned.
+//!
+//! ```ignore
+//! let client = Policy::new(client)
+//!     .rule(Rule::DenyMethod("one.user.delete".to_string()))
+//!     .rule(Rule::DenyVmAction("terminate-hard".to_string()))
+//!     .rule(Rule::OnlyGroups(ResourceKind::VirtualMachine, vec![GroupId(105)]));
+//! let controller = Controller::new(client);
+//!
+//! // fails with Errors::PolicyDenied, nothing is sent
+//! controller.virtual_machine(VmId(12)).action(Action::TerminateHard)?;
+//! ```
+
+use std::fmt::{Debug, Display};
+use std::sync::Arc;
+
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+use serde_xmlrpc::Value;
+
+use crate::common::ids::GroupId;
+use crate::common::methods;
+use crate::common::operations::ResourceKind;
+use crate::common::response::Response;
+use crate::common::xml::resource::{Resource, XMLDocGetters};
+use crate::common::xml::shared_getters::BaseGetters;
+use crate::common::{args_summary, Errors};
+#[cfg(feature = "async")]
+use crate::controller::AsyncRPCCaller;
+use crate::controller::RPCCaller;
+
+/// Denies the call if the function returns true, it receives the method and the arguments
+pub type Check = Arc<dyn Fn(&str, &[Value]) -> bool + Send + Sync>;
+
+/// A rule of the policy, a call is sent only if no rule denies it
+#[derive(Clone)]
+pub enum Rule {
+    /// Denies all the mutating calls
+    ReadOnly,
+    /// Denies a method, or the methods starting with a prefix ending with '*', i.e. "one.user.*"
+    DenyMethod(String),
+    /// Denies an action of `one.vm.action`, i.e. "terminate-hard"
+    DenyVmAction(String),
+    /// Only the resources of the kind belonging to these groups may be
+    /// modified, and they may only be given to these groups (chown, user chgrp and addgroup).
+    /// The group of the resource is read with an extra info call.
+    OnlyGroups(ResourceKind, Vec<GroupId>),
+    /// A named custom rule
+    Custom(String, Check),
+}
+
+impl Debug for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Rule({})", self)
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rule::ReadOnly => f.write_str("read only"),
+            Rule::DenyMethod(pattern) => write!(f, "deny {}", pattern),
+            Rule::DenyVmAction(action) => write!(f, "deny the VM action {}", action),
+            Rule::OnlyGroups(kind, groups) => {
+                let groups: Vec<String> = groups.iter().map(|gid| gid.to_string()).collect();
+                write!(f, "only the {} of the groups {}", kind, groups.join(", "))
+            }
+            Rule::Custom(name, _) => f.write_str(name),
+        }
+    }
+}
+
+impl Rule {
+    // tells if the rule denies the call, without looking at the resource
+    fn denies(&self, method: &str, args: &[Value]) -> bool {
+        match self {
+            Rule::ReadOnly => !methods::is_read_only(method),
+            Rule::DenyMethod(pattern) => match pattern.strip_suffix('*') {
+                Some(prefix) => method.starts_with(prefix),
+                None => pattern == method,
+            },
+            Rule::DenyVmAction(action) => {
+                method == "one.vm.action"
+                    && matches!(args.first(), Some(Value::String(a)) if a == action)
+            }
+            Rule::OnlyGroups(kind, groups) => {
+                restricts(*kind, method)
+                    && matches!(target_group(method, args), Some(gid) if !groups.contains(&gid))
+            }
+            Rule::Custom(_, check) => check(method, args),
+        }
+    }
+
+    // the info method and the ID of the resource whose group should be checked
+    fn lookup(&self, method: &str, args: &[Value]) -> Option<(String, i32)> {
+        match self {
+            Rule::OnlyGroups(kind, _) if restricts(*kind, method) => {
+                let id = methods::resource_id(method, args)?;
+                Some((kind.method("info"), id))
+            }
+            _ => None,
+        }
+    }
+}
+
+// the mutating methods of a kind of resource
+fn restricts(kind: ResourceKind, method: &str) -> bool {
+    let prefix = format!("one.{}.", kind.prefix());
+    method.starts_with(&prefix) && !methods::is_read_only(method)
+}
+
+// the group the resource is given to: the new group of a chown (-1 keeps the group),
+// or the group of a `one.user.chgrp` or `one.user.addgroup`
+fn target_group(method: &str, args: &[Value]) -> Option<GroupId> {
+    let position = match method.rsplit('.').next()? {
+        "chown" => 2,
+        "chgrp" | "addgroup" => 1,
+        _ => return None,
+    };
+    match args.get(position) {
+        Some(Value::Int(gid)) if *gid != -1 => Some(GroupId(*gid)),
+        _ => None,
+    }
+}
+
+fn denied(method: &str, rule: &Rule) -> Errors {
+    Errors::PolicyDenied(format!("{} denied by the rule: {}", method, rule))
+}
+
+/// Returns the group of the resource from the raw response of its info method
+fn resource_group(raw: &str, method: &str, id: i32) -> Result<GroupId, Errors> {
+    struct Info(Resource);
+
+    impl XMLDocGetters for Info {
+        fn get_internal(&self) -> (&xml_doc::Document, &xml_doc::Element) {
+            (&self.0.document, &self.0.root)
+        }
+    }
+
+    let body = Response::<String>::decode(raw, method, &args_summary(&[id.into()]))?.payload;
+    let info = Info(Resource::try_from(body.as_str())?);
+    Ok(GroupId(info.get_i64("GID")? as i32))
+}
+
+/// Policy wraps any RPCCaller (or AsyncRPCCaller) and fails with `Errors::PolicyDenied`
+/// the calls denied by one of its rules, without sending them
+#[derive(Debug)]
+pub struct Policy<C> {
+    inner: C,
+    rules: Vec<Rule>,
+}
+
+impl<C> Policy<C> {
+    pub fn new(inner: C) -> Self {
+        Policy {
+            inner,
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Checks the call against the rules which don't need to read the resource,
+    /// returns the resources whose group should be checked
+    fn check_call(
+        &self,
+        method: &str,
+        args: &[Value],
+    ) -> Result<Vec<(&Rule, String, i32)>, Errors> {
+        if let Some(rule) = self.rules.iter().find(|rule| rule.denies(method, args)) {
+            return Err(denied(method, rule));
+        }
+
+        Ok(self
+            .rules
+            .iter()
+            .filter_map(|rule| {
+                let (info, id) = rule.lookup(method, args)?;
+                Some((rule, info, id))
+            })
+            .collect())
+    }
+
+    fn check_group(&self, method: &str, rule: &Rule, group: GroupId) -> Result<(), Errors> {
+        match rule {
+            Rule::OnlyGroups(_, groups) if !groups.contains(&group) => Err(denied(method, rule)),
+            _ => Ok(()),
+        }
+    }
+
+    // a batch is sent as is only if each call is allowed without reading the resources,
+    // otherwise it's sent call by call and each call is checked on its own
+    fn batchable(&self, calls: &[(String, Vec<Value>)]) -> bool {
+        calls.iter().all(|(method, args)| {
+            matches!(self.check_call(method, args), Ok(lookups) if lookups.is_empty())
+        })
+    }
+}
+
+impl<C: RPCCaller> Policy<C> {
+    /// Checks the call against the rules, without sending it
+    pub fn check(&self, method: &str, args: &[Value]) -> Result<(), Errors> {
+        for (rule, info, id) in self.check_call(method, args)? {
+            let raw = self.inner.call(&info, vec![id.into()])?;
+            self.check_group(method, rule, resource_group(&raw, &info, id)?)?;
+        }
+        Ok(())
+    }
+}
+
+impl<C: RPCCaller> RPCCaller for Policy<C> {
+    fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        self.check(name, &args)?;
+        self.inner.call(name, args)
+    }
+
+    fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        if self.batchable(calls) {
+            self.inner.multicall(calls)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> Policy<C> {
+    /// Checks the call against the rules, without sending it
+    pub async fn check_async(&self, method: &str, args: &[Value]) -> Result<(), Errors> {
+        for (rule, info, id) in self.check_call(method, args)? {
+            let raw = self.inner.call(&info, vec![id.into()]).await?;
+            self.check_group(method, rule, resource_group(&raw, &info, id)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl<C: AsyncRPCCaller> AsyncRPCCaller for Policy<C> {
+    async fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        self.check_async(name, &args).await?;
+        self.inner.call(name, args).await
+    }
+
+    async fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        if self.batchable(calls) {
+            self.inner.multicall(calls).await
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use serde_xmlrpc::Value;
+
+    use super::{Policy, Rule};
+    use crate::common::ids::GroupId;
+    use crate::common::operations::ResourceKind;
+    use crate::common::Errors;
+    use crate::controller::RPCCaller;
+    use crate::testing::fake::FakeCaller;
+    use crate::testing::xmlrpc;
+
+    // accepts every call, the batches too, the VMs belong to the group 105
+    fn oned() -> FakeCaller {
+        FakeCaller::new(|name, args| match (name, args.first()) {
+            ("one.vm.info", Some(Value::Int(id))) => Ok(xmlrpc::success_response(format!(
+                "<VM><ID>{}</ID><GID>105</GID></VM>",
+                id
+            ))),
+            _ => Ok("response".to_string()),
+        })
+        .with_batches()
+    }
+
+    #[test]
+    fn policy_rules() {
+        let client = Policy::new(oned())
+            .rule(Rule::DenyMethod("one.user.delete".to_string()))
+            .rule(Rule::DenyMethod("one.acl.*".to_string()))
+            .rule(Rule::DenyVmAction("terminate-hard".to_string()))
+            .rule(Rule::Custom(
+                "no VM above 100".to_string(),
+                Arc::new(|method, args| {
+                    method.starts_with("one.vm.")
+                        && matches!(args.first(), Some(Value::Int(id)) if *id > 100)
+                }),
+            ));
+
+        let resp = client.call("one.user.delete", vec![12.into()]);
+        assert!(matches!(resp, Err(Errors::PolicyDenied(_))));
+        assert!(client.call("one.acl.addrule", vec![]).is_err());
+
+        let resp = client.call("one.vm.action", vec!["terminate-hard".into(), 12.into()]);
+        assert!(matches!(resp, Err(Errors::PolicyDenied(msg)) if msg.contains("terminate-hard")));
+        assert!(client.call("one.vm.info", vec![101.into()]).is_err());
+
+        assert!(client
+            .call("one.vm.action", vec!["terminate".into(), 12.into()])
+            .is_ok());
+        assert!(client.call("one.user.info", vec![12.into()]).is_ok());
+        assert_eq!(
+            client.inner().methods(),
+            vec!["one.vm.action", "one.user.info"]
+        );
+
+        // the batches with a denied call are sent call by call
+        let allowed = vec![("one.vm.info".to_string(), vec![12.into()])];
+        let denied = vec![
+            ("one.vm.info".to_string(), vec![12.into()]),
+            ("one.user.delete".to_string(), vec![12.into()]),
+        ];
+        assert!(client.multicall(&allowed).is_some());
+        assert!(client.multicall(&denied).is_none());
+    }
+
+    #[test]
+    fn policy_read_only_and_groups() {
+        let client = Policy::new(oned()).rule(Rule::ReadOnly);
+        assert!(client.call("one.vmpool.info", vec![(-2).into()]).is_ok());
+        assert!(client.call("one.vm.allocate", vec!["".into()]).is_err());
+
+        // the chown to another group is denied without reading the VM
+        let client = Policy::new(oned()).rule(Rule::OnlyGroups(
+            ResourceKind::VirtualMachine,
+            vec![GroupId(105)],
+        ));
+        assert!(client.check("one.vm.info", &[12.into()]).is_ok());
+        assert!(client.check("one.user.delete", &[12.into()]).is_ok());
+        assert!(client
+            .check("one.vm.chown", &[12.into(), (-1).into(), 0.into()])
+            .is_err());
+        assert!(client.inner().methods().is_empty());
+        assert!(!client.batchable(&[("one.vm.action".to_string(), vec!["stop".into(), 12.into()])]));
+
+        // a user can't be moved or added to another group
+        let client =
+            Policy::new(oned()).rule(Rule::OnlyGroups(ResourceKind::User, vec![GroupId(105)]));
+        assert!(client
+            .check("one.user.chgrp", &[7.into(), 0.into()])
+            .is_err());
+        assert!(client
+            .check("one.user.addgroup", &[7.into(), 0.into()])
+            .is_err());
+        assert!(client.inner().methods().is_empty());
+    }
+
+    #[test]
+    fn policy_groups_lookup() {
+        // the VM belongs to the group 105: it's read, then the call is sent
+        let client = Policy::new(oned()).rule(Rule::OnlyGroups(
+            ResourceKind::VirtualMachine,
+            vec![GroupId(105)],
+        ));
+        assert!(client
+            .call("one.vm.action", vec!["stop".into(), 12.into()])
+            .is_ok());
+        assert_eq!(
+            client.inner().methods(),
+            vec!["one.vm.info", "one.vm.action"]
+        );
+
+        // the VM belongs to another group: it's read, the call isn't sent
+        let client = Policy::new(oned()).rule(Rule::OnlyGroups(
+            ResourceKind::VirtualMachine,
+            vec![GroupId(106)],
+        ));
+        let resp = client.call("one.vm.action", vec!["stop".into(), 12.into()]);
+        assert!(matches!(resp, Err(Errors::PolicyDenied(msg)) if msg.contains("groups 106")));
+        assert_eq!(client.inner().methods(), vec!["one.vm.info"]);
+    }
+}
@@ -0,0 +1,265 @@
+//! The retry module wraps a RPCCaller to retry the calls failing on transient transport errors
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::thread;
+use std::time::Duration;
+
+use serde_xmlrpc::Value;
+
+use crate::common::methods;
+use crate::common::Errors;
+use crate::controller::RPCCaller;
+
+/// Tells if a method may be retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryRule {
+    Retry,
+    Never,
+}
+
+/// Exponential backoff parameters
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled at each new attempt
+    pub base_delay: Duration,
+    /// Upper bound of the delay between two attempts
+    pub max_delay: Duration,
+    /// Randomize each delay between the half and the full computed delay
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay to wait before the retry number `attempt` (starting at 0)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt);
+        let delay = self
+            .base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        if !self.jitter || delay.is_zero() {
+            return delay;
+        }
+
+        // the std hasher is randomly seeded, it's enough for jitter
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(attempt);
+        let ratio = (hasher.finish() % 1000) as u32;
+
+        delay / 2 + (delay / 2) * ratio / 1000
+    }
+}
+
+/// Retry wraps any RPCCaller and retries with exponential backoff the calls failing on transport errors.
+/// By default read only methods (*.info...) are retried and mutating methods are never retried,
+/// use `rule` to override the behavior of a method.
+#[derive(Debug)]
+pub struct Retry<C: RPCCaller> {
+    inner: C,
+    policy: RetryPolicy,
+    rules: Vec<(String, RetryRule)>,
+}
+
+impl<C: RPCCaller> Retry<C> {
+    pub fn new(inner: C) -> Self {
+        Retry {
+            inner,
+            policy: RetryPolicy::default(),
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Overrides the retry behavior of a method.
+    /// The pattern is either a method name or a prefix ending with '*', i.e. "one.vm.*"
+    pub fn rule(mut self, pattern: &str, rule: RetryRule) -> Self {
+        self.rules.push((pattern.to_string(), rule));
+        self
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Returns the rule applying to the method, the last matching rule wins
+    pub fn rule_for(&self, method: &str) -> RetryRule {
+        let rule = self
+            .rules
+            .iter()
+            .rev()
+            .find(|(pattern, _)| match pattern.strip_suffix('*') {
+                Some(prefix) => method.starts_with(prefix),
+                None => pattern == method,
+            });
+
+        match rule {
+            Some((_, rule)) => *rule,
+            None if methods::is_read_only(method) => RetryRule::Retry,
+            None => RetryRule::Never,
+        }
+    }
+}
+
+/// Transport errors that may disappear on a new attempt, i.e. during a raft leader change
+pub fn is_transient(err: &Errors) -> bool {
+    match err {
+        Errors::Connect(_) | Errors::HTTPReq(_) | Errors::HTTPRespHandling(_) => true,
+        Errors::HTTPStatus(code, _) => *code == 429 || (500..600).contains(code),
+        _ => false,
+    }
+}
+
+impl<C: RPCCaller> RPCCaller for Retry<C> {
+    fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        let max_retries = match self.rule_for(name) {
+            RetryRule::Retry => self.policy.max_retries,
+            RetryRule::Never => 0,
+        };
+
+        let mut attempt = 0;
+        loop {
+            match self.inner.call(name, args.clone()) {
+                Err(e) if is_transient(&e) && attempt < max_retries => {
+                    thread::sleep(self.policy.delay(attempt));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // a batch may contain mutating calls, it's never retried
+    fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        self.inner.multicall(calls)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    // fails with a transport error on the first calls
+    struct Flaky {
+        failures: u32,
+        calls: AtomicU32,
+    }
+
+    impl Flaky {
+        fn new(failures: u32) -> Self {
+            Flaky {
+                failures,
+                calls: AtomicU32::new(0),
+            }
+        }
+    }
+
+    impl RPCCaller for Flaky {
+        fn call(&self, _name: &str, _args: Vec<Value>) -> Result<String, Errors> {
+            let calls = self.calls.fetch_add(1, Ordering::SeqCst);
+            if calls < self.failures {
+                Err(Errors::HTTPStatus(503, "leader election".to_string()))
+            } else {
+                Ok("response".to_string())
+            }
+        }
+    }
+
+    fn no_delay() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            jitter: false,
+        }
+    }
+
+    #[test]
+    fn retry_read_method() {
+        let client = Retry::new(Flaky::new(2)).with_policy(no_delay());
+
+        let resp = client.call("one.vm.info", vec![0.into()]);
+        assert!(resp.is_ok());
+        assert_eq!(client.inner().calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn retry_exhausted() {
+        let client = Retry::new(Flaky::new(10)).with_policy(no_delay());
+
+        let resp = client.call("one.vm.info", vec![0.into()]);
+        assert!(matches!(resp, Err(Errors::HTTPStatus(503, _))));
+        assert_eq!(client.inner().calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn no_retry_mutate_method() {
+        let client = Retry::new(Flaky::new(1)).with_policy(no_delay());
+
+        let resp = client.call("one.vm.allocate", vec!["NAME=test".into(), false.into()]);
+        assert!(resp.is_err());
+        assert_eq!(client.inner().calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn retry_custom_rules() {
+        let client = Retry::new(Flaky::new(1))
+            .with_policy(no_delay())
+            .rule("one.vm.*", RetryRule::Never)
+            .rule("one.vm.rename", RetryRule::Retry);
+
+        assert_eq!(client.rule_for("one.vm.info"), RetryRule::Never);
+        assert_eq!(client.rule_for("one.vm.rename"), RetryRule::Retry);
+        assert_eq!(client.rule_for("one.user.info"), RetryRule::Retry);
+
+        let resp = client.call("one.vm.rename", vec![0.into(), "new".into()]);
+        assert!(resp.is_ok());
+        assert_eq!(client.inner().calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn backoff_delays() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            jitter: false,
+        };
+
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(400));
+        assert_eq!(policy.delay(3), Duration::from_millis(500));
+        assert_eq!(policy.delay(40), Duration::from_millis(500));
+
+        let policy = RetryPolicy {
+            jitter: true,
+            ..policy
+        };
+        let delay = policy.delay(1);
+        assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,606 @@
+//! The sim module provides an in-memory OpenNebula: a RPCCaller keeping the users, the groups
+//! and the virtual machines of a single zone in memory, so the code built on the controllers
+//! can be tested against a realistic behavior.
+//!
+//! The lifecycle transitions are applied immediately, as if the drivers answered at once.
+//! There is no scheduler: the pending VMs are deployed with `one.vm.deploy` or `Simulator::schedule`.
+//! The calls are made on behalf of oneadmin.
+//!
+//! It's available with the `testing` cargo feature.
+
+mod users;
+mod vms;
+mod zones;
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+use serde_xmlrpc::Value;
+
+use crate::common::template::builder::Builder;
+use crate::common::template::elements::{Pair, Vector};
+use crate::common::template::parser::Parser;
+use crate::common::xml::resource::Resource;
+use crate::common::xml::template::Template;
+use crate::common::{ErrorCode, Errors};
+#[cfg(feature = "async")]
+use crate::controller::AsyncRPCCaller;
+use crate::controller::RPCCaller;
+use crate::testing::xmlrpc;
+
+pub use vms::{LcmState, VmState};
+
+use users::{Group, User};
+use vms::Vm;
+
+const NO_EXISTS: ErrorCode = ErrorCode::NoExists;
+const ACTION: ErrorCode = ErrorCode::Action;
+const XML_RPC_API: ErrorCode = ErrorCode::XMLRPCAPI;
+const ALLOCATE: ErrorCode = ErrorCode::Allocate;
+
+const ONEADMIN_ID: i32 = 0;
+const ONEADMIN_GROUP_ID: i32 = 0;
+const USERS_GROUP_ID: i32 = 1;
+
+// The error part of the (false, message, code) tuple
+#[derive(Debug)]
+struct Failure {
+    message: String,
+    code: ErrorCode,
+}
+
+impl Failure {
+    fn new<S: Into<String>>(code: ErrorCode, message: S) -> Self {
+        Failure {
+            message: message.into(),
+            code,
+        }
+    }
+}
+
+type SimResult = Result<Value, Failure>;
+
+#[derive(Debug)]
+struct State {
+    users: BTreeMap<i32, User>,
+    groups: BTreeMap<i32, Group>,
+    vms: BTreeMap<i32, Vm>,
+    default_user_quotas: Builder,
+    next_user_id: i32,
+    next_group_id: i32,
+    next_vm_id: i32,
+}
+
+impl State {
+    fn new() -> Self {
+        let mut state = State {
+            users: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            vms: BTreeMap::new(),
+            default_user_quotas: Builder::new(),
+            next_user_id: 0,
+            next_group_id: 0,
+            next_vm_id: 0,
+        };
+
+        // the bootstrap resources of a fresh install
+        state.insert_group("oneadmin");
+        state.insert_group("users");
+        state.next_group_id = 100;
+        state.insert_user("oneadmin", "oneadmin", "core", ONEADMIN_GROUP_ID);
+        state.insert_user(
+            "serveradmin",
+            "serveradmin",
+            "server_cipher",
+            ONEADMIN_GROUP_ID,
+        );
+
+        state
+    }
+
+    fn dispatch(&mut self, name: &str, args: &[Value]) -> SimResult {
+        match name {
+            "one.user.allocate" => self.user_allocate(args),
+            "one.user.delete" => self.user_delete(args),
+            "one.user.info" => self.user_info(args),
+            "one.user.update" => self.user_update(args),
+            "one.user.passwd" => self.user_passwd(args),
+            "one.user.login" => self.user_login(args),
+            "one.user.quota" => self.user_quota(args),
+            "one.userpool.info" => self.userpool_info(),
+            "one.userquota.info" => self.userquota_info(),
+            "one.userquota.update" => self.userquota_update(args),
+            "one.group.allocate" => self.group_allocate(args),
+            "one.group.delete" => self.group_delete(args),
+            "one.group.info" => self.group_info(args),
+            "one.grouppool.info" => self.grouppool_info(),
+            "one.vm.allocate" => self.vm_allocate(args),
+            "one.vm.info" => self.vm_info(args),
+            "one.vm.action" => self.vm_action(args),
+            "one.vm.deploy" => self.vm_deploy(args),
+            "one.vm.rename" => self.vm_rename(args),
+            "one.vm.chmod" => self.vm_chmod(args),
+            "one.vm.chown" => self.vm_chown(args),
+            "one.vm.update" => self.vm_update(args),
+            "one.vmpool.info" => self.vmpool_info(args),
+            "one.vmpool.accounting" => self.vmpool_accounting(args),
+            "one.zone.info" => self.zone_info(args),
+            "one.zone.raftstatus" => self.zone_raftstatus(),
+            "one.zonepool.info" => self.zonepool_info(),
+            _ => Err(Failure::new(
+                XML_RPC_API,
+                "method not supported by the simulator",
+            )),
+        }
+    }
+}
+
+/// Simulator is a RPCCaller answering like oned from an in-memory state
+#[derive(Debug)]
+pub struct Simulator {
+    state: Mutex<State>,
+}
+
+impl Simulator {
+    /// Returns a simulator containing the resources of a fresh install:
+    /// the oneadmin and serveradmin users, the oneadmin and users groups
+    pub fn new() -> Self {
+        Simulator {
+            state: Mutex::new(State::new()),
+        }
+    }
+
+    /// Deploys all the pending VMs, like the scheduler would do
+    pub fn schedule(&self) {
+        let mut state = self.state.lock().unwrap();
+        for vm in state.vms.values_mut() {
+            if vm.state == VmState::Pending {
+                vm.deploy(0);
+            }
+        }
+    }
+
+    /// Returns the state of a VM, to check the effect of the calls
+    pub fn vm_state(&self, id: i32) -> Option<(VmState, LcmState)> {
+        let state = self.state.lock().unwrap();
+        state.vms.get(&id).map(|vm| (vm.state, vm.lcm_state))
+    }
+
+    fn answer(&self, name: &str, args: &[Value]) -> String {
+        xmlrpc::response(&self.result(name, args))
+    }
+
+    // the (success, payload, code) tuple of the call
+    fn result(&self, name: &str, args: &[Value]) -> Value {
+        let result = self.state.lock().unwrap().dispatch(name, args);
+        match result {
+            Ok(payload) => xmlrpc::success_value(payload),
+            Err(f) => xmlrpc::failure_value(&format!("[{}] {}", name, f.message), f.code.value()),
+        }
+    }
+
+    fn answer_multicall(&self, calls: &[(String, Vec<Value>)]) -> String {
+        let results = calls
+            .iter()
+            .map(|(name, args)| self.result(name, args))
+            .collect();
+        xmlrpc::multicall_response(results)
+    }
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RPCCaller for Simulator {
+    fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        Ok(self.answer(name, &args))
+    }
+
+    fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        Some(Ok(self.answer_multicall(calls)))
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl AsyncRPCCaller for Simulator {
+    async fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        Ok(self.answer(name, &args))
+    }
+
+    async fn multicall(&self, calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        Some(Ok(self.answer_multicall(calls)))
+    }
+}
+
+// Arguments helpers
+
+fn int_arg(args: &[Value], i: usize) -> Result<i32, Failure> {
+    match args.get(i) {
+        Some(Value::Int(v)) => Ok(*v),
+        _ => Err(invalid_arg(i, "int")),
+    }
+}
+
+fn str_arg(args: &[Value], i: usize) -> Result<&str, Failure> {
+    match args.get(i) {
+        Some(Value::String(v)) => Ok(v),
+        _ => Err(invalid_arg(i, "string")),
+    }
+}
+
+fn bool_arg(args: &[Value], i: usize) -> Result<bool, Failure> {
+    match args.get(i) {
+        Some(Value::Bool(v)) => Ok(*v),
+        _ => Err(invalid_arg(i, "boolean")),
+    }
+}
+
+fn invalid_arg(i: usize, kind: &str) -> Failure {
+    // the session string is the parameter 0
+    Failure::new(
+        XML_RPC_API,
+        format!("parameter {} should be of type {}", i + 1, kind),
+    )
+}
+
+// Parses a template in XML or in the OpenNebula syntax, keys are uppercased like oned does
+fn parse_template(raw: &str) -> Result<Builder, Failure> {
+    let parsed = if raw.trim_start().starts_with('<') {
+        match Resource::try_from(raw) {
+            Ok(resource) => Ok(Builder::from(Template::from_resource(
+                &resource.document,
+                resource.root,
+            ))),
+            Err(e) => Err(e.to_string()),
+        }
+    } else {
+        Parser::new(raw).parse().map_err(|e| e.to_string())
+    };
+
+    match parsed {
+        Ok(template) => Ok(uppercase_keys(&template)),
+        Err(e) => Err(Failure::new(
+            ACTION,
+            format!("Error parsing the template: {}", e),
+        )),
+    }
+}
+
+fn uppercase_keys(template: &Builder) -> Builder {
+    let mut upper = Builder::new();
+    for pair in template.pairs() {
+        upper.put_str(&pair.0.to_uppercase(), &pair.1);
+    }
+    for vector in template.vectors() {
+        let mut vec = Vector::new(vector.0.to_uppercase());
+        for pair in &vector.1 {
+            vec.1.push(Pair(pair.0.to_uppercase(), pair.1.clone()));
+        }
+        upper.put_vector(vec);
+    }
+    upper
+}
+
+// Merges the new template in the existing one, or replaces it
+fn update_template(template: &mut Builder, raw: &str, merge: i32) -> Result<(), Failure> {
+    let new = parse_template(raw)?;
+
+    if merge != 1 {
+        *template = new;
+        return Ok(());
+    }
+
+    for pair in new.pairs() {
+        let _ = template.rm(&pair.0);
+    }
+    for vector in new.vectors() {
+        let _ = template.rm(&vector.0);
+    }
+    for pair in new.pairs() {
+        template.put_str(&pair.0, &pair.1);
+    }
+    for vector in new.vectors() {
+        template.put_vector(vector.clone());
+    }
+    Ok(())
+}
+
+// XML helpers, the values are written like oned does
+
+fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(_) => 0,
+    }
+}
+
+fn tag<V: Display>(out: &mut String, name: &str, value: V) {
+    out.push_str(&format!(
+        "<{0}>{1}</{0}>",
+        name,
+        xmlrpc::escape(&value.to_string())
+    ));
+}
+
+fn cdata(value: &str) -> String {
+    format!("<![CDATA[{}]]>", value.replace("]]>", "]]]]><![CDATA[>"))
+}
+
+fn template_tag(out: &mut String, name: &str, template: &Builder) {
+    out.push_str(&format!("<{}>", name));
+    for pair in template.pairs() {
+        out.push_str(&format!("<{0}>{1}</{0}>", pair.0, cdata(&pair.1)));
+    }
+    for vector in template.vectors() {
+        out.push_str(&format!("<{}>", vector.0));
+        for pair in &vector.1 {
+            out.push_str(&format!("<{0}>{1}</{0}>", pair.0, cdata(&pair.1)));
+        }
+        out.push_str(&format!("</{}>", vector.0));
+    }
+    out.push_str(&format!("</{}>", name));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::ids::VmId;
+    use crate::common::operations::Info;
+    use crate::controller::Controller;
+    use crate::vm::Action;
+
+    fn call(sim: &Simulator, name: &str, args: Vec<Value>) -> String {
+        RPCCaller::call(sim, name, args).unwrap()
+    }
+
+    fn succeeded(response: &str) -> bool {
+        response.contains("<value><boolean>1</boolean></value>")
+    }
+
+    #[test]
+    fn vm_lifecycle() {
+        let sim = Simulator::new();
+
+        let resp = call(
+            &sim,
+            "one.vm.allocate",
+            vec!["NAME=test\nCPU=1\nMEMORY=32".into(), false.into()],
+        );
+        assert!(succeeded(&resp));
+        assert!(resp.contains("<i4>0</i4>"));
+        assert_eq!(sim.vm_state(0), Some((VmState::Pending, LcmState::Init)));
+
+        // not available while pending
+        let resp = call(&sim, "one.vm.action", vec!["poweroff".into(), 0.into()]);
+        assert!(!succeeded(&resp));
+        assert!(resp.contains(&format!("<i4>{}</i4>", ACTION.value())));
+
+        sim.schedule();
+        assert_eq!(sim.vm_state(0), Some((VmState::Active, LcmState::Running)));
+
+        let resp = call(&sim, "one.vm.action", vec!["poweroff".into(), 0.into()]);
+        assert!(succeeded(&resp));
+        assert_eq!(sim.vm_state(0), Some((VmState::Poweroff, LcmState::Init)));
+
+        call(&sim, "one.vm.action", vec!["resume".into(), 0.into()]);
+        assert_eq!(sim.vm_state(0), Some((VmState::Active, LcmState::Running)));
+
+        call(
+            &sim,
+            "one.vm.action",
+            vec!["terminate-hard".into(), 0.into()],
+        );
+        assert_eq!(sim.vm_state(0), Some((VmState::Done, LcmState::Init)));
+
+        // the next VM gets a new ID, and is on hold
+        let resp = call(
+            &sim,
+            "one.vm.allocate",
+            vec!["NAME=test2".into(), true.into()],
+        );
+        assert!(resp.contains("<i4>1</i4>"));
+        assert_eq!(sim.vm_state(1), Some((VmState::Hold, LcmState::Init)));
+
+        let resp = call(&sim, "one.vm.info", vec![42.into()]);
+        assert!(resp.contains(&format!("<i4>{}</i4>", NO_EXISTS.value())));
+    }
+
+    #[test]
+    fn vm_xml() {
+        let sim = Simulator::new();
+
+        call(
+            &sim,
+            "one.vm.allocate",
+            vec![
+                "NAME=test\nCPU=1\nMEMORY=32\ncustom=test\nCUSTOM=test2".into(),
+                false.into(),
+            ],
+        );
+        call(
+            &sim,
+            "one.vm.deploy",
+            vec![0.into(), 3.into(), false.into(), (-1).into()],
+        );
+
+        let resp = call(&sim, "one.vm.info", vec![0.into()]);
+        assert!(resp.contains("&lt;NAME&gt;test&lt;/NAME&gt;"));
+        assert!(resp.contains("&lt;STATE&gt;3&lt;/STATE&gt;&lt;LCM_STATE&gt;3&lt;/LCM_STATE&gt;"));
+        assert!(resp.contains("&lt;MEMORY&gt;&lt;![CDATA[32]]&gt;&lt;/MEMORY&gt;"));
+        assert!(resp
+            .contains("&lt;USER_TEMPLATE&gt;&lt;CUSTOM&gt;&lt;![CDATA[test]]&gt;&lt;/CUSTOM&gt;"));
+        assert!(resp.contains("&lt;HID&gt;3&lt;/HID&gt;"));
+
+        // DONE VMs are hidden by default
+        call(&sim, "one.vm.action", vec!["terminate".into(), 0.into()]);
+        let resp = call(
+            &sim,
+            "one.vmpool.info",
+            vec![(-2).into(), (-1).into(), (-1).into(), (-1).into()],
+        );
+        assert!(resp.contains("&lt;VM_POOL&gt;&lt;/VM_POOL&gt;"));
+
+        let resp = call(
+            &sim,
+            "one.vmpool.info",
+            vec![(-2).into(), (-1).into(), (-1).into(), (-2).into()],
+        );
+        assert!(resp.contains("&lt;VM_POOL&gt;&lt;VM&gt;&lt;ID&gt;0&lt;/ID&gt;"));
+    }
+
+    #[test]
+    fn users_and_groups() {
+        let sim = Simulator::new();
+
+        let resp = call(&sim, "one.group.allocate", vec!["dev".into()]);
+        assert!(resp.contains("<i4>100</i4>"));
+
+        let resp = call(
+            &sim,
+            "one.user.allocate",
+            vec!["alice".into(), "password".into(), "".into()],
+        );
+        assert!(resp.contains("<i4>2</i4>"));
+
+        // the name is already taken
+        let resp = call(
+            &sim,
+            "one.user.allocate",
+            vec!["alice".into(), "password".into(), "".into()],
+        );
+        assert!(resp.contains(&format!("<i4>{}</i4>", ALLOCATE.value())));
+
+        let resp = call(&sim, "one.user.info", vec![2.into()]);
+        assert!(resp.contains("&lt;GNAME&gt;users&lt;/GNAME&gt;"));
+        assert!(resp.contains("&lt;AUTH_DRIVER&gt;core&lt;/AUTH_DRIVER&gt;"));
+
+        let resp = call(&sim, "one.group.info", vec![1.into()]);
+        assert!(resp.contains("&lt;USERS&gt;&lt;ID&gt;2&lt;/ID&gt;&lt;/USERS&gt;"));
+
+        // the group has users
+        assert!(!succeeded(&call(&sim, "one.group.delete", vec![1.into()])));
+        assert!(succeeded(&call(&sim, "one.user.delete", vec![2.into()])));
+        assert!(!succeeded(&call(&sim, "one.user.delete", vec![0.into()])));
+        assert!(succeeded(&call(&sim, "one.group.delete", vec![100.into()])));
+
+        let resp = call(&sim, "one.user.unknown", vec![]);
+        assert!(resp.contains(&format!("<i4>{}</i4>", XML_RPC_API.value())));
+    }
+
+    #[test]
+    fn quotas_and_accounting() {
+        let sim = Simulator::new();
+
+        let resp = call(
+            &sim,
+            "one.user.quota",
+            vec![0.into(), "VM=[CPU=4, MEMORY=8192]".into()],
+        );
+        assert!(succeeded(&resp));
+        let resp = call(
+            &sim,
+            "one.user.quota",
+            vec![0.into(), "VM=[CPU=8, MEMORY=8192]".into()],
+        );
+        assert!(succeeded(&resp));
+
+        let resp = call(&sim, "one.user.info", vec![0.into()]);
+        assert!(resp.contains(
+            "&lt;VM_QUOTA&gt;&lt;VM&gt;&lt;CPU&gt;8&lt;/CPU&gt;&lt;MEMORY&gt;8192&lt;/MEMORY&gt;&lt;/VM&gt;&lt;/VM_QUOTA&gt;"
+        ));
+
+        let resp = call(
+            &sim,
+            "one.user.quota",
+            vec![0.into(), "HOST=[CPU=1]".into()],
+        );
+        assert!(resp.contains(&format!("<i4>{}</i4>", ACTION.value())));
+
+        let resp = call(
+            &sim,
+            "one.userquota.update",
+            vec!["DATASTORE=[ID=1, SIZE=1024]".into()],
+        );
+        assert!(resp.contains("&lt;DATASTORE_QUOTA&gt;&lt;DATASTORE&gt;&lt;ID&gt;1&lt;/ID&gt;"));
+        let resp = call(&sim, "one.userquota.info", vec![]);
+        assert!(resp.contains("&lt;SIZE&gt;1024&lt;/SIZE&gt;"));
+
+        // a record per deployment
+        call(
+            &sim,
+            "one.vm.allocate",
+            vec!["NAME=test\nMEMORY=32".into(), false.into()],
+        );
+        sim.schedule();
+        call(&sim, "one.vm.action", vec!["undeploy".into(), 0.into()]);
+        call(&sim, "one.vm.deploy", vec![0.into(), 2.into()]);
+
+        let resp = call(
+            &sim,
+            "one.vmpool.accounting",
+            vec![(-2).into(), (-1).into(), (-1).into()],
+        );
+        assert_eq!(resp.matches("&lt;HISTORY&gt;").count(), 2);
+        assert!(resp.contains("&lt;HID&gt;2&lt;/HID&gt;"));
+
+        // ended before the time range
+        let resp = call(
+            &sim,
+            "one.vmpool.accounting",
+            vec![(-2).into(), Value::Int(now() as i32 + 3600), (-1).into()],
+        );
+        assert_eq!(resp.matches("&lt;HISTORY&gt;").count(), 1);
+    }
+
+    #[test]
+    fn standalone_zone() {
+        let sim = Simulator::new();
+
+        let resp = call(&sim, "one.zonepool.info", vec![]);
+        assert_eq!(resp.matches("&lt;ZONE&gt;").count(), 1);
+        assert!(resp.contains("&lt;ENDPOINT&gt;http://localhost:2633/RPC2&lt;/ENDPOINT&gt;"));
+
+        let resp = call(&sim, "one.zone.info", vec![100.into()]);
+        assert!(resp.contains(&format!("<i4>{}</i4>", NO_EXISTS.value())));
+
+        let resp = call(&sim, "one.zone.raftstatus", vec![]);
+        assert!(resp.contains("&lt;STATE&gt;0&lt;/STATE&gt;"));
+    }
+
+    #[test]
+    fn controller_on_simulator() {
+        let controller = Controller::new(Simulator::new());
+
+        let mut tpl = crate::common::template::builder::Builder::new();
+        tpl.put_str("NAME", "roca-sim");
+        tpl.put_str("MEMORY", "32");
+
+        let vm_id = controller.virtual_machines().allocate(tpl, false).unwrap();
+        controller.client.schedule();
+
+        let vm_controller = controller.virtual_machine(vm_id);
+        assert!(vm_controller.action(Action::PowerOff).is_ok());
+
+        // already powered off
+        let err = vm_controller.action(Action::PowerOff).unwrap_err();
+        assert_eq!(err.code(), Some(ErrorCode::Action));
+
+        match controller.virtual_machine(VmId(42)).info() {
+            Err(e) => assert_eq!(e.code(), Some(ErrorCode::NoExists)),
+            Ok(_) => panic!("the VM shouldn't exist"),
+        }
+
+        assert_eq!(
+            controller.client.vm_state(vm_id.0),
+            Some((VmState::Poweroff, LcmState::Init))
+        );
+    }
+}
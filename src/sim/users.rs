@@ -0,0 +1,405 @@
+//! Users and groups of the simulator
+
+use serde_xmlrpc::Value;
+
+use super::{
+    int_arg, now, parse_template, str_arg, tag, template_tag, update_template, Failure, SimResult,
+    State, ACTION, ALLOCATE, NO_EXISTS, ONEADMIN_GROUP_ID, ONEADMIN_ID, USERS_GROUP_ID,
+};
+use crate::common::template::builder::Builder;
+
+// the quota sections and the name of their vectors
+const QUOTAS: [(&str, &str); 4] = [
+    ("DATASTORE_QUOTA", "DATASTORE"),
+    ("NETWORK_QUOTA", "NETWORK"),
+    ("VM_QUOTA", "VM"),
+    ("IMAGE_QUOTA", "IMAGE"),
+];
+
+fn quotas_xml(out: &mut String, quotas: &Builder) {
+    for (section, name) in QUOTAS {
+        out.push_str(&format!("<{}>", section));
+        for vector in quotas.vectors().iter().filter(|v| v.0 == name) {
+            out.push_str(&format!("<{}>", name));
+            for pair in &vector.1 {
+                tag(out, &pair.0, &pair.1);
+            }
+            out.push_str(&format!("</{}>", name));
+        }
+        out.push_str(&format!("</{}>", section));
+    }
+}
+
+// Sets the quotas of the template, a quota replaces the one with the same name and ID
+fn set_quotas(quotas: &mut Builder, raw: &str) -> Result<(), Failure> {
+    let new = parse_template(raw)?;
+    for vector in new.vectors() {
+        if !QUOTAS.iter().any(|(_, name)| *name == vector.0) {
+            return Err(Failure::new(
+                ACTION,
+                format!("Unknown quota type {}.", vector.0),
+            ));
+        }
+    }
+
+    let mut merged = Builder::new();
+    for vector in quotas.vectors() {
+        let replaced = new
+            .vectors()
+            .iter()
+            .any(|v| v.0 == vector.0 && v.get("ID").ok() == vector.get("ID").ok());
+        if !replaced {
+            merged.put_vector(vector.clone());
+        }
+    }
+    for vector in new.vectors() {
+        merged.put_vector(vector.clone());
+    }
+    *quotas = merged;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub(super) struct User {
+    pub id: i32,
+    pub gid: i32,
+    pub groups: Vec<i32>,
+    pub name: String,
+    pub password: String,
+    pub auth_driver: String,
+    pub login_token: Option<(String, i64)>,
+    pub template: Builder,
+    pub quotas: Builder,
+}
+
+#[derive(Debug)]
+pub(super) struct Group {
+    pub id: i32,
+    pub name: String,
+    pub template: Builder,
+}
+
+impl State {
+    pub(super) fn insert_group(&mut self, name: &str) -> i32 {
+        let id = self.next_group_id;
+        self.next_group_id += 1;
+        self.groups.insert(
+            id,
+            Group {
+                id,
+                name: name.to_string(),
+                template: Builder::new(),
+            },
+        );
+        id
+    }
+
+    pub(super) fn insert_user(
+        &mut self,
+        name: &str,
+        password: &str,
+        driver: &str,
+        gid: i32,
+    ) -> i32 {
+        let id = self.next_user_id;
+        self.next_user_id += 1;
+        self.users.insert(
+            id,
+            User {
+                id,
+                gid,
+                groups: vec![gid],
+                name: name.to_string(),
+                password: password.to_string(),
+                auth_driver: driver.to_string(),
+                login_token: None,
+                template: Builder::new(),
+                quotas: Builder::new(),
+            },
+        );
+        id
+    }
+
+    pub(super) fn user(&self, id: i32) -> Result<&User, Failure> {
+        // -1 is the user making the call
+        let id = if id == -1 { ONEADMIN_ID } else { id };
+        match self.users.get(&id) {
+            Some(user) => Ok(user),
+            None => Err(Failure::new(
+                NO_EXISTS,
+                format!("Error getting user [{}].", id),
+            )),
+        }
+    }
+
+    pub(super) fn group(&self, id: i32) -> Result<&Group, Failure> {
+        let id = if id == -1 { ONEADMIN_GROUP_ID } else { id };
+        match self.groups.get(&id) {
+            Some(group) => Ok(group),
+            None => Err(Failure::new(
+                NO_EXISTS,
+                format!("Error getting group [{}].", id),
+            )),
+        }
+    }
+
+    pub(super) fn user_mut(&mut self, id: i32) -> Result<&mut User, Failure> {
+        let id = self.user(id)?.id;
+        Ok(self.users.get_mut(&id).unwrap())
+    }
+
+    pub(super) fn group_name(&self, id: i32) -> String {
+        match self.groups.get(&id) {
+            Some(group) => group.name.clone(),
+            None => String::new(),
+        }
+    }
+
+    pub(super) fn user_name(&self, id: i32) -> String {
+        match self.users.get(&id) {
+            Some(user) => user.name.clone(),
+            None => String::new(),
+        }
+    }
+
+    fn user_xml(&self, out: &mut String, user: &User) {
+        out.push_str("<USER>");
+        tag(out, "ID", user.id);
+        tag(out, "GID", user.gid);
+        out.push_str("<GROUPS>");
+        for gid in &user.groups {
+            tag(out, "ID", gid);
+        }
+        out.push_str("</GROUPS>");
+        tag(out, "GNAME", self.group_name(user.gid));
+        tag(out, "NAME", &user.name);
+        tag(out, "PASSWORD", &user.password);
+        tag(out, "AUTH_DRIVER", &user.auth_driver);
+        tag(out, "ENABLED", 1);
+        match &user.login_token {
+            Some((token, expiration)) => {
+                out.push_str("<LOGIN_TOKEN>");
+                tag(out, "TOKEN", token);
+                tag(out, "EXPIRATION_TIME", expiration);
+                tag(out, "EGID", -1);
+                out.push_str("</LOGIN_TOKEN>");
+            }
+            None => out.push_str("<LOGIN_TOKEN></LOGIN_TOKEN>"),
+        }
+        template_tag(out, "TEMPLATE", &user.template);
+        quotas_xml(out, &user.quotas);
+        self.default_user_quotas_xml(out);
+        out.push_str("</USER>");
+    }
+
+    fn group_xml(&self, out: &mut String, group: &Group) {
+        out.push_str("<GROUP>");
+        tag(out, "ID", group.id);
+        tag(out, "NAME", &group.name);
+        template_tag(out, "TEMPLATE", &group.template);
+        out.push_str("<USERS>");
+        for user in self.users.values() {
+            if user.groups.contains(&group.id) {
+                tag(out, "ID", user.id);
+            }
+        }
+        out.push_str("</USERS><ADMINS></ADMINS>");
+        quotas_xml(out, &Builder::new());
+        out.push_str("<DEFAULT_GROUP_QUOTAS>");
+        quotas_xml(out, &Builder::new());
+        out.push_str("</DEFAULT_GROUP_QUOTAS>");
+        out.push_str("</GROUP>");
+    }
+
+    pub(super) fn user_allocate(&mut self, args: &[Value]) -> SimResult {
+        let name = str_arg(args, 0)?;
+        let password = str_arg(args, 1)?;
+        let driver = match str_arg(args, 2)? {
+            "" => "core",
+            d => d,
+        };
+
+        // the optional groups, the first one is the primary group
+        let mut groups = Vec::new();
+        if let Some(Value::Array(values)) = args.get(3) {
+            for i in 0..values.len() {
+                groups.push(int_arg(values, i)?);
+            }
+        }
+
+        if name.is_empty() {
+            return Err(Failure::new(ALLOCATE, "Invalid NAME, it cannot be empty"));
+        }
+        if let Some(user) = self.users.values().find(|u| u.name == name) {
+            return Err(Failure::new(
+                ALLOCATE,
+                format!("NAME is already taken by USER {}.", user.id),
+            ));
+        }
+        for gid in &groups {
+            self.group(*gid)?;
+        }
+
+        let gid = groups.first().copied().unwrap_or(USERS_GROUP_ID);
+        let id = self.insert_user(name, password, driver, gid);
+        if groups.len() > 1 {
+            self.users.get_mut(&id).unwrap().groups = groups;
+        }
+
+        Ok(Value::Int(id))
+    }
+
+    pub(super) fn user_delete(&mut self, args: &[Value]) -> SimResult {
+        let id = self.user(int_arg(args, 0)?)?.id;
+        if id == ONEADMIN_ID {
+            return Err(Failure::new(ACTION, "oneadmin cannot be deleted."));
+        }
+        self.users.remove(&id);
+        Ok(Value::Int(id))
+    }
+
+    pub(super) fn user_info(&mut self, args: &[Value]) -> SimResult {
+        let user = self.user(int_arg(args, 0)?)?;
+        let mut out = String::new();
+        self.user_xml(&mut out, user);
+        Ok(Value::String(out))
+    }
+
+    pub(super) fn user_update(&mut self, args: &[Value]) -> SimResult {
+        let id = int_arg(args, 0)?;
+        let raw = str_arg(args, 1)?;
+        let merge = int_arg(args, 2)?;
+
+        let user = self.user_mut(id)?;
+        update_template(&mut user.template, raw, merge)?;
+        Ok(Value::Int(user.id))
+    }
+
+    pub(super) fn user_passwd(&mut self, args: &[Value]) -> SimResult {
+        let id = int_arg(args, 0)?;
+        let password = str_arg(args, 1)?;
+        if password.is_empty() {
+            return Err(Failure::new(ACTION, "Invalid password, it cannot be empty"));
+        }
+
+        let user = self.user_mut(id)?;
+        user.password = password.to_string();
+        Ok(Value::Int(user.id))
+    }
+
+    pub(super) fn user_quota(&mut self, args: &[Value]) -> SimResult {
+        let id = int_arg(args, 0)?;
+        let raw = str_arg(args, 1)?;
+
+        let user = self.user_mut(id)?;
+        set_quotas(&mut user.quotas, raw)?;
+        Ok(Value::Int(user.id))
+    }
+
+    fn default_user_quotas_xml(&self, out: &mut String) {
+        out.push_str("<DEFAULT_USER_QUOTAS>");
+        quotas_xml(out, &self.default_user_quotas);
+        out.push_str("</DEFAULT_USER_QUOTAS>");
+    }
+
+    pub(super) fn userquota_info(&mut self) -> SimResult {
+        let mut out = String::new();
+        self.default_user_quotas_xml(&mut out);
+        Ok(Value::String(out))
+    }
+
+    pub(super) fn userquota_update(&mut self, args: &[Value]) -> SimResult {
+        let raw = str_arg(args, 0)?;
+        set_quotas(&mut self.default_user_quotas, raw)?;
+        self.userquota_info()
+    }
+
+    pub(super) fn user_login(&mut self, args: &[Value]) -> SimResult {
+        let name = str_arg(args, 0)?;
+        let token = str_arg(args, 1)?;
+        let valid = int_arg(args, 2)?;
+
+        let id = match self.users.values().find(|u| u.name == name) {
+            Some(user) => user.id,
+            None => {
+                return Err(Failure::new(
+                    NO_EXISTS,
+                    format!("Error getting user {}.", name),
+                ))
+            }
+        };
+        let user = self.users.get_mut(&id).unwrap();
+
+        // a zero validity resets the token
+        if valid == 0 {
+            user.login_token = None;
+            return Ok(Value::String(String::new()));
+        }
+
+        let token = match token {
+            "" => format!("{:040x}", (id as u64) << 32 | now() as u64),
+            t => t.to_string(),
+        };
+        let expiration = if valid < 0 { -1 } else { now() + valid as i64 };
+        user.login_token = Some((token.clone(), expiration));
+
+        Ok(Value::String(token))
+    }
+
+    pub(super) fn userpool_info(&mut self) -> SimResult {
+        let mut out = String::from("<USER_POOL>");
+        for user in self.users.values() {
+            self.user_xml(&mut out, user);
+        }
+        out.push_str("</USER_POOL>");
+        Ok(Value::String(out))
+    }
+
+    pub(super) fn group_allocate(&mut self, args: &[Value]) -> SimResult {
+        let name = str_arg(args, 0)?;
+
+        if name.is_empty() {
+            return Err(Failure::new(ALLOCATE, "Invalid NAME, it cannot be empty"));
+        }
+        if let Some(group) = self.groups.values().find(|g| g.name == name) {
+            return Err(Failure::new(
+                ALLOCATE,
+                format!("NAME is already taken by GROUP {}.", group.id),
+            ));
+        }
+
+        Ok(Value::Int(self.insert_group(name)))
+    }
+
+    pub(super) fn group_delete(&mut self, args: &[Value]) -> SimResult {
+        let id = self.group(int_arg(args, 0)?)?.id;
+        if id == ONEADMIN_GROUP_ID || id == USERS_GROUP_ID {
+            return Err(Failure::new(
+                ACTION,
+                "System Groups (ID < 100) cannot be deleted.",
+            ));
+        }
+        if self.users.values().any(|u| u.groups.contains(&id)) {
+            return Err(Failure::new(ACTION, format!("Group {} is not empty.", id)));
+        }
+        self.groups.remove(&id);
+        Ok(Value::Int(id))
+    }
+
+    pub(super) fn group_info(&mut self, args: &[Value]) -> SimResult {
+        let group = self.group(int_arg(args, 0)?)?;
+        let mut out = String::new();
+        self.group_xml(&mut out, group);
+        Ok(Value::String(out))
+    }
+
+    pub(super) fn grouppool_info(&mut self) -> SimResult {
+        let mut out = String::from("<GROUP_POOL>");
+        for group in self.groups.values() {
+            self.group_xml(&mut out, group);
+        }
+        out.push_str("</GROUP_POOL>");
+        Ok(Value::String(out))
+    }
+}
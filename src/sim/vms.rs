@@ -0,0 +1,489 @@
+//! Virtual machines of the simulator
+
+use std::fmt::Display;
+
+use serde_xmlrpc::Value;
+
+use super::{
+    bool_arg, int_arg, now, parse_template, str_arg, tag, template_tag, update_template, Failure,
+    SimResult, State, ACTION, NO_EXISTS, ONEADMIN_GROUP_ID, ONEADMIN_ID, XML_RPC_API,
+};
+use crate::common::template::builder::Builder;
+
+// Attributes kept in the VM template, the others go to the user template
+const SYSTEM_ATTRIBUTES: &[&str] = &[
+    "CPU",
+    "VCPU",
+    "MEMORY",
+    "DISK",
+    "NIC",
+    "NIC_ALIAS",
+    "OS",
+    "FEATURES",
+    "GRAPHICS",
+    "INPUT",
+    "CONTEXT",
+    "RAW",
+    "PCI",
+    "CPU_MODEL",
+    "TOPOLOGY",
+    "NUMA_NODE",
+    "TEMPLATE_ID",
+];
+
+/// VM state, numbered like oned does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmState {
+    Init = 0,
+    Pending = 1,
+    Hold = 2,
+    Active = 3,
+    Stopped = 4,
+    Suspended = 5,
+    Done = 6,
+    Poweroff = 8,
+    Undeployed = 9,
+}
+
+/// LCM state of an active VM, only the stable states are reached by the simulator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LcmState {
+    Init = 0,
+    Running = 3,
+}
+
+impl Display for VmState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmState::Init => f.write_str("INIT"),
+            VmState::Pending => f.write_str("PENDING"),
+            VmState::Hold => f.write_str("HOLD"),
+            VmState::Active => f.write_str("ACTIVE"),
+            VmState::Stopped => f.write_str("STOPPED"),
+            VmState::Suspended => f.write_str("SUSPENDED"),
+            VmState::Done => f.write_str("DONE"),
+            VmState::Poweroff => f.write_str("POWEROFF"),
+            VmState::Undeployed => f.write_str("UNDEPLOYED"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct History {
+    seq: i32,
+    hid: i32,
+    stime: i64,
+    etime: i64,
+}
+
+fn history_xml(out: &mut String, vm_id: i32, history: &History) {
+    out.push_str("<HISTORY>");
+    tag(out, "OID", vm_id);
+    tag(out, "SEQ", history.seq);
+    tag(out, "HOSTNAME", format!("host{}", history.hid));
+    tag(out, "HID", history.hid);
+    tag(out, "STIME", history.stime);
+    tag(out, "ETIME", history.etime);
+    out.push_str("</HISTORY>");
+}
+
+#[derive(Debug)]
+pub(super) struct Vm {
+    id: i32,
+    uid: i32,
+    gid: i32,
+    name: String,
+    permissions: [i32; 9],
+    pub state: VmState,
+    pub lcm_state: LcmState,
+    prev_state: VmState,
+    prev_lcm_state: LcmState,
+    resched: bool,
+    stime: i64,
+    etime: i64,
+    template: Builder,
+    user_template: Builder,
+    history: Vec<History>,
+}
+
+impl Vm {
+    fn set_state(&mut self, state: VmState, lcm_state: LcmState) {
+        self.prev_state = self.state;
+        self.prev_lcm_state = self.lcm_state;
+        self.state = state;
+        self.lcm_state = lcm_state;
+    }
+
+    fn close_history(&mut self) {
+        if let Some(history) = self.history.last_mut() {
+            if history.etime == 0 {
+                history.etime = now();
+            }
+        }
+    }
+
+    pub(super) fn deploy(&mut self, hid: i32) {
+        self.close_history();
+        self.history.push(History {
+            seq: self.history.len() as i32,
+            hid,
+            stime: now(),
+            etime: 0,
+        });
+        self.set_state(VmState::Active, LcmState::Running);
+    }
+
+    // Applies the action immediately, returns false if it's not available in the current state
+    fn apply(&mut self, action: &str) -> Result<bool, Failure> {
+        let running = self.state == VmState::Active && self.lcm_state == LcmState::Running;
+
+        let applied = match action {
+            "terminate" | "terminate-hard" => {
+                if self.state == VmState::Done {
+                    return Ok(false);
+                }
+                self.close_history();
+                self.etime = now();
+                self.set_state(VmState::Done, LcmState::Init);
+                true
+            }
+            "poweroff" | "poweroff-hard" if running => {
+                self.set_state(VmState::Poweroff, LcmState::Init);
+                true
+            }
+            "undeploy" | "undeploy-hard" if running || self.state == VmState::Poweroff => {
+                self.close_history();
+                self.set_state(VmState::Undeployed, LcmState::Init);
+                true
+            }
+            "reboot" | "reboot-hard" => running,
+            "hold" if self.state == VmState::Pending => {
+                self.set_state(VmState::Hold, LcmState::Init);
+                true
+            }
+            "release" if self.state == VmState::Hold => {
+                self.set_state(VmState::Pending, LcmState::Init);
+                true
+            }
+            "stop" if running => {
+                self.close_history();
+                self.set_state(VmState::Stopped, LcmState::Init);
+                true
+            }
+            "suspend" if running => {
+                self.set_state(VmState::Suspended, LcmState::Init);
+                true
+            }
+            "resume" => match self.state {
+                VmState::Poweroff | VmState::Suspended => {
+                    self.set_state(VmState::Active, LcmState::Running);
+                    true
+                }
+                // the VM has to be scheduled again
+                VmState::Stopped | VmState::Undeployed => {
+                    self.set_state(VmState::Pending, LcmState::Init);
+                    true
+                }
+                _ => false,
+            },
+            "resched" | "unresched" if running => {
+                self.resched = action == "resched";
+                true
+            }
+            "poweroff" | "poweroff-hard" | "undeploy" | "undeploy-hard" | "hold" | "release"
+            | "stop" | "suspend" | "resched" | "unresched" => false,
+            _ => {
+                return Err(Failure::new(
+                    XML_RPC_API,
+                    format!("Invalid action \"{}\".", action),
+                ))
+            }
+        };
+
+        Ok(applied)
+    }
+}
+
+impl State {
+    fn vm(&self, id: i32) -> Result<&Vm, Failure> {
+        match self.vms.get(&id) {
+            Some(vm) => Ok(vm),
+            None => Err(Failure::new(
+                NO_EXISTS,
+                format!("Error getting virtual machine [{}].", id),
+            )),
+        }
+    }
+
+    fn vm_mut(&mut self, id: i32) -> Result<&mut Vm, Failure> {
+        self.vm(id)?;
+        Ok(self.vms.get_mut(&id).unwrap())
+    }
+
+    fn vm_xml(&self, out: &mut String, vm: &Vm) {
+        out.push_str("<VM>");
+        tag(out, "ID", vm.id);
+        tag(out, "UID", vm.uid);
+        tag(out, "GID", vm.gid);
+        tag(out, "UNAME", self.user_name(vm.uid));
+        tag(out, "GNAME", self.group_name(vm.gid));
+        tag(out, "NAME", &vm.name);
+        out.push_str("<PERMISSIONS>");
+        let names = [
+            "OWNER_U", "OWNER_M", "OWNER_A", "GROUP_U", "GROUP_M", "GROUP_A", "OTHER_U", "OTHER_M",
+            "OTHER_A",
+        ];
+        for (name, value) in names.iter().zip(vm.permissions.iter()) {
+            tag(out, name, value);
+        }
+        out.push_str("</PERMISSIONS>");
+        tag(out, "LAST_POLL", 0);
+        tag(out, "STATE", vm.state as i32);
+        tag(out, "LCM_STATE", vm.lcm_state as i32);
+        tag(out, "PREV_STATE", vm.prev_state as i32);
+        tag(out, "PREV_LCM_STATE", vm.prev_lcm_state as i32);
+        tag(out, "RESCHED", vm.resched as i32);
+        tag(out, "STIME", vm.stime);
+        tag(out, "ETIME", vm.etime);
+        match vm.history.is_empty() {
+            true => tag(out, "DEPLOY_ID", ""),
+            false => tag(out, "DEPLOY_ID", format!("one-{}", vm.id)),
+        }
+        out.push_str("<MONITORING></MONITORING><SCHED_ACTIONS></SCHED_ACTIONS>");
+        template_tag(out, "TEMPLATE", &vm.template);
+        template_tag(out, "USER_TEMPLATE", &vm.user_template);
+        out.push_str("<HISTORY_RECORDS>");
+        for history in &vm.history {
+            history_xml(out, vm.id, history);
+        }
+        out.push_str("</HISTORY_RECORDS>");
+        out.push_str("</VM>");
+    }
+
+    pub(super) fn vm_allocate(&mut self, args: &[Value]) -> SimResult {
+        let raw = str_arg(args, 0)?;
+        let on_hold = bool_arg(args, 1)?;
+        let parsed = parse_template(raw)?;
+
+        let id = self.next_vm_id;
+        let mut name = format!("one-{}", id);
+        let mut template = Builder::new();
+        let mut user_template = Builder::new();
+
+        for pair in parsed.pairs() {
+            if pair.0 == "NAME" {
+                name = pair.1.clone();
+            } else if SYSTEM_ATTRIBUTES.contains(&pair.0.as_str()) {
+                template.put_str(&pair.0, &pair.1);
+            } else {
+                user_template.put_str(&pair.0, &pair.1);
+            }
+        }
+        for vector in parsed.vectors() {
+            if SYSTEM_ATTRIBUTES.contains(&vector.0.as_str()) {
+                template.put_vector(vector.clone());
+            } else {
+                user_template.put_vector(vector.clone());
+            }
+        }
+        template.put_str("VMID", &id.to_string());
+
+        let state = if on_hold {
+            VmState::Hold
+        } else {
+            VmState::Pending
+        };
+
+        self.next_vm_id += 1;
+        self.vms.insert(
+            id,
+            Vm {
+                id,
+                uid: ONEADMIN_ID,
+                gid: ONEADMIN_GROUP_ID,
+                name,
+                // default umask: 177
+                permissions: [1, 1, 0, 0, 0, 0, 0, 0, 0],
+                state,
+                lcm_state: LcmState::Init,
+                prev_state: state,
+                prev_lcm_state: LcmState::Init,
+                resched: false,
+                stime: now(),
+                etime: 0,
+                template,
+                user_template,
+                history: Vec::new(),
+            },
+        );
+
+        Ok(Value::Int(id))
+    }
+
+    pub(super) fn vm_info(&mut self, args: &[Value]) -> SimResult {
+        let vm = self.vm(int_arg(args, 0)?)?;
+        let mut out = String::new();
+        self.vm_xml(&mut out, vm);
+        Ok(Value::String(out))
+    }
+
+    pub(super) fn vm_action(&mut self, args: &[Value]) -> SimResult {
+        let action = str_arg(args, 0)?;
+        let id = int_arg(args, 1)?;
+
+        let vm = self.vm_mut(id)?;
+        let state = vm.state;
+        if !vm.apply(action)? {
+            return Err(Failure::new(
+                ACTION,
+                format!(
+                    "Error performing action \"{}\" on virtual machine [{}]. This action is not available for state {}",
+                    action, id, state
+                ),
+            ));
+        }
+
+        Ok(Value::Int(id))
+    }
+
+    pub(super) fn vm_deploy(&mut self, args: &[Value]) -> SimResult {
+        let id = int_arg(args, 0)?;
+        let hid = int_arg(args, 1)?;
+
+        let vm = self.vm_mut(id)?;
+        match vm.state {
+            VmState::Pending | VmState::Hold | VmState::Stopped | VmState::Undeployed => {
+                vm.deploy(hid);
+                Ok(Value::Int(id))
+            }
+            state => Err(Failure::new(
+                ACTION,
+                format!(
+                    "Error performing action \"deploy\" on virtual machine [{}]. This action is not available for state {}",
+                    id, state
+                ),
+            )),
+        }
+    }
+
+    pub(super) fn vm_rename(&mut self, args: &[Value]) -> SimResult {
+        let id = int_arg(args, 0)?;
+        let name = str_arg(args, 1)?;
+
+        if name.is_empty() {
+            return Err(Failure::new(ACTION, "Invalid NAME, it cannot be empty"));
+        }
+
+        self.vm_mut(id)?.name = name.to_string();
+        Ok(Value::Int(id))
+    }
+
+    pub(super) fn vm_chmod(&mut self, args: &[Value]) -> SimResult {
+        let id = int_arg(args, 0)?;
+        let mut permissions = [0; 9];
+        for (i, p) in permissions.iter_mut().enumerate() {
+            *p = int_arg(args, i + 1)?;
+        }
+
+        let vm = self.vm_mut(id)?;
+        for (current, new) in vm.permissions.iter_mut().zip(permissions) {
+            // -1 keeps the current value
+            if new != -1 {
+                *current = new;
+            }
+        }
+        Ok(Value::Int(id))
+    }
+
+    pub(super) fn vm_chown(&mut self, args: &[Value]) -> SimResult {
+        let id = int_arg(args, 0)?;
+        let uid = int_arg(args, 1)?;
+        let gid = int_arg(args, 2)?;
+
+        if uid != -1 {
+            self.user(uid)?;
+        }
+        if gid != -1 {
+            self.group(gid)?;
+        }
+
+        let vm = self.vm_mut(id)?;
+        if uid != -1 {
+            vm.uid = uid;
+        }
+        if gid != -1 {
+            vm.gid = gid;
+        }
+        Ok(Value::Int(id))
+    }
+
+    pub(super) fn vm_update(&mut self, args: &[Value]) -> SimResult {
+        let id = int_arg(args, 0)?;
+        let raw = str_arg(args, 1)?;
+        let merge = int_arg(args, 2)?;
+
+        let vm = self.vm_mut(id)?;
+        update_template(&mut vm.user_template, raw, merge)?;
+        Ok(Value::Int(id))
+    }
+
+    // filter: -2 all, -1 mine and my groups, -3 mine, >= 0 a user ID
+    // state: -2 any, -1 any except DONE, >= 0 a state
+    pub(super) fn vmpool_info(&mut self, args: &[Value]) -> SimResult {
+        let filter = int_arg(args, 0)?;
+        let start = int_arg(args, 1)?;
+        let end = int_arg(args, 2)?;
+        let state = int_arg(args, 3)?;
+
+        let mut out = String::from("<VM_POOL>");
+        for vm in self.vms.values() {
+            let owner = match filter {
+                f if f >= 0 => vm.uid == f,
+                -3 => vm.uid == ONEADMIN_ID,
+                _ => true,
+            };
+            let in_range = (start < 0 || vm.id >= start) && (end < 0 || vm.id <= end);
+            let in_state = match state {
+                -2 => true,
+                -1 => vm.state != VmState::Done,
+                s => vm.state as i32 == s,
+            };
+
+            if owner && in_range && in_state {
+                self.vm_xml(&mut out, vm);
+            }
+        }
+        out.push_str("</VM_POOL>");
+        Ok(Value::String(out))
+    }
+
+    // filter: same as vmpool_info
+    // start, end: the records overlapping the time range are returned, -1 for no limit
+    pub(super) fn vmpool_accounting(&mut self, args: &[Value]) -> SimResult {
+        let filter = int_arg(args, 0)?;
+        let start = int_arg(args, 1)? as i64;
+        let end = int_arg(args, 2)? as i64;
+
+        let mut out = String::from("<HISTORY_RECORDS>");
+        for vm in self.vms.values() {
+            let owner = match filter {
+                f if f >= 0 => vm.uid == f,
+                -3 => vm.uid == ONEADMIN_ID,
+                _ => true,
+            };
+            if !owner {
+                continue;
+            }
+
+            for history in &vm.history {
+                let started = end < 0 || history.stime <= end;
+                let not_ended = start < 0 || history.etime == 0 || history.etime >= start;
+                if started && not_ended {
+                    history_xml(&mut out, vm.id, history);
+                }
+            }
+        }
+        out.push_str("</HISTORY_RECORDS>");
+        Ok(Value::String(out))
+    }
+}
@@ -0,0 +1,59 @@
+//! The zone of the simulator: a standalone OpenNebula, a single zone without HA
+
+use serde_xmlrpc::Value;
+
+use super::{int_arg, tag, Failure, SimResult, State, NO_EXISTS};
+
+const ZONE_ID: i32 = 0;
+const ZONE_NAME: &str = "OpenNebula";
+const ZONE_ENDPOINT: &str = "http://localhost:2633/RPC2";
+
+// the raft state of a standalone frontend
+const SOLO: i32 = 0;
+
+fn zone_xml(out: &mut String) {
+    out.push_str("<ZONE>");
+    tag(out, "ID", ZONE_ID);
+    tag(out, "NAME", ZONE_NAME);
+    tag(out, "STATE", 0);
+    out.push_str("<TEMPLATE>");
+    tag(out, "ENDPOINT", ZONE_ENDPOINT);
+    out.push_str("</TEMPLATE><SERVER_POOL/></ZONE>");
+}
+
+impl State {
+    pub(super) fn zone_info(&mut self, args: &[Value]) -> SimResult {
+        let id = int_arg(args, 0)?;
+        if id != ZONE_ID {
+            return Err(Failure::new(
+                NO_EXISTS,
+                format!("Error getting zone [{}].", id),
+            ));
+        }
+
+        let mut out = String::new();
+        zone_xml(&mut out);
+        Ok(Value::String(out))
+    }
+
+    pub(super) fn zonepool_info(&mut self) -> SimResult {
+        let mut out = String::from("<ZONE_POOL>");
+        zone_xml(&mut out);
+        out.push_str("</ZONE_POOL>");
+        Ok(Value::String(out))
+    }
+
+    pub(super) fn zone_raftstatus(&mut self) -> SimResult {
+        let mut out = String::from("<RAFT>");
+        tag(&mut out, "SERVER_ID", -1);
+        tag(&mut out, "STATE", SOLO);
+        tag(&mut out, "TERM", 0);
+        tag(&mut out, "VOTEDFOR", -1);
+        tag(&mut out, "COMMIT", 0);
+        tag(&mut out, "LOG_INDEX", 0);
+        tag(&mut out, "LOG_TERM", -1);
+        tag(&mut out, "FEDLOG_INDEX", -1);
+        out.push_str("</RAFT>");
+        Ok(Value::String(out))
+    }
+}
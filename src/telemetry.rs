@@ -0,0 +1,214 @@
+//! The telemetry module instruments the calls of the controllers with `tracing` spans.
+//!
+//! Each call gets a `rpc_call` span with the XML-RPC method, the resource ID, the redacted
+//! arguments, then the duration, the success and the OpenNebula error code. The session string
+//! is never recorded. Batches sent with `system.multicall` get a `rpc_batch` span.
+//!
+//! With the `otel` feature, the spans also carry the OpenTelemetry conventions
+//! (`otel.name`, `otel.kind`, `rpc.system`, `otel.status_code`) so they're exported as client
+//! spans by a `tracing-opentelemetry` layer, as children of the current request trace.
+
+use std::time::Instant;
+
+use serde_xmlrpc::Value;
+use tracing::field::Empty;
+use tracing::Span;
+
+use crate::common::{methods, Errors};
+
+/// Creates the span of a call, `summary` is the redacted summary of the arguments
+pub(crate) fn call_span(method: &str, args: &[Value], summary: &str) -> Span {
+    #[cfg(not(feature = "otel"))]
+    let span = tracing::info_span!(
+        "rpc_call",
+        rpc.method = method,
+        resource_id = Empty,
+        args = summary,
+        duration_ms = Empty,
+        success = Empty,
+        error_code = Empty,
+    );
+    #[cfg(feature = "otel")]
+    let span = tracing::info_span!(
+        "rpc_call",
+        otel.name = method,
+        otel.kind = "client",
+        otel.status_code = Empty,
+        rpc.system = "xmlrpc",
+        rpc.method = method,
+        resource_id = Empty,
+        args = summary,
+        duration_ms = Empty,
+        success = Empty,
+        error_code = Empty,
+    );
+
+    if let Some(id) = methods::resource_id(method, args) {
+        span.record("resource_id", id);
+    }
+    span
+}
+
+/// Creates the span of a batch sent in a single `system.multicall` request
+pub(crate) fn batch_span(calls: &[(String, Vec<Value>)]) -> Span {
+    let methods = calls
+        .iter()
+        .map(|(method, _)| method.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    #[cfg(not(feature = "otel"))]
+    let span = tracing::info_span!(
+        "rpc_batch",
+        rpc.method = "system.multicall",
+        calls = calls.len(),
+        methods = methods.as_str(),
+        failed_calls = Empty,
+        duration_ms = Empty,
+        success = Empty,
+        error_code = Empty,
+    );
+    #[cfg(feature = "otel")]
+    let span = tracing::info_span!(
+        "rpc_batch",
+        otel.name = "system.multicall",
+        otel.kind = "client",
+        otel.status_code = Empty,
+        rpc.system = "xmlrpc",
+        rpc.method = "system.multicall",
+        calls = calls.len(),
+        methods = methods.as_str(),
+        failed_calls = Empty,
+        duration_ms = Empty,
+        success = Empty,
+        error_code = Empty,
+    );
+    span
+}
+
+/// Records the duration and the outcome of the call
+pub(crate) fn record_result<T>(span: &Span, start: Instant, result: &Result<T, Errors>) {
+    span.record("duration_ms", start.elapsed().as_millis() as u64);
+    span.record("success", result.is_ok());
+
+    if let Err(e) = result {
+        record_error(span, e);
+    }
+}
+
+/// Records the duration and the outcome of a batch, the batch fails if one of its calls fails
+pub(crate) fn record_results<T>(span: &Span, start: Instant, results: &[Result<T, Errors>]) {
+    span.record("duration_ms", start.elapsed().as_millis() as u64);
+
+    let errors = results
+        .iter()
+        .filter_map(|result| result.as_ref().err())
+        .collect::<Vec<_>>();
+    span.record("success", errors.is_empty());
+    span.record("failed_calls", errors.len());
+
+    if let Some(e) = errors.first() {
+        record_error(span, e);
+    }
+}
+
+fn record_error(span: &Span, e: &Errors) {
+    if let Some(code) = e.code() {
+        span.record("error_code", tracing::field::display(code));
+    }
+    #[cfg(feature = "otel")]
+    span.record("otel.status_code", "ERROR");
+
+    span.in_scope(|| tracing::debug!(error = %e, "call failed"));
+}
+
+/// Returns the OpenTelemetry trace ID of the current span, i.e. to log it with an error
+#[cfg(feature = "otel")]
+pub fn trace_id() -> Option<String> {
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let context = Span::current().context();
+    let span_context = context.span().span_context().clone();
+
+    if span_context.is_valid() {
+        Some(span_context.trace_id().to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fmt::Debug;
+    use std::sync::{Arc, Mutex};
+
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use super::*;
+    use crate::common::{ErrorCode, OpenNebulaError};
+    use crate::controller::Controller;
+    use crate::testing::fake::FakeCaller;
+
+    // collects the fields of the spans as "name=value"
+    #[derive(Clone, Default)]
+    struct Fields(Arc<Mutex<Vec<String>>>);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            let field = format!("{}={:?}", field.name(), value);
+            self.0.lock().unwrap().push(field);
+        }
+    }
+
+    impl Subscriber for Fields {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            span.record(&mut self.clone());
+            Id::from_u64(1)
+        }
+        fn record(&self, _: &Id, values: &Record<'_>) {
+            values.record(&mut self.clone());
+        }
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    // fails every call, the resources are locked
+    fn locked() -> FakeCaller {
+        FakeCaller::new(|name, _| {
+            Err(Errors::OpenNebula(OpenNebulaError {
+                code: ErrorCode::Locked,
+                message: "locked".to_string(),
+                method: name.to_string(),
+                args: String::new(),
+            }))
+        })
+    }
+
+    #[test]
+    fn call_span_fields() {
+        let fields = Fields::default();
+        let controller = Controller::new(locked());
+
+        tracing::subscriber::with_default(fields.clone(), || {
+            let result =
+                controller.call::<i32>("one.vm.update", vec![12.into(), "PASSWORD=secret".into()]);
+            assert!(result.is_err());
+        });
+
+        let fields = fields.0.lock().unwrap();
+        assert!(fields.contains(&"rpc.method=\"one.vm.update\"".to_string()));
+        assert!(fields.contains(&"resource_id=12".to_string()));
+        assert!(fields.contains(&"success=false".to_string()));
+        assert!(fields.contains(&"error_code=LOCKED".to_string()));
+        assert!(fields.iter().any(|field| field.starts_with("duration_ms=")));
+        assert!(!fields.iter().any(|field| field.contains("secret")));
+    }
+}
@@ -0,0 +1,387 @@
+//! The cassette module allows to record the XML-RPC calls made through a RPCCaller
+//! then to replay them, so the controllers can be tested without an OpenNebula frontend.
+//!
+//! A cassette is a JSON file containing, for each call, the method name, the arguments
+//! (without the session string) and the raw response body.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+use serde_json::{json, Map};
+use serde_xmlrpc::Value;
+
+use crate::common::Errors;
+#[cfg(feature = "async")]
+use crate::controller::AsyncRPCCaller;
+use crate::controller::RPCCaller;
+
+/// A recorded XML-RPC call
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interaction {
+    pub method: String,
+    pub args: Vec<Value>,
+    pub response: String,
+}
+
+/// A sequence of recorded calls
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    pub fn new() -> Self {
+        Cassette {
+            interactions: Vec::new(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Errors> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(content) => Self::from_json(&content),
+            Err(e) => Err(Errors::Roca(format!(
+                "can't read cassette {}: {}",
+                path.display(),
+                e
+            ))),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Errors> {
+        let path = path.as_ref();
+        match fs::write(path, self.to_json()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Errors::Roca(format!(
+                "can't write cassette {}: {}",
+                path.display(),
+                e
+            ))),
+        }
+    }
+
+    pub fn from_json(content: &str) -> Result<Self, Errors> {
+        let root: serde_json::Value = match serde_json::from_str(content) {
+            Ok(r) => r,
+            Err(e) => return Err(Errors::Roca(format!("invalid cassette: {}", e))),
+        };
+
+        let raw_interactions = match root.get("interactions").and_then(|i| i.as_array()) {
+            Some(i) => i,
+            None => {
+                return Err(Errors::Roca(
+                    "invalid cassette: missing interactions array".to_string(),
+                ))
+            }
+        };
+
+        let mut interactions = Vec::new();
+        for raw in raw_interactions {
+            let method = raw.get("method").and_then(|m| m.as_str());
+            let args = raw.get("args").and_then(|a| a.as_array());
+            let response = raw.get("response").and_then(|r| r.as_str());
+
+            match (method, args, response) {
+                (Some(method), Some(args), Some(response)) => {
+                    let mut values = Vec::new();
+                    for arg in args {
+                        values.push(json_to_value(arg)?);
+                    }
+                    interactions.push(Interaction {
+                        method: method.to_string(),
+                        args: values,
+                        response: response.to_string(),
+                    })
+                }
+                _ => {
+                    return Err(Errors::Roca(format!(
+                        "invalid cassette interaction: {}",
+                        raw
+                    )))
+                }
+            }
+        }
+
+        Ok(Cassette { interactions })
+    }
+
+    pub fn to_json(&self) -> String {
+        let interactions: Vec<serde_json::Value> = self
+            .interactions
+            .iter()
+            .map(|i| {
+                json!({
+                    "method": i.method,
+                    "args": i.args.iter().map(value_to_json).collect::<Vec<_>>(),
+                    "response": i.response,
+                })
+            })
+            .collect();
+
+        serde_json::to_string_pretty(&json!({ "interactions": interactions })).unwrap()
+    }
+}
+
+// XML-RPC values are stored with their type as key, i.e. {"i4": 12}.
+// OpenNebula doesn't use dateTime values, they're recorded for debugging but can't be replayed.
+fn value_to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Int(v) => json!({ "i4": v }),
+        Value::Int64(v) => json!({ "i8": v }),
+        Value::Bool(v) => json!({ "boolean": v }),
+        Value::String(v) => json!({ "string": v }),
+        Value::Double(v) => json!({ "double": v }),
+        Value::DateTime(v) => json!({ "dateTime.iso8601": format!("{:?}", v) }),
+        Value::Base64(v) => json!({ "base64": v }),
+        Value::Struct(members) => {
+            let mut map = Map::new();
+            for (k, v) in members {
+                map.insert(k.clone(), value_to_json(v));
+            }
+            json!({ "struct": map })
+        }
+        Value::Array(values) => {
+            json!({ "array": values.iter().map(value_to_json).collect::<Vec<_>>() })
+        }
+        Value::Nil => json!({ "nil": null }),
+    }
+}
+
+fn json_to_value(raw: &serde_json::Value) -> Result<Value, Errors> {
+    let invalid = || Errors::Roca(format!("invalid cassette value: {}", raw));
+
+    let (kind, content) = match raw.as_object() {
+        Some(obj) if obj.len() == 1 => obj.iter().next().unwrap(),
+        _ => return Err(invalid()),
+    };
+
+    let value = match kind.as_str() {
+        "i4" => match content.as_i64().map(i32::try_from) {
+            Some(Ok(v)) => Value::Int(v),
+            _ => return Err(invalid()),
+        },
+        "i8" => Value::Int64(content.as_i64().ok_or_else(invalid)?),
+        "boolean" => Value::Bool(content.as_bool().ok_or_else(invalid)?),
+        "string" => Value::String(content.as_str().ok_or_else(invalid)?.to_string()),
+        "double" => Value::Double(content.as_f64().ok_or_else(invalid)?),
+        "base64" => {
+            let mut bytes = Vec::new();
+            for b in content.as_array().ok_or_else(invalid)? {
+                match b.as_u64().map(u8::try_from) {
+                    Some(Ok(b)) => bytes.push(b),
+                    _ => return Err(invalid()),
+                }
+            }
+            Value::Base64(bytes)
+        }
+        "struct" => {
+            let mut members = BTreeMap::new();
+            for (k, v) in content.as_object().ok_or_else(invalid)? {
+                members.insert(k.clone(), json_to_value(v)?);
+            }
+            Value::Struct(members.into_iter().collect())
+        }
+        "array" => {
+            let mut values = Vec::new();
+            for v in content.as_array().ok_or_else(invalid)? {
+                values.push(json_to_value(v)?);
+            }
+            Value::Array(values)
+        }
+        "nil" => Value::Nil,
+        _ => return Err(invalid()),
+    };
+
+    Ok(value)
+}
+
+/// Recorder wraps a RPCCaller and records each successful call.
+/// Calls failing at the transport level are forwarded but not recorded.
+#[derive(Debug)]
+pub struct Recorder<C> {
+    inner: C,
+    cassette: Mutex<Cassette>,
+}
+
+impl<C> Recorder<C> {
+    pub fn new(inner: C) -> Self {
+        Recorder {
+            inner,
+            cassette: Mutex::new(Cassette::new()),
+        }
+    }
+
+    /// Returns a copy of the calls recorded so far
+    pub fn cassette(&self) -> Cassette {
+        self.cassette.lock().unwrap().clone()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Errors> {
+        self.cassette.lock().unwrap().save(path)
+    }
+
+    fn record(&self, name: &str, args: Vec<Value>, result: &Result<String, Errors>) {
+        if let Ok(response) = result {
+            self.cassette
+                .lock()
+                .unwrap()
+                .interactions
+                .push(Interaction {
+                    method: name.to_string(),
+                    args,
+                    response: response.clone(),
+                });
+        }
+    }
+}
+
+impl<C: RPCCaller> RPCCaller for Recorder<C> {
+    fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        let result = self.inner.call(name, args.clone());
+        self.record(name, args, &result);
+        result
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl<C: AsyncRPCCaller> AsyncRPCCaller for Recorder<C> {
+    async fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        let result = self.inner.call(name, args.clone()).await;
+        self.record(name, args, &result);
+        result
+    }
+}
+
+/// Replayer serves the responses of a cassette, in the recorded order.
+/// A call that doesn't match the next recorded method and arguments fails.
+#[derive(Debug)]
+pub struct Replayer {
+    cassette: Cassette,
+    position: Mutex<usize>,
+    match_args: bool,
+}
+
+impl Replayer {
+    pub fn new(cassette: Cassette) -> Self {
+        Replayer {
+            cassette,
+            position: Mutex::new(0),
+            match_args: true,
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Errors> {
+        Ok(Self::new(Cassette::load(path)?))
+    }
+
+    /// Only check the method names, not the arguments
+    pub fn ignore_args(mut self) -> Self {
+        self.match_args = false;
+        self
+    }
+
+    /// Number of recorded calls not replayed yet
+    pub fn remaining(&self) -> usize {
+        self.cassette.interactions.len() - *self.position.lock().unwrap()
+    }
+
+    fn replay(&self, name: &str, args: &[Value]) -> Result<String, Errors> {
+        let mut position = self.position.lock().unwrap();
+
+        let interaction = match self.cassette.interactions.get(*position) {
+            Some(i) => i,
+            None => {
+                return Err(Errors::Roca(format!(
+                    "replay: unexpected call {}, the cassette is over",
+                    name
+                )))
+            }
+        };
+
+        if interaction.method != name || (self.match_args && interaction.args != args) {
+            return Err(Errors::Roca(format!(
+                "replay: unexpected call {} {:?}, expected {} {:?}",
+                name, args, interaction.method, interaction.args
+            )));
+        }
+
+        *position += 1;
+        Ok(interaction.response.clone())
+    }
+}
+
+impl RPCCaller for Replayer {
+    fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        self.replay(name, &args)
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl AsyncRPCCaller for Replayer {
+    async fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        self.replay(name, &args)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_xmlrpc::Value;
+
+    use super::{Cassette, Recorder, Replayer};
+    use crate::common::Errors;
+    use crate::controller::RPCCaller;
+
+    struct Echo;
+
+    impl RPCCaller for Echo {
+        fn call(&self, name: &str, _args: Vec<Value>) -> Result<String, Errors> {
+            Ok(format!("response of {}", name))
+        }
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let recorder = Recorder::new(Echo);
+        recorder.call("one.vm.info", vec![12.into()]).unwrap();
+        recorder
+            .call(
+                "one.user.allocate",
+                vec!["name".into(), Value::Array(vec![1.into()]), Value::Nil],
+            )
+            .unwrap();
+
+        // serialize then deserialize the cassette
+        let cassette = Cassette::from_json(&recorder.cassette().to_json());
+        assert!(cassette.is_ok());
+        let cassette = cassette.unwrap();
+        assert_eq!(cassette, recorder.cassette());
+
+        let replayer = Replayer::new(cassette);
+        assert_eq!(replayer.remaining(), 2);
+
+        // wrong arguments
+        assert!(replayer.call("one.vm.info", vec![13.into()]).is_err());
+
+        let resp = replayer.call("one.vm.info", vec![12.into()]);
+        assert_eq!(resp.unwrap(), "response of one.vm.info");
+
+        // wrong method
+        assert!(replayer.call("one.vm.delete", vec![]).is_err());
+
+        let resp = replayer.call(
+            "one.user.allocate",
+            vec!["name".into(), Value::Array(vec![1.into()]), Value::Nil],
+        );
+        assert!(resp.is_ok());
+        assert_eq!(replayer.remaining(), 0);
+
+        // the cassette is over
+        assert!(replayer.call("one.vm.info", vec![12.into()]).is_err());
+    }
+}
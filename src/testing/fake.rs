@@ -0,0 +1,143 @@
+//! The fake module provides a RPCCaller answering the calls with a function, to test the code
+//! wrapping a client (i.e. the middlewares) without writing the exact responses of oned.
+//!
+//! ```ignore
+//! let oned = FakeCaller::new(|method, _args| match method {
+//!     "one.vm.info" => Ok(xmlrpc::success_response("<VM><ID>12</ID></VM>")),
+//!     _ => Err(Errors::Roca(format!("{} reached oned", method))),
+//! });
+//! ```
+
+use std::fmt::Debug;
+use std::sync::Mutex;
+
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+use serde_xmlrpc::Value;
+
+use crate::common::Errors;
+#[cfg(feature = "async")]
+use crate::controller::AsyncRPCCaller;
+use crate::controller::RPCCaller;
+
+/// The function answering a call, it receives the method and the arguments
+pub type Answer = Box<dyn Fn(&str, &[Value]) -> Result<String, Errors> + Send + Sync>;
+
+/// FakeCaller answers each call with a function and records the calls, in order
+pub struct FakeCaller {
+    answer: Answer,
+    batches: bool,
+    calls: Mutex<Vec<(String, Vec<Value>)>>,
+}
+
+impl Debug for FakeCaller {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FakeCaller")
+            .field("batches", &self.batches)
+            .field("calls", &self.calls)
+            .finish()
+    }
+}
+
+impl FakeCaller {
+    pub fn new<F>(answer: F) -> Self
+    where
+        F: Fn(&str, &[Value]) -> Result<String, Errors> + Send + Sync + 'static,
+    {
+        FakeCaller {
+            answer: Box::new(answer),
+            batches: false,
+            calls: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Answers the batches like a `system.multicall` call, by default they're sent call by call
+    pub fn with_batches(mut self) -> Self {
+        self.batches = true;
+        self
+    }
+
+    /// Returns the calls received so far, a batch is recorded as a `system.multicall` call
+    pub fn calls(&self) -> Vec<(String, Vec<Value>)> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Returns the methods of the calls received so far
+    pub fn methods(&self) -> Vec<String> {
+        self.calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(method, _)| method.clone())
+            .collect()
+    }
+
+    fn answer(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        let result = (self.answer)(name, &args);
+        self.calls.lock().unwrap().push((name.to_string(), args));
+        result
+    }
+
+    fn answer_batch(&self) -> Option<Result<String, Errors>> {
+        if self.batches {
+            Some(self.answer("system.multicall", Vec::new()))
+        } else {
+            None
+        }
+    }
+}
+
+impl RPCCaller for FakeCaller {
+    fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        self.answer(name, args)
+    }
+
+    fn multicall(&self, _calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        self.answer_batch()
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl AsyncRPCCaller for FakeCaller {
+    async fn call(&self, name: &str, args: Vec<Value>) -> Result<String, Errors> {
+        self.answer(name, args)
+    }
+
+    async fn multicall(&self, _calls: &[(String, Vec<Value>)]) -> Option<Result<String, Errors>> {
+        self.answer_batch()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FakeCaller;
+    use crate::common::Errors;
+    use crate::controller::RPCCaller;
+
+    #[test]
+    fn fake_caller() {
+        let oned = FakeCaller::new(|method, args| match method {
+            "one.vm.info" => Ok(format!("VM {:?}", args[0])),
+            _ => Err(Errors::Roca(format!("{} reached oned", method))),
+        });
+
+        assert_eq!(
+            oned.call("one.vm.info", vec![12.into()]).unwrap(),
+            "VM Int(12)"
+        );
+        assert!(oned.call("one.vm.delete", vec![12.into()]).is_err());
+        assert!(oned.multicall(&[]).is_none());
+        assert_eq!(
+            oned.calls(),
+            vec![
+                ("one.vm.info".to_string(), vec![12.into()]),
+                ("one.vm.delete".to_string(), vec![12.into()]),
+            ]
+        );
+
+        let oned = FakeCaller::new(|_, _| Ok("response".to_string())).with_batches();
+        assert_eq!(oned.multicall(&[]).unwrap().unwrap(), "response");
+        assert_eq!(oned.methods(), vec!["system.multicall"]);
+    }
+}
@@ -0,0 +1,8 @@
+//! The testing module provides tools to test code built on roca without an OpenNebula frontend.
+//!
+//! It's available with the `testing` cargo feature.
+
+pub mod cassette;
+pub mod fake;
+pub mod server;
+pub mod xmlrpc;
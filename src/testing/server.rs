@@ -0,0 +1,397 @@
+//! The server module provides a fake oned: an in-process HTTP server answering the XML-RPC calls
+//! with scripted responses, so the clients can be tested end-to-end without network.
+//!
+//! ```ignore
+//! use roca::prelude::*;
+//! use roca::testing::server::{FakeOned, Reply};
+//!
+//! let server = FakeOned::start().unwrap();
+//! server.reply("one.user.delete", Reply::id(5));
+//!
+//! let client = ClientXMLRPC::new("oneadmin:pass".to_string(), server.endpoint());
+//! let controller = Controller::new(client);
+//! assert!(controller.user(UserId(5)).delete().is_ok());
+//! assert_eq!(server.requests()[0].method, "one.user.delete");
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use serde_xmlrpc::Value;
+
+use crate::common::{ErrorCode, Errors};
+use crate::testing::xmlrpc;
+
+/// A scripted answer of the fake server
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    /// The (true, payload, 0) tuple, the payload being a body or an ID
+    Success(Value),
+    /// The (false, message, code) tuple
+    Failure(String, i32),
+    /// A non successful HTTP status with a text body
+    HTTPStatus(u16, String),
+    /// A raw body sent with a 200 status, i.e. some malformed XML
+    Raw(String),
+}
+
+impl Reply {
+    pub fn body(body: &str) -> Self {
+        Reply::Success(Value::String(body.to_string()))
+    }
+
+    pub fn id(id: i32) -> Self {
+        Reply::Success(Value::Int(id))
+    }
+
+    pub fn failure(message: &str, code: i32) -> Self {
+        Reply::Failure(message.to_string(), code)
+    }
+
+    fn status(&self) -> u16 {
+        match self {
+            Reply::HTTPStatus(code, _) => *code,
+            _ => 200,
+        }
+    }
+
+    // the result of a call inside a system.multicall request
+    fn to_value(&self) -> Value {
+        match self {
+            Reply::Success(payload) => xmlrpc::success_value(payload.clone()),
+            Reply::Failure(message, code) => xmlrpc::failure_value(message, *code),
+            Reply::HTTPStatus(_, body) | Reply::Raw(body) => xmlrpc::fault_value(-1, body),
+        }
+    }
+
+    fn to_body(&self) -> String {
+        match self {
+            Reply::Success(payload) => xmlrpc::success_response(payload.clone()),
+            Reply::Failure(message, code) => xmlrpc::failure_response(message, *code),
+            Reply::HTTPStatus(_, body) | Reply::Raw(body) => body.clone(),
+        }
+    }
+}
+
+/// A request received by the fake server
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    /// The XML-RPC request, session string included
+    pub body: String,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // one shot replies, used before the default ones
+    queued: HashMap<String, VecDeque<Reply>>,
+    defaults: HashMap<String, Reply>,
+    requests: Vec<Request>,
+}
+
+impl State {
+    fn is_scripted(&self, method: &str) -> bool {
+        self.defaults.contains_key(method) || self.queued.get(method).is_some_and(|q| !q.is_empty())
+    }
+
+    fn next_reply(&mut self, method: &str) -> Reply {
+        if let Some(reply) = self.queued.get_mut(method).and_then(|q| q.pop_front()) {
+            return reply;
+        }
+        match self.defaults.get(method) {
+            Some(reply) => reply.clone(),
+            None => Reply::failure(
+                &format!("[{}] no reply scripted for this method", method),
+                ErrorCode::XMLRPCAPI.value(),
+            ),
+        }
+    }
+}
+
+/// Fake oned listening on a random local port, stopped when dropped
+#[derive(Debug)]
+pub struct FakeOned {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl FakeOned {
+    pub fn start() -> Result<Self, Errors> {
+        let listener = match TcpListener::bind("127.0.0.1:0") {
+            Ok(l) => l,
+            Err(e) => return Err(Errors::Roca(format!("fake oned: can't listen: {}", e))),
+        };
+        let addr = match listener.local_addr() {
+            Ok(a) => a,
+            Err(e) => return Err(Errors::Roca(format!("fake oned: no local address: {}", e))),
+        };
+
+        let state = Arc::new(Mutex::new(State::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let state = state.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        // a broken connection only fails the call on the client side
+                        let _ = handle_connection(stream, &state);
+                    }
+                }
+            })
+        };
+
+        Ok(FakeOned {
+            addr,
+            state,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// The XML-RPC endpoint to give to the client
+    pub fn endpoint(&self) -> String {
+        format!("http://{}/RPC2", self.addr)
+    }
+
+    /// Sets the reply sent to each call of the method
+    pub fn reply(&self, method: &str, reply: Reply) -> &Self {
+        let mut state = self.state.lock().unwrap();
+        state.defaults.insert(method.to_string(), reply);
+        self
+    }
+
+    /// Queues a reply sent once, before the one set with `reply`
+    pub fn reply_once(&self, method: &str, reply: Reply) -> &Self {
+        let mut state = self.state.lock().unwrap();
+        state
+            .queued
+            .entry(method.to_string())
+            .or_default()
+            .push_back(reply);
+        self
+    }
+
+    /// Returns the requests received so far, in order
+    pub fn requests(&self) -> Vec<Request> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl Drop for FakeOned {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // wake up the accept loop
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Handles a single request then closes the connection
+fn handle_connection(stream: TcpStream, state: &Mutex<State>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    let body = String::from_utf8_lossy(&body).into_owned();
+
+    let method = method_name(&body).unwrap_or_default();
+    let reply = {
+        let mut state = state.lock().unwrap();
+        // the calls of a batch are answered with their own replies,
+        // unless a reply is scripted for system.multicall itself
+        let reply = if method == "system.multicall" && !state.is_scripted(&method) {
+            let results = multicall_methods(&body)
+                .iter()
+                .map(|m| state.next_reply(m).to_value())
+                .collect();
+            Reply::Raw(xmlrpc::multicall_response(results))
+        } else {
+            state.next_reply(&method)
+        };
+        state.requests.push(Request {
+            method: method.clone(),
+            body,
+        });
+        reply
+    };
+
+    let content = reply.to_body();
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: text/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        reply.status(),
+        if reply.status() == 200 { "OK" } else { "Error" },
+        content.len(),
+        content
+    )?;
+    stream.flush()
+}
+
+fn method_name(body: &str) -> Option<String> {
+    let start = body.find("<methodName>")? + "<methodName>".len();
+    let end = body[start..].find("</methodName>")? + start;
+    Some(body[start..end].trim().to_string())
+}
+
+// Returns the methods of the calls of a system.multicall request, in order
+fn multicall_methods(body: &str) -> Vec<String> {
+    let mut methods = Vec::new();
+    let mut rest = body;
+    while let Some(start) = rest.find("<name>methodName</name>") {
+        rest = &rest[start + "<name>methodName</name>".len()..];
+        let value = match (rest.find("<value>"), rest.find("</value>")) {
+            (Some(start), Some(end)) if start < end => &rest[start + "<value>".len()..end],
+            _ => break,
+        };
+        let value = value
+            .trim()
+            .trim_start_matches("<string>")
+            .trim_end_matches("</string>");
+        methods.push(value.trim().to_string());
+    }
+    methods
+}
+
+#[cfg(all(test, feature = "client", feature = "blocking"))]
+mod test {
+    use super::*;
+    use crate::client::ClientXMLRPC;
+    use crate::controller::RPCCaller;
+
+    fn client(server: &FakeOned) -> ClientXMLRPC {
+        ClientXMLRPC::new(String::from("oneadmin:pDi4mFBHue"), server.endpoint())
+    }
+
+    #[test]
+    fn scripted_replies() {
+        let server = FakeOned::start().unwrap();
+        server
+            .reply("one.vm.info", Reply::body("<VM><ID>12</ID></VM>"))
+            .reply_once(
+                "one.vm.info",
+                Reply::failure("[one.vm.info] locked", 0x8000),
+            );
+
+        let client = client(&server);
+
+        let resp = client.call("one.vm.info", vec![12.into()]).unwrap();
+        assert!(resp.contains("<boolean>0</boolean>"));
+        assert!(resp.contains("<i4>32768</i4>"));
+
+        let resp = client.call("one.vm.info", vec![12.into()]).unwrap();
+        assert!(resp.contains("&lt;VM&gt;&lt;ID&gt;12&lt;/ID&gt;&lt;/VM&gt;"));
+
+        // unknown method
+        let resp = client.call("one.vm.unknown", vec![]).unwrap();
+        assert!(resp.contains("<i4>4096</i4>"));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].method, "one.vm.info");
+        assert_eq!(requests[2].method, "one.vm.unknown");
+    }
+
+    #[test]
+    fn multicall_replies() {
+        let server = FakeOned::start().unwrap();
+        server
+            .reply("one.vm.info", Reply::body("<VM><ID>12</ID></VM>"))
+            .reply_once(
+                "one.vm.info",
+                Reply::failure("[one.vm.info] locked", 0x8000),
+            );
+
+        let client = client(&server);
+
+        let calls = vec![
+            ("one.vm.info".to_string(), vec![12.into()]),
+            ("one.vm.info".to_string(), vec![12.into()]),
+        ];
+        let resp = client.multicall(&calls).unwrap().unwrap();
+        assert_eq!(
+            resp,
+            xmlrpc::multicall_response(vec![
+                xmlrpc::failure_value("[one.vm.info] locked", 0x8000),
+                xmlrpc::success_value("<VM><ID>12</ID></VM>"),
+            ])
+        );
+
+        // scripted replies of system.multicall take precedence
+        server.reply_once("system.multicall", Reply::HTTPStatus(404, String::new()));
+        assert!(matches!(
+            client.multicall(&calls),
+            Some(Err(Errors::HTTPStatus(404, _)))
+        ));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, "system.multicall");
+    }
+
+    #[test]
+    fn http_errors() {
+        let server = FakeOned::start().unwrap();
+        server
+            .reply(
+                "one.user.info",
+                Reply::HTTPStatus(503, "unavailable".to_string()),
+            )
+            .reply(
+                "one.vm.info",
+                Reply::Raw("<methodResponse><params>".to_string()),
+            );
+
+        let client = client(&server);
+
+        match client.call("one.user.info", vec![0.into()]) {
+            Err(Errors::HTTPStatus(code, body)) => {
+                assert_eq!(code, 503);
+                assert_eq!(body, "unavailable");
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        let resp = client.call("one.vm.info", vec![0.into()]);
+        assert_eq!(resp.unwrap(), "<methodResponse><params>");
+
+        // the server is stopped
+        let endpoint = server.endpoint();
+        drop(server);
+        let client = ClientXMLRPC::new(String::from("oneadmin:pDi4mFBHue"), endpoint);
+        assert!(matches!(
+            client.call("one.user.info", vec![0.into()]),
+            Err(Errors::Connect(_))
+        ));
+    }
+}
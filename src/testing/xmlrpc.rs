@@ -0,0 +1,165 @@
+//! The xmlrpc module writes the XML-RPC responses in the same format as oned
+
+use std::collections::BTreeMap;
+
+use serde_xmlrpc::Value;
+
+const BASE64_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Builds the response of a successful call, `payload` is either a body or an ID
+pub fn success_response<V: Into<Value>>(payload: V) -> String {
+    response(&success_value(payload))
+}
+
+/// Builds the response of a failed call, with an OpenNebula error code
+pub fn failure_response(message: &str, code: i32) -> String {
+    response(&failure_value(message, code))
+}
+
+/// Builds the response of a `system.multicall` request from the result of each call,
+/// see `success_value`, `failure_value` and `fault_value`
+pub fn multicall_response(results: Vec<Value>) -> String {
+    let results = results
+        .into_iter()
+        .map(|result| match result {
+            // the faults aren't wrapped
+            Value::Struct(_) => result,
+            _ => Value::Array(vec![result]),
+        })
+        .collect();
+    response(&Value::Array(results))
+}
+
+/// The (true, payload, 0) tuple
+pub fn success_value<V: Into<Value>>(payload: V) -> Value {
+    Value::Array(vec![Value::Bool(true), payload.into(), Value::Int(0)])
+}
+
+/// The (false, message, code) tuple
+pub fn failure_value(message: &str, code: i32) -> Value {
+    Value::Array(vec![
+        Value::Bool(false),
+        Value::String(message.to_string()),
+        Value::Int(code),
+    ])
+}
+
+/// The XML-RPC fault of a call of a `system.multicall` request
+pub fn fault_value(code: i32, message: &str) -> Value {
+    let mut members = BTreeMap::new();
+    members.insert("faultCode".to_string(), Value::Int(code));
+    members.insert(
+        "faultString".to_string(),
+        Value::String(message.to_string()),
+    );
+    Value::Struct(members)
+}
+
+/// Builds a response containing a single value
+pub fn response(value: &Value) -> String {
+    let mut out = String::from("<?xml version=\"1.0\"?>\n<methodResponse><params><param>");
+    write_value(&mut out, value);
+    out.push_str("</param></params></methodResponse>\n");
+    out
+}
+
+/// Appends the XML representation of the value
+pub fn write_value(out: &mut String, value: &Value) {
+    out.push_str("<value>");
+    match value {
+        Value::Int(v) => out.push_str(&format!("<i4>{}</i4>", v)),
+        Value::Int64(v) => out.push_str(&format!("<i8>{}</i8>", v)),
+        Value::Bool(v) => out.push_str(&format!("<boolean>{}</boolean>", *v as u8)),
+        Value::String(v) => {
+            out.push_str("<string>");
+            out.push_str(&escape(v));
+            out.push_str("</string>");
+        }
+        Value::Double(v) => out.push_str(&format!("<double>{}</double>", v)),
+        // OpenNebula never sends dates
+        Value::DateTime(_) => out.push_str("<nil/>"),
+        Value::Base64(v) => {
+            out.push_str("<base64>");
+            out.push_str(&base64(v));
+            out.push_str("</base64>");
+        }
+        Value::Struct(members) => {
+            out.push_str("<struct>");
+            for (name, v) in members {
+                out.push_str("<member><name>");
+                out.push_str(&escape(name));
+                out.push_str("</name>");
+                write_value(out, v);
+                out.push_str("</member>");
+            }
+            out.push_str("</struct>");
+        }
+        Value::Array(values) => {
+            out.push_str("<array><data>");
+            for v in values {
+                write_value(out, v);
+            }
+            out.push_str("</data></array>");
+        }
+        Value::Nil => out.push_str("<nil/>"),
+    }
+    out.push_str("</value>");
+}
+
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn base64(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_CHARS[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write_responses() {
+        assert_eq!(
+            success_response(12),
+            "<?xml version=\"1.0\"?>\n<methodResponse><params><param><value><array><data>\
+             <value><boolean>1</boolean></value><value><i4>12</i4></value><value><i4>0</i4></value>\
+             </data></array></value></param></params></methodResponse>\n"
+        );
+        assert!(success_response("<USER><ID>0</ID></USER>")
+            .contains("<string>&lt;USER&gt;&lt;ID&gt;0&lt;/ID&gt;&lt;/USER&gt;</string>"));
+        assert!(
+            failure_response("[one.vm.info] Error getting virtual machine [12].", 0x400)
+                .contains("<value><boolean>0</boolean></value>")
+        );
+
+        let resp = multicall_response(vec![success_value(12), fault_value(-1, "no method")]);
+        assert!(
+            resp.contains("<array><data><value><array><data><value><boolean>1</boolean></value>")
+        );
+        assert!(resp.contains("<member><name>faultCode</name><value><i4>-1</i4></value></member>"));
+
+        let mut out = String::new();
+        write_value(&mut out, &Value::Base64(b"roca!".to_vec()));
+        assert_eq!(out, "<value><base64>cm9jYSE=</base64></value>");
+    }
+}
@@ -2,31 +2,68 @@
 
 use std::fmt::Display;
 
-use crate::common::parameters::UpdateType;
+use crate::common::ids::{GroupId, Select, UserId};
+#[cfg(feature = "async")]
+use crate::common::operations::{AsyncDelete, AsyncInfo, AsyncUpdate};
+use crate::common::operations::{Delete, Info, ResourceKind, Update};
 use crate::common::resource_getters::GetGroup;
 use crate::common::template::builder as template;
-use crate::common::Errors;
+use crate::common::{value, Errors};
+#[cfg(feature = "async")]
+use crate::controller::{AsyncController, AsyncRPCCaller};
 use crate::controller::{Controller, RPCCaller};
-use crate::{define_resource, rpc_noparam_method};
+use crate::{define_resource, resource_controller, rpc_methods};
 
 use crate::common::xml::resource::Resource;
-use crate::common::xml::shared_getters::BaseGetters;
 
 #[derive(Debug)]
-pub struct UserController<'a, C: RPCCaller> {
-    pub controller: &'a Controller<C>,
-    pub id: i32,
+pub struct UserController<C: RPCCaller> {
+    pub controller: Controller<C>,
+    pub id: UserId,
 }
 
 #[derive(Debug)]
-pub struct UsersController<'a, C: RPCCaller> {
-    pub controller: &'a Controller<C>,
+pub struct UsersController<C: RPCCaller> {
+    pub controller: Controller<C>,
 }
 
-define_resource!(User);
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncUserController<C: AsyncRPCCaller> {
+    pub controller: AsyncController<C>,
+    pub id: UserId,
+}
+
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncUsersController<C: AsyncRPCCaller> {
+    pub controller: AsyncController<C>,
+}
+
+define_resource!(User, UserId);
 
 impl GetGroup for User {}
 
+resource_controller!(UserController, ResourceKind::User);
+
+impl<C: RPCCaller> Info for UserController<C> {
+    type Output = User;
+}
+impl<C: RPCCaller> Delete for UserController<C> {}
+impl<C: RPCCaller> Update for UserController<C> {}
+
+#[cfg(feature = "async")]
+resource_controller!(async AsyncUserController, ResourceKind::User);
+
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncInfo for AsyncUserController<C> {
+    type Output = User;
+}
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncDelete for AsyncUserController<C> {}
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncUpdate for AsyncUserController<C> {}
+
 //https://docs.opennebula.io/6.4/installation_and_configuration/authentication/overview.html
 // or look at the opennebula terraform provider code
 
@@ -36,109 +73,106 @@ impl GetGroup for User {}
 //    x509,
 //}
 
-// TODOs:
-// - enum form auth_drv options
-// - more helpers without some options (i.e groups, auth_drv)
-// - add last parameter: array of groups. Currently the client only support Array(Vec<Value>)
-impl<'a, C: RPCCaller> UsersController<'a, C> {
-    pub fn allocate(&self, name: &str, passwd: &str, auth_drv: &str) -> Result<i32, Errors> {
-        let resp_txt = self.controller.client.call(
-            "one.user.allocate",
-            vec![name.into(), passwd.into(), auth_drv.into()],
-        )?;
-
-        let id = self.controller.parse_id_resp(resp_txt)?;
-
-        Ok(id)
+/// The default quotas, applied to the users without specific quotas
+#[derive(Clone)]
+pub struct DefaultQuotas {
+    resource: Resource,
+}
+
+impl XMLDocGetters for DefaultQuotas {
+    fn get_internal(&self) -> (&Document, &Element) {
+        (&self.resource.document, &self.resource.root)
     }
 }
 
-impl<'a, C: RPCCaller> UserController<'a, C> {
-    //pub fn delete(&self) -> Result<(), Errors> {
-    //    let (success, err) = self
-    //        .controller
-    //        .client
-    //        .call("one.user.delete", vec![self.id.into()])?;
-    //
-    //    self.controller.parse_resp(resp_txt)
-    //}
-    rpc_noparam_method!(delete, "one.user.delete");
-
-    pub fn info(&self) -> Result<User, Errors> {
-        let resp_txt = self
-            .controller
-            .client
-            .call("one.user.info", vec![self.id.into()])?;
-
-        let body = self.controller.parse_body_resp(resp_txt)?;
-        match Resource::try_from(body.as_str()) {
-            Ok(resource) => Ok(User { resource }),
-            Err(e) => Err(Errors::Roca(format!("Failed to parse the resource: {}", e))),
-        }
+impl Display for DefaultQuotas {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.resource.document.write_str().unwrap())
     }
+}
 
-    /// Updates adds user content
-    /// * tpl: The new user contents. Syntax can be the usual attribute=value or XML.
-    /// * policy: Update type: 0: Replace the whole template. 1: Merge new template with the existing one.
-    pub fn update(&self, tpl: template::Builder, policy: UpdateType) -> Result<(), Errors> {
-        let resp_txt = self.controller.client.call(
-            "one.user.update",
-            vec![
-                self.id.into(),
-                tpl.to_string().into(),
-                policy.value().into(),
-            ],
-        )?;
-
-        self.controller.parse_id_resp(resp_txt)?;
-
-        Ok(())
+fn build_default_quotas(body: &str) -> Result<DefaultQuotas, Errors> {
+    match Resource::try_from(body) {
+        Ok(resource) => Ok(DefaultQuotas { resource }),
+        Err(e) => Err(Errors::Roca(format!("Failed to parse the quotas: {}", e))),
     }
+}
 
-    pub fn passwd(&self, new_passd: i32) -> Result<(), Errors> {
-        let resp_txt = self
-            .controller
-            .client
-            .call("one.user.passwd", vec![self.id.into(), new_passd.into()])?;
+// TODOs:
+// - enum form auth_drv options
+// - more helpers without some options (i.e auth_drv)
+rpc_methods! {
+    UsersController, AsyncUsersController;
+
+    fn allocate(&self, name: &str, passwd: &str, auth_drv: &str) -> UserId
+        => call(self.controller, "one.user.allocate", [name, passwd, auth_drv]);
+
+    /// Allocates a user belonging to the groups, the first one is the primary group
+    fn allocate_with_groups(
+        &self,
+        name: &str,
+        passwd: &str,
+        auth_drv: &str,
+        groups: &[GroupId],
+    ) -> UserId => call(
+        self.controller,
+        "one.user.allocate",
+        [name, passwd, auth_drv, value::array(groups.iter().copied())]
+    );
+
+    /// Returns the default user quotas
+    fn default_quota(&self) -> DefaultQuotas
+        => call(self.controller, "one.userquota.info", []) | build_default_quotas;
+
+    /// Updates the default user quotas
+    /// * tpl: the new quota template, i.e. VM=[ CPU=4, MEMORY=8192 ]
+    fn update_default_quota(&self, tpl: template::Builder) -> DefaultQuotas
+        => call(self.controller, "one.userquota.update", [tpl.to_string()]) | build_default_quotas;
+}
 
-        self.controller.parse_resp(resp_txt)
-    }
+rpc_methods! {
+    UserController, AsyncUserController;
 
-    pub fn login(&self, name: &str, token: &str, period: i32, gid: i32) -> Result<String, Errors> {
-        let resp_txt = self.controller.client.call(
-            "one.user.login",
-            vec![name.into(), token.into(), period.into(), gid.into()],
-        )?;
+    fn passwd(&self, new_passd: &str)
+        => call(self.controller, "one.user.passwd", [self.id, new_passd]);
 
-        self.controller.parse_body_resp(resp_txt)
-    }
+    /// Sets the user quotas
+    /// * tpl: the quota template, i.e. VM=[ CPU=4, MEMORY=8192 ]
+    fn quota(&self, tpl: template::Builder)
+        => call(self.controller, "one.user.quota", [self.id, tpl.to_string()]);
+
+    /// Generates a login token, valid in the group gid or in any group
+    fn login(&self, name: &str, token: &str, period: i32, gid: Select<GroupId>) -> String
+        => call(self.controller, "one.user.login", [name, token, period, gid]);
 }
 
 #[cfg(test)]
 mod test {
 
     use super::*;
-    use crate::client::ClientXMLRPC;
+    use crate::common::xml::shared_getters::BaseGetters;
+    use crate::testing::cassette::Replayer;
 
     #[test]
     fn user_info() {
-        let client = ClientXMLRPC::new(
-            String::from("oneadmin:pDi4mFBHue"),
-            String::from("http://192.168.33.10:2633/RPC2"),
-        );
+        let client = Replayer::from_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/cassettes/user_info.json"
+        ))
+        .unwrap();
         let controller = Controller::new(client);
-        let user_controller = controller.user(0);
+        let user_controller = controller.user(UserId(0));
 
         match user_controller.info() {
             Ok(infos) => {
                 assert!(infos.id().is_ok());
-                assert_eq!(infos.id().unwrap(), 0);
+                assert_eq!(infos.id().unwrap(), UserId(0));
 
                 assert!(infos.name().is_ok());
                 assert_eq!(infos.name().unwrap(), "oneadmin");
 
                 assert!(infos.gid().is_ok());
-                assert_eq!(infos.gid().unwrap(), 0);
+                assert_eq!(infos.gid().unwrap(), GroupId(0));
 
                 assert!(infos.groupname().is_ok());
                 assert_eq!(infos.groupname().unwrap(), "oneadmin");
@@ -148,14 +182,16 @@ mod test {
             }
             Err(e) => panic!("Error on user info: {}", e),
         }
+        assert_eq!(controller.client.remaining(), 0);
     }
 
     #[test]
     fn user_allocate_delete() {
-        let client = ClientXMLRPC::new(
-            String::from("oneadmin:pDi4mFBHue"),
-            String::from("http://192.168.33.10:2633/RPC2"),
-        );
+        let client = Replayer::from_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/cassettes/user_allocate_delete.json"
+        ))
+        .unwrap();
 
         // Create the user
         let controller = Controller::new(client);
@@ -164,21 +200,23 @@ mod test {
 
         assert!(allocate_response.is_ok());
         let user_id = allocate_response.unwrap();
-        assert!(user_id > 0);
+        assert!(user_id.0 > 0);
 
         let ucontroller = controller.user(user_id);
 
         // Delete the user
         let delete_response = ucontroller.delete();
         assert!(delete_response.is_ok());
+        assert_eq!(controller.client.remaining(), 0);
     }
 
     #[test]
     fn user_login() {
-        let client = ClientXMLRPC::new(
-            String::from("oneadmin:pDi4mFBHue"),
-            String::from("http://192.168.33.10:2633/RPC2"),
-        );
+        let client = Replayer::from_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/cassettes/user_login.json"
+        ))
+        .unwrap();
         let controller = Controller::new(client);
 
         // Create the user
@@ -186,16 +224,69 @@ mod test {
         let allocate_response = controller.users().allocate(name, "password", "core");
         assert!(allocate_response.is_ok());
         let user_id = allocate_response.unwrap();
-        assert!(user_id > 0);
+        assert!(user_id.0 > 0);
 
         let ucontroller = controller.user(user_id);
 
         // Test loging
-        let login_response = ucontroller.login(name, "password", 60, 0);
+        let login_response = ucontroller.login(name, "password", 60, Select::Id(GroupId(0)));
         assert!(login_response.is_ok());
 
         // Delete the user
         let delete_response = ucontroller.delete();
         assert!(delete_response.is_ok());
+        assert_eq!(controller.client.remaining(), 0);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_user_info() {
+        let client = Replayer::from_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/cassettes/user_info.json"
+        ))
+        .unwrap();
+        let controller = AsyncController::new(client);
+        let user_controller = controller.user(UserId(0));
+
+        match user_controller.info().await {
+            Ok(infos) => {
+                assert!(infos.id().is_ok());
+                assert_eq!(infos.id().unwrap(), UserId(0));
+
+                assert!(infos.name().is_ok());
+                assert_eq!(infos.name().unwrap(), "oneadmin");
+            }
+            Err(e) => panic!("Error on user info: {}", e),
+        }
+        assert_eq!(controller.client.remaining(), 0);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_user_login() {
+        let client = Replayer::from_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/cassettes/user_login.json"
+        ))
+        .unwrap();
+        let controller = AsyncController::new(client);
+
+        let name = "test-login4";
+        let user_id = controller
+            .users()
+            .allocate(name, "password", "core")
+            .await
+            .unwrap();
+        assert!(user_id.0 > 0);
+
+        let ucontroller = controller.user(user_id);
+        let login_response = ucontroller
+            .login(name, "password", 60, Select::Id(GroupId(0)))
+            .await;
+        assert!(login_response.is_ok());
+
+        assert!(ucontroller.delete().await.is_ok());
+        assert_eq!(controller.client.remaining(), 0);
     }
 }
@@ -2,41 +2,98 @@
 
 use std::fmt::Display;
 
+use crate::common::ids::{
+    Change, DatastoreId, DiskId, HostId, ImageId, NicId, SecurityGroupId, Select, SnapshotId, VmId,
+};
+#[cfg(feature = "async")]
+use crate::common::operations::{
+    AsyncChmod, AsyncChown, AsyncInfo, AsyncLock, AsyncRename, AsyncUpdate,
+};
+use crate::common::operations::{Chmod, Chown, Info, Lock, Rename, ResourceKind, Update};
 use crate::common::parameters::UpdateType;
-use crate::common::permissions::{Permissions, PermissionsBits};
 use crate::common::resource_getters::{GetGroup, GetOwner, GetPermissions};
-use crate::common::Errors;
+#[cfg(feature = "async")]
+use crate::controller::{AsyncController, AsyncRPCCaller};
 use crate::controller::{Controller, RPCCaller};
-use crate::rpc_chmod_method;
 
 use crate::common::xml::shared_getters::BaseGetters;
 use crate::common::xml::template::Template;
-use crate::{common::xml::resource::Resource, define_resource};
+use crate::{common::xml::resource::Resource, define_resource, resource_controller, rpc_methods};
 
 #[derive(Debug)]
-pub struct VirtualMachineController<'a, C: RPCCaller> {
-    pub controller: &'a Controller<C>,
-    pub id: i32,
+pub struct VirtualMachineController<C: RPCCaller> {
+    pub controller: Controller<C>,
+    pub id: VmId,
 }
 
 #[derive(Debug)]
-pub struct VMDiskController<'a, C: RPCCaller> {
-    pub vm_controller: &'a VirtualMachineController<'a, C>,
-    pub id: i32,
+pub struct VMDiskController<C: RPCCaller> {
+    pub vm_controller: VirtualMachineController<C>,
+    pub id: DiskId,
 }
 
 #[derive(Debug)]
-pub struct VMNICController<'a, C: RPCCaller> {
-    pub vm_controller: &'a VirtualMachineController<'a, C>,
-    pub id: i32,
+pub struct VMNICController<C: RPCCaller> {
+    pub vm_controller: VirtualMachineController<C>,
+    pub id: NicId,
 }
 
-define_resource!(VirtualMachine);
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncVirtualMachineController<C: AsyncRPCCaller> {
+    pub controller: AsyncController<C>,
+    pub id: VmId,
+}
+
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncVMDiskController<C: AsyncRPCCaller> {
+    pub vm_controller: AsyncVirtualMachineController<C>,
+    pub id: DiskId,
+}
+
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncVMNICController<C: AsyncRPCCaller> {
+    pub vm_controller: AsyncVirtualMachineController<C>,
+    pub id: NicId,
+}
+
+define_resource!(VirtualMachine, VmId);
 
 impl GetGroup for VirtualMachine {}
 impl GetOwner for VirtualMachine {}
 impl GetPermissions for VirtualMachine {}
 
+resource_controller!(VirtualMachineController, ResourceKind::VirtualMachine);
+
+impl<C: RPCCaller> Info for VirtualMachineController<C> {
+    type Output = VirtualMachine;
+}
+impl<C: RPCCaller> Chown for VirtualMachineController<C> {}
+impl<C: RPCCaller> Chmod for VirtualMachineController<C> {}
+impl<C: RPCCaller> Rename for VirtualMachineController<C> {}
+impl<C: RPCCaller> Update for VirtualMachineController<C> {}
+impl<C: RPCCaller> Lock for VirtualMachineController<C> {}
+
+#[cfg(feature = "async")]
+resource_controller!(async AsyncVirtualMachineController, ResourceKind::VirtualMachine);
+
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncInfo for AsyncVirtualMachineController<C> {
+    type Output = VirtualMachine;
+}
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncChown for AsyncVirtualMachineController<C> {}
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncChmod for AsyncVirtualMachineController<C> {}
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncRename for AsyncVirtualMachineController<C> {}
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncUpdate for AsyncVirtualMachineController<C> {}
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncLock for AsyncVirtualMachineController<C> {}
+
 // Shared behavior between VirtualMachine and VirtualMachinePool
 pub trait VMShared: XMLDocGetters {
     /// Allow to retrieve the user template section of the VM
@@ -96,274 +153,130 @@ impl Display for Action {
     }
 }
 
-impl<'a, C: RPCCaller> VirtualMachineController<'a, C> {
-    // TODO: add a method per action ?
-    /// Action is the generic method to run any action on the VM
-    pub fn action(&self, action: Action) -> Result<(), Errors> {
-        let resp_txt = self.controller.client.call(
-            "one.vm.action",
-            vec![action.to_string().into(), self.id.into()],
-        )?;
-
-        self.controller.parse_id_resp(resp_txt)?;
-
-        Ok(())
-    }
-
-    /// Deploy in the selected hostID and/or dsID. Enforce to return error in case of
-    /// overcommitment. Enforce is automatically enabled for non-oneadmin users.
-    /// Set dsID to -1 to let OpenNebula choose the datastore.
-    pub fn deploy(&self, host_id: i32, enforce: bool, ds_id: i32) -> Result<(), Errors> {
-        let resp_txt = self.controller.client.call(
-            "one.vm.deploy",
-            vec![self.id.into(), host_id.into(), enforce.into(), ds_id.into()],
-        )?;
-
-        self.controller.parse_resp(resp_txt)
-    }
-
-    /// Migrate a VM to a target host and/or to another ds
-    pub fn migrate(
-        &self,
-        host_id: i32,
-        live: bool,
-        enforce: bool,
-        ds_id: i32,
-        migration_type: i32,
-    ) -> Result<(), Errors> {
-        let resp_txt = self.controller.client.call(
-            "one.vm.migrate",
-            vec![
-                self.id.into(),
-                host_id.into(),
-                live.into(),
-                enforce.into(),
-                ds_id.into(),
-                migration_type.into(),
-            ],
-        )?;
-
-        self.controller.parse_resp(resp_txt)
-    }
-
-    /// Attach a new disk
-    pub fn disk_attach(&self, disk_template: &str) -> Result<(), Errors> {
-        let resp_txt = self
-            .controller
-            .client
-            .call("one.vm.attach", vec![self.id.into(), disk_template.into()])?;
-
-        self.controller.parse_resp(resp_txt)
-    }
-
+impl<C: RPCCaller> VirtualMachineController<C> {
     /// Return a disk controller allowing to manage the disk
-    pub fn disk(&'a self, disk_id: i32) -> VMDiskController<'a, C> {
+    pub fn disk(&self, disk_id: DiskId) -> VMDiskController<C> {
         VMDiskController {
-            vm_controller: self,
+            vm_controller: VirtualMachineController {
+                controller: self.controller.clone(),
+                id: self.id,
+            },
             id: disk_id,
         }
     }
 
-    /// Attach a new NIC
-    pub fn nic_attach(&self, nic_template: &str) -> Result<(), Errors> {
-        let resp_txt = self.controller.client.call(
-            "one.vm.attachnic",
-            vec![self.id.into(), nic_template.into()],
-        )?;
-
-        self.controller.parse_resp(resp_txt)
-    }
-
     /// Return a nic controller allowing to manage the disk
-    pub fn nic(&'a self, nic_id: i32) -> VMNICController<'a, C> {
+    pub fn nic(&self, nic_id: NicId) -> VMNICController<C> {
         VMNICController {
-            vm_controller: self,
+            vm_controller: VirtualMachineController {
+                controller: self.controller.clone(),
+                id: self.id,
+            },
             id: nic_id,
         }
     }
+}
 
-    pub fn secgroup_attach(&self, secgroup_id: i32) -> Result<(), Errors> {
-        let resp_txt = self
-            .controller
-            .client
-            .call("one.vm.attachsg", vec![self.id.into(), secgroup_id.into()])?;
-
-        self.controller.parse_resp(resp_txt)
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncVirtualMachineController<C> {
+    /// Return a disk controller allowing to manage the disk
+    pub fn disk(&self, disk_id: DiskId) -> AsyncVMDiskController<C> {
+        AsyncVMDiskController {
+            vm_controller: AsyncVirtualMachineController {
+                controller: self.controller.clone(),
+                id: self.id,
+            },
+            id: disk_id,
+        }
     }
 
-    pub fn secgroup_detach(&self) -> Result<(), Errors> {
-        let resp_txt = self
-            .controller
-            .client
-            .call("one.vm.detachsg", vec![self.id.into()])?;
-
-        self.controller.parse_resp(resp_txt)
+    /// Return a nic controller allowing to manage the disk
+    pub fn nic(&self, nic_id: NicId) -> AsyncVMNICController<C> {
+        AsyncVMNICController {
+            vm_controller: AsyncVirtualMachineController {
+                controller: self.controller.clone(),
+                id: self.id,
+            },
+            id: nic_id,
+        }
     }
+}
 
-    rpc_chmod_method!("one.vm.chmod");
+rpc_methods! {
+    VirtualMachineController, AsyncVirtualMachineController;
 
-    /// Changes the owner/group of a VM. If uid or gid is -1 it will not change
-    pub fn chown(&self, uid: i32, gid: i32) -> Result<(), Errors> {
-        let resp_txt = self
-            .controller
-            .client
-            .call("one.vm.chown", vec![self.id.into(), uid.into(), gid.into()])?;
+    // TODO: add a method per action ?
+    /// Action is the generic method to run any action on the VM
+    fn action(&self, action: Action)
+        => call(self.controller, "one.vm.action", [action.to_string(), self.id]);
 
-        self.controller.parse_resp(resp_txt)
-    }
+    /// Deploy in the selected hostID and/or dsID. Enforce to return error in case of
+    /// overcommitment. Enforce is automatically enabled for non-oneadmin users.
+    /// Set dsID to Select::Any to let OpenNebula choose the datastore.
+    fn deploy(&self, host_id: HostId, enforce: bool, ds_id: Select<DatastoreId>)
+        => call(self.controller, "one.vm.deploy", [self.id, host_id, enforce, ds_id]);
 
-    /// Changes the name of a VM
-    pub fn rename(&self, new_name: &str) -> Result<(), Errors> {
-        let resp_txt = self
-            .controller
-            .client
-            .call("one.vm.rename", vec![self.id.into(), new_name.into()])?;
+    /// Migrate a VM to a target host and/or to another ds, Change::Unchanged keeps the ds
+    fn migrate(
+        &self,
+        host_id: HostId,
+        live: bool,
+        enforce: bool,
+        ds_id: Change<DatastoreId>,
+        migration_type: i32,
+    ) => call(
+        self.controller,
+        "one.vm.migrate",
+        [self.id, host_id, live, enforce, ds_id, migration_type]
+    );
 
-        self.controller.parse_resp(resp_txt)
-    }
+    /// Attach a new disk
+    fn disk_attach(&self, disk_template: &str)
+        => call(self.controller, "one.vm.attach", [self.id, disk_template]);
 
-    /// Changes the capacity of the virtual machine
-    pub fn resize(&self, template: &str, enforce: bool) -> Result<(), Errors> {
-        let resp_txt = self.controller.client.call(
-            "one.vm.resize",
-            vec![self.id.into(), template.into(), enforce.into()],
-        )?;
+    /// Attach a new NIC
+    fn nic_attach(&self, nic_template: &str)
+        => call(self.controller, "one.vm.attachnic", [self.id, nic_template]);
 
-        self.controller.parse_resp(resp_txt)
-    }
+    fn secgroup_attach(&self, secgroup_id: SecurityGroupId)
+        => call(self.controller, "one.vm.attachsg", [self.id, secgroup_id]);
 
-    /// Adds VM content
-    /// * vm_tpl: The new vm contents. Syntax can be the usual attribute=value or XML.
-    /// * policy: Update type: 0: Replace the whole template. 1: Merge new template with the existing one.
-    pub fn update<T: BaseGetters + Display>(
-        &self,
-        vm_tpl: T,
-        policy: UpdateType,
-    ) -> Result<(), Errors> {
-        let resp_txt = self.controller.client.call(
-            "one.vm.update",
-            vec![
-                self.id.into(),
-                vm_tpl.to_string().into(),
-                policy.value().into(),
-            ],
-        )?;
-
-        self.controller.parse_resp(resp_txt)
-    }
+    fn secgroup_detach(&self) => call(self.controller, "one.vm.detachsg", [self.id]);
+
+    /// Changes the capacity of the virtual machine
+    fn resize(&self, template: &str, enforce: bool)
+        => call(self.controller, "one.vm.resize", [self.id, template, enforce]);
 
     /// Udates (appends) a set of supported configuration attributes in
     /// the VM template
-    pub fn update_conf<T: BaseGetters + Display>(&self, vm_tpl: T) -> Result<(), Errors> {
-        let resp_txt = self.controller.client.call(
-            "one.vm.updateconf",
-            vec![self.id.into(), vm_tpl.to_string().into()],
-        )?;
-
-        self.controller.parse_resp(resp_txt)
-    }
-
-    /// Info connects to OpenNebula and fetches the information of the VM
-    pub fn info(&self) -> Result<VirtualMachine, Errors> {
-        let resp_txt = self
-            .controller
-            .client
-            .call("one.vm.info", vec![self.id.into()])?;
-
-        let body = self.controller.parse_body_resp(resp_txt)?;
-        match Resource::try_from(body.as_str()) {
-            Ok(resource) => Ok(VirtualMachine { resource }),
-            Err(e) => Err(Errors::Roca(format!("Failed to parse the resource: {}", e))),
-        }
-    }
+    fn update_conf<T: BaseGetters + Display>(&self, vm_tpl: T)
+        => call(self.controller, "one.vm.updateconf", [self.id, vm_tpl.to_string()]);
 
     // TODO: add an enum for op
     /// Recovers a stuck VM that is waiting for a driver operation
-    pub fn recover(&self, op: i32) -> Result<(), Errors> {
-        let resp_txt = self
-            .controller
-            .client
-            .call("one.vm.recover", vec![self.id.into(), op.into()])?;
-
-        self.controller.parse_resp(resp_txt)
-    }
-
-    // TODO: add an enum for lock level
-    /// Locks the vm following lock level.
-    pub fn lock(&self, level: i32) -> Result<(), Errors> {
-        let resp_txt = self
-            .controller
-            .client
-            .call("one.vm.lock", vec![self.id.into(), level.into()])?;
-
-        self.controller.parse_resp(resp_txt)
-    }
-
-    /// Unlock unlocks the vm.
-    pub fn unlock(&self) -> Result<(), Errors> {
-        let resp_txt = self
-            .controller
-            .client
-            .call("one.vm.unlock", vec![self.id.into()])?;
-
-        self.controller.parse_resp(resp_txt)
-    }
+    fn recover(&self, op: i32) => call(self.controller, "one.vm.recover", [self.id, op]);
 
     /// adds a new scheduled action to the VM
-    pub fn sched_add<T: BaseGetters + Display>(&self, action_tpl: T) -> Result<(), Errors> {
-        let resp_txt = self.controller.client.call(
-            "one.vm.schedadd",
-            vec![self.id.into(), action_tpl.to_string().into()],
-        )?;
-
-        self.controller.parse_resp(resp_txt)
-    }
+    fn sched_add<T: BaseGetters + Display>(&self, action_tpl: T)
+        => call(self.controller, "one.vm.schedadd", [self.id, action_tpl.to_string()]);
 
     /// Updates the scheduled action specified by the action ID attribute
-    pub fn sched_update<T: BaseGetters + Display + 'a>(&self, action_tpl: T) -> Result<(), Errors> {
-        let action_id = action_tpl.get_i64("ID")?;
-
-        let resp_txt = self.controller.client.call(
-            "one.vm.schedupdate",
-            vec![
-                self.id.into(),
-                action_id.into(),
-                action_tpl.to_string().into(),
-            ],
-        )?;
-
-        self.controller.parse_resp(resp_txt)
-    }
+    fn sched_update<T: BaseGetters + Display>(&self, action_tpl: T) => call(
+        self.controller,
+        "one.vm.schedupdate",
+        [self.id, action_tpl.get_i64("ID")?, action_tpl.to_string()]
+    );
 
     /// Deletes the actionId action
-    pub fn sched_delete(&self, action_id: i32) -> Result<(), Errors> {
-        let resp_txt = self
-            .controller
-            .client
-            .call("one.vm.scheddelete", vec![self.id.into(), action_id.into()])?;
-
-        self.controller.parse_resp(resp_txt)
-    }
+    fn sched_delete(&self, action_id: i32)
+        => call(self.controller, "one.vm.scheddelete", [self.id, action_id]);
 
     /// Backup virtual machine
-    pub fn backup(&self, ds_id: i32, reset: bool) -> Result<(), Errors> {
-        let resp_txt = self.controller.client.call(
-            "one.vm.backup",
-            vec![self.id.into(), ds_id.into(), reset.into()],
-        )?;
-
-        self.controller.parse_resp(resp_txt)
-    }
+    fn backup(&self, ds_id: DatastoreId, reset: bool)
+        => call(self.controller, "one.vm.backup", [self.id, ds_id, reset]);
 
     /// Cancel ongoing backup operation
-    pub fn backup_cancel(&self) -> Result<(), Errors> {
-        let resp_txt = self
-            .controller
-            .client
-            .call("one.vm.backupcancel", vec![self.id.into()])?;
-
-        self.controller.parse_resp(resp_txt)
-    }
+    fn backup_cancel(&self) => call(self.controller, "one.vm.backupcancel", [self.id]);
 
     /*
         Missing methods:
@@ -372,159 +285,260 @@ impl<'a, C: RPCCaller> VirtualMachineController<'a, C> {
     */
 }
 
-impl<'a, C: RPCCaller> VMNICController<'a, C> {
-    /// detaches a network interface from the virtual machine
-    pub fn detach(&self) -> Result<(), Errors> {
-        let resp_txt = self.vm_controller.controller.client.call(
-            "one.vm.detachnic",
-            vec![self.vm_controller.id.into(), self.id.into()],
-        )?;
+rpc_methods! {
+    VMNICController, AsyncVMNICController;
 
-        self.vm_controller.controller.parse_resp(resp_txt)
-    }
+    /// detaches a network interface from the virtual machine
+    fn detach(&self) => call(
+        self.vm_controller.controller,
+        "one.vm.detachnic",
+        [self.vm_controller.id, self.id]
+    );
 
     /// Updates (appends) a NIC attributes
     /// * nic_tpl: The new nic contents. Syntax can be the usual attribute=value or XML.
     /// * policy: Update type: 0: Replace the whole NIC. 1: Merge new NIC with the existing one.
-    pub fn update<T: BaseGetters + Display>(
-        &self,
-        nic_tpl: T,
-        policy: UpdateType,
-    ) -> Result<(), Errors> {
-        let resp_txt = self.vm_controller.controller.client.call(
-            "one.vm.updatenic",
-            vec![
-                self.vm_controller.id.into(),
-                self.id.into(),
-                nic_tpl.to_string().into(),
-                policy.value().into(),
-            ],
-        )?;
-
-        self.vm_controller.controller.parse_resp(resp_txt)
-    }
+    fn update<T: BaseGetters + Display>(&self, nic_tpl: T, policy: UpdateType) => call(
+        self.vm_controller.controller,
+        "one.vm.updatenic",
+        [self.vm_controller.id, self.id, nic_tpl.to_string(), policy.value()]
+    );
 }
 
-impl<'a, C: RPCCaller> VMDiskController<'a, C> {
-    /// Detach a disk from a virtual machine
-    pub fn detach(&self) -> Result<(), Errors> {
-        let resp_txt = self.vm_controller.controller.client.call(
-            "one.vm.detach",
-            vec![self.vm_controller.id.into(), self.id.into()],
-        )?;
+rpc_methods! {
+    VMDiskController, AsyncVMDiskController;
 
-        self.vm_controller.controller.parse_resp(resp_txt)
-    }
+    /// Detach a disk from a virtual machine
+    fn detach(&self) => call(
+        self.vm_controller.controller,
+        "one.vm.detach",
+        [self.vm_controller.id, self.id]
+    );
 
     /// Resize a disk of a virtual machine
-    pub fn resize(&self, new_size: i64) -> Result<(), Errors> {
-        let resp_txt = self.vm_controller.controller.client.call(
-            "one.vm.resize",
-            vec![
-                self.vm_controller.id.into(),
-                self.id.into(),
-                new_size.into(),
-            ],
-        )?;
-
-        self.vm_controller.controller.parse_resp(resp_txt)
-    }
+    /// * new_size: the new size of the disk, in MB
+    fn resize(&self, new_size: i64) => call(
+        self.vm_controller.controller,
+        "one.vm.diskresize",
+        [self.vm_controller.id, self.id, new_size.to_string()]
+    );
 
     /// Exports a disk to an image and returns the image ID.
     /// If imageType is empty the default one will be used.
-    /// If snapID is -1 the current image state will be exported
-    pub fn saveas(&self, image_name: i32, image_type: &str, snap_id: i32) -> Result<(), Errors> {
-        let resp_txt = self.vm_controller.controller.client.call(
-            "one.vm.disksaveas",
-            vec![
-                self.vm_controller.id.into(),
-                self.id.into(),
-                image_name.into(),
-                image_type.into(),
-                snap_id.into(),
-            ],
-        )?;
-
-        self.vm_controller.controller.parse_resp(resp_txt)
-    }
-
-    /// Creates a new virtual machine snapshot. name can be empty
-    pub fn snapshot_create(&self, disk_id: i32, desc: &str) -> Result<(), Errors> {
-        let resp_txt = self.vm_controller.controller.client.call(
-            "one.vm.disksnapshotcreate",
-            vec![
-                self.vm_controller.id.into(),
-                self.id.into(),
-                disk_id.into(),
-                desc.into(),
-            ],
-        )?;
-
-        self.vm_controller.controller.parse_resp(resp_txt)
-    }
-
-    /// Deletes a virtual machine snapshot
-    pub fn snapshot_delete(&self, disk_id: i32, snap_id: i32) -> Result<(), Errors> {
-        let resp_txt = self.vm_controller.controller.client.call(
-            "one.vm.disksnapshotdelete",
-            vec![
-                self.vm_controller.id.into(),
-                self.id.into(),
-                disk_id.into(),
-                snap_id.into(),
-            ],
-        )?;
-
-        self.vm_controller.controller.parse_resp(resp_txt)
-    }
+    /// If snapID is None the current image state will be exported
+    fn saveas(
+        &self,
+        image_name: &str,
+        image_type: &str,
+        snap_id: Option<SnapshotId>,
+    ) -> ImageId => call(
+        self.vm_controller.controller,
+        "one.vm.disksaveas",
+        [
+            self.vm_controller.id,
+            self.id,
+            image_name,
+            image_type,
+            snap_id.map_or(-1, i32::from),
+        ]
+    );
+
+    /// Creates a new disk snapshot, name can be empty, and returns the snapshot ID
+    fn snapshot_create(&self, desc: &str) -> SnapshotId => call(
+        self.vm_controller.controller,
+        "one.vm.disksnapshotcreate",
+        [self.vm_controller.id, self.id, desc]
+    );
+
+    /// Deletes a disk snapshot
+    fn snapshot_delete(&self, snap_id: SnapshotId) => call(
+        self.vm_controller.controller,
+        "one.vm.disksnapshotdelete",
+        [self.vm_controller.id, self.id, snap_id]
+    );
 
     /// Revert disk state to a previously taken snapshot
-    pub fn snapshot_revert(&self, disk_id: i32, snap_id: i32) -> Result<(), Errors> {
-        let resp_txt = self.vm_controller.controller.client.call(
-            "one.vm.disksnapshotrevert",
-            vec![
-                self.vm_controller.id.into(),
-                self.id.into(),
-                disk_id.into(),
-                snap_id.into(),
-            ],
-        )?;
-
-        self.vm_controller.controller.parse_resp(resp_txt)
-    }
+    fn snapshot_revert(&self, snap_id: SnapshotId) => call(
+        self.vm_controller.controller,
+        "one.vm.disksnapshotrevert",
+        [self.vm_controller.id, self.id, snap_id]
+    );
 
     /// Renames a snapshot
-    pub fn snapshot_rename(
-        &self,
-        disk_id: i32,
-        snap_id: i32,
-        new_name: &str,
-    ) -> Result<(), Errors> {
-        let resp_txt = self.vm_controller.controller.client.call(
-            "one.vm.disksnapshotrename",
-            vec![
-                self.vm_controller.id.into(),
-                self.id.into(),
-                disk_id.into(),
-                snap_id.into(),
-                new_name.into(),
-            ],
-        )?;
-
-        self.vm_controller.controller.parse_resp(resp_txt)
-    }
+    fn snapshot_rename(&self, snap_id: SnapshotId, new_name: &str) => call(
+        self.vm_controller.controller,
+        "one.vm.disksnapshotrename",
+        [self.vm_controller.id, self.id, snap_id, new_name]
+    );
 }
 
 #[cfg(test)]
 mod test {
 
+    use serde_xmlrpc::Value;
+
     use super::*;
     use crate::{
-        common::permissions::flags::{GRP_A, OTH_UMA, USR_UMA},
+        common::permissions::{
+            flags::{GRP_A, OTH_UMA, USR_UMA},
+            Permissions,
+        },
         prelude::*,
+        testing::{
+            cassette::{Cassette, Interaction, Replayer},
+            xmlrpc,
+        },
     };
 
-    fn create_vm(controller: &Controller<ClientXMLRPC>, name: &str) -> i32 {
+    // the calls made by run_vm_methods, with the exact arguments
+    fn vm_methods_cassette() -> Cassette {
+        let calls: Vec<(&str, Vec<Value>, i32)> = vec![
+            ("one.vm.action", vec!["poweroff".into(), 12.into()], 12),
+            (
+                "one.vm.deploy",
+                vec![12.into(), 3.into(), true.into(), (-1).into()],
+                12,
+            ),
+            (
+                "one.vm.migrate",
+                vec![
+                    12.into(),
+                    4.into(),
+                    true.into(),
+                    false.into(),
+                    (-1).into(),
+                    0.into(),
+                ],
+                12,
+            ),
+            (
+                "one.vm.attachnic",
+                vec![12.into(), "NIC = [ NETWORK_ID = 1 ]".into()],
+                12,
+            ),
+            ("one.vm.attachsg", vec![12.into(), 5.into()], 12),
+            ("one.vm.detachsg", vec![12.into()], 12),
+            (
+                "one.vm.resize",
+                vec![12.into(), "CPU = 2".into(), false.into()],
+                12,
+            ),
+            ("one.vm.recover", vec![12.into(), 2.into()], 12),
+            ("one.vm.scheddelete", vec![12.into(), 1.into()], 12),
+            (
+                "one.vm.backup",
+                vec![12.into(), 100.into(), true.into()],
+                12,
+            ),
+            ("one.vm.backupcancel", vec![12.into()], 12),
+            ("one.vm.detachnic", vec![12.into(), 1.into()], 12),
+            ("one.vm.detach", vec![12.into(), 0.into()], 12),
+            (
+                "one.vm.diskresize",
+                vec![12.into(), 0.into(), "10240".into()],
+                12,
+            ),
+            (
+                "one.vm.disksaveas",
+                vec![12.into(), 0.into(), "backup".into(), "".into(), (-1).into()],
+                25,
+            ),
+        ];
+
+        Cassette {
+            interactions: calls
+                .into_iter()
+                .map(|(method, args, id)| Interaction {
+                    method: method.to_string(),
+                    args,
+                    response: xmlrpc::success_response(id),
+                })
+                .collect(),
+        }
+    }
+
+    fn run_vm_methods(vm: &VirtualMachineController<Replayer>) {
+        vm.action(Action::PowerOff).unwrap();
+        vm.deploy(HostId(3), true, Select::Any).unwrap();
+        vm.migrate(HostId(4), true, false, Change::Unchanged, 0)
+            .unwrap();
+        vm.nic_attach("NIC = [ NETWORK_ID = 1 ]").unwrap();
+        vm.secgroup_attach(SecurityGroupId(5)).unwrap();
+        vm.secgroup_detach().unwrap();
+        vm.resize("CPU = 2", false).unwrap();
+        vm.recover(2).unwrap();
+        vm.sched_delete(1).unwrap();
+        vm.backup(DatastoreId(100), true).unwrap();
+        vm.backup_cancel().unwrap();
+        vm.nic(NicId(1)).detach().unwrap();
+
+        let disk = vm.disk(DiskId(0));
+        disk.detach().unwrap();
+        disk.resize(10240).unwrap();
+        assert_eq!(disk.saveas("backup", "", None).unwrap(), ImageId(25));
+    }
+
+    #[cfg(feature = "async")]
+    async fn run_async_vm_methods(vm: &AsyncVirtualMachineController<Replayer>) {
+        vm.action(Action::PowerOff).await.unwrap();
+        vm.deploy(HostId(3), true, Select::Any).await.unwrap();
+        vm.migrate(HostId(4), true, false, Change::Unchanged, 0)
+            .await
+            .unwrap();
+        vm.nic_attach("NIC = [ NETWORK_ID = 1 ]").await.unwrap();
+        vm.secgroup_attach(SecurityGroupId(5)).await.unwrap();
+        vm.secgroup_detach().await.unwrap();
+        vm.resize("CPU = 2", false).await.unwrap();
+        vm.recover(2).await.unwrap();
+        vm.sched_delete(1).await.unwrap();
+        vm.backup(DatastoreId(100), true).await.unwrap();
+        vm.backup_cancel().await.unwrap();
+        vm.nic(NicId(1)).detach().await.unwrap();
+
+        let disk = vm.disk(DiskId(0));
+        disk.detach().await.unwrap();
+        disk.resize(10240).await.unwrap();
+        assert_eq!(disk.saveas("backup", "", None).await.unwrap(), ImageId(25));
+    }
+
+    // the snapshot and the VM IDs returned by the disk snapshot methods
+    fn disk_snapshot_cassette() -> Cassette {
+        let calls: Vec<(&str, Vec<Value>, i32)> = vec![
+            (
+                "one.vm.disksnapshotcreate",
+                vec![12.into(), 0.into(), "before upgrade".into()],
+                3,
+            ),
+            (
+                "one.vm.disksnapshotrename",
+                vec![12.into(), 0.into(), 3.into(), "upgrade".into()],
+                3,
+            ),
+            (
+                "one.vm.disksnapshotrevert",
+                vec![12.into(), 0.into(), 3.into()],
+                12,
+            ),
+            (
+                "one.vm.disksnapshotdelete",
+                vec![12.into(), 0.into(), 3.into()],
+                3,
+            ),
+        ];
+
+        Cassette {
+            interactions: calls
+                .into_iter()
+                .map(|(method, args, id)| Interaction {
+                    method: method.to_string(),
+                    args,
+                    response: xmlrpc::success_response(id),
+                })
+                .collect(),
+        }
+    }
+
+    fn create_vm(controller: &Controller<Replayer>, name: &str) -> VmId {
         let mut tpl = template::Builder::new();
         tpl.put_str("NAME", name);
         tpl.put_str("CPU", "1");
@@ -539,12 +553,12 @@ mod test {
         println!("{:?}", allocate_response);
         assert!(allocate_response.is_ok());
         let vm_id = allocate_response.unwrap();
-        assert!(vm_id > 0);
+        assert!(vm_id.0 > 0);
 
         vm_id
     }
 
-    fn destroy_vm(vm_controller: VirtualMachineController<ClientXMLRPC>) {
+    fn destroy_vm(vm_controller: VirtualMachineController<Replayer>) {
         // Terminate the virtual_machine
         let terminate_response = vm_controller.action(Action::TerminateHard);
         println!("{:?}", terminate_response);
@@ -554,10 +568,14 @@ mod test {
 
     #[test]
     fn virtual_machine_complex() {
-        let client = ClientXMLRPC::new(
-            String::from("oneadmin:pDi4mFBHue"),
-            String::from("http://192.168.33.10:2633/RPC2"),
-        );
+        // the allocate arguments depend on the XML serialization of the template,
+        // only the order of the methods is checked
+        let client = Replayer::from_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/cassettes/vm_complex.json"
+        ))
+        .unwrap()
+        .ignore_args();
 
         // Create the virtual_machine
         let controller = Controller::new(client);
@@ -576,13 +594,13 @@ mod test {
         match infos {
             Ok(infos) => {
                 assert!(infos.id().is_ok());
-                assert!(infos.id().unwrap() > 0);
+                assert!(infos.id().unwrap().0 > 0);
 
                 assert!(infos.name().is_ok());
                 assert_eq!(infos.name().unwrap(), "roca-test-vm");
 
                 assert!(infos.gid().is_ok());
-                assert_eq!(infos.gid().unwrap(), 0);
+                assert_eq!(infos.gid().unwrap(), GroupId(0));
 
                 assert!(infos.groupname().is_ok());
                 assert_eq!(infos.groupname().unwrap(), "oneadmin");
@@ -611,5 +629,50 @@ mod test {
         }
 
         destroy_vm(vm_controller);
+        assert_eq!(controller.client.remaining(), 0);
+    }
+
+    #[test]
+    fn disk_snapshots() {
+        let controller = Controller::new(Replayer::new(disk_snapshot_cassette()));
+        let disk = controller.virtual_machine(VmId(12)).disk(DiskId(0));
+
+        let snapshot = disk.snapshot_create("before upgrade").unwrap();
+        assert_eq!(snapshot, SnapshotId(3));
+
+        disk.snapshot_rename(snapshot, "upgrade").unwrap();
+        disk.snapshot_revert(snapshot).unwrap();
+        disk.snapshot_delete(snapshot).unwrap();
+        assert_eq!(controller.client.remaining(), 0);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_disk_snapshots() {
+        let controller = AsyncController::new(Replayer::new(disk_snapshot_cassette()));
+        let disk = controller.virtual_machine(VmId(12)).disk(DiskId(0));
+
+        let snapshot = disk.snapshot_create("before upgrade").await.unwrap();
+        assert_eq!(snapshot, SnapshotId(3));
+
+        disk.snapshot_rename(snapshot, "upgrade").await.unwrap();
+        disk.snapshot_revert(snapshot).await.unwrap();
+        disk.snapshot_delete(snapshot).await.unwrap();
+        assert_eq!(controller.client.remaining(), 0);
+    }
+
+    #[test]
+    fn virtual_machine_methods() {
+        let controller = Controller::new(Replayer::new(vm_methods_cassette()));
+        run_vm_methods(&controller.virtual_machine(VmId(12)));
+        assert_eq!(controller.client.remaining(), 0);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_virtual_machine_methods() {
+        let controller = AsyncController::new(Replayer::new(vm_methods_cassette()));
+        run_async_vm_methods(&controller.virtual_machine(VmId(12))).await;
+        assert_eq!(controller.client.remaining(), 0);
     }
 }
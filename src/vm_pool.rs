@@ -4,20 +4,31 @@ use std::fmt::Display;
 
 use xml_doc::{Document, Element};
 
+use crate::common::ids::{HostId, PoolFilter, VmId};
 use crate::common::resource_getters::{GetGroup, GetOwner, ResourceGetters};
 use crate::common::template::builder as template;
 use crate::common::Errors;
+#[cfg(feature = "async")]
+use crate::controller::{AsyncController, AsyncRPCCaller};
 use crate::controller::{Controller, RPCCaller};
-use crate::vm;
+use crate::{rpc_methods, vm};
 
 use crate::common::xml::resource::XMLDocGetters;
 use crate::common::xml::resource_pool::{build_pool, ResourcePool};
+use crate::common::xml::shared_getters::BaseGetters;
 
 #[derive(Debug)]
-pub struct VirtualMachinesController<'a, C: RPCCaller> {
-    pub controller: &'a Controller<C>,
+pub struct VirtualMachinesController<C: RPCCaller> {
+    pub controller: Controller<C>,
 }
 
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncVirtualMachinesController<C: AsyncRPCCaller> {
+    pub controller: AsyncController<C>,
+}
+
+#[derive(Clone)]
 pub struct VirtualMachinePool {
     resource: ResourcePool,
 }
@@ -28,7 +39,9 @@ impl XMLDocGetters for VirtualMachinePool {
     }
 }
 
-impl ResourceGetters for VirtualMachinePool {}
+impl ResourceGetters for VirtualMachinePool {
+    type Id = VmId;
+}
 impl GetGroup for VirtualMachinePool {}
 impl GetOwner for VirtualMachinePool {}
 
@@ -40,47 +53,70 @@ impl Display for VirtualMachinePool {
 
 impl vm::VMShared for VirtualMachinePool {}
 
-impl<'a, C: RPCCaller> VirtualMachinesController<'a, C> {
-    pub fn allocate(&self, template: template::Builder, pending: bool) -> Result<i32, Errors> {
-        let resp_txt = self.controller.client.call(
-            "one.vm.allocate",
-            vec![template.to_string().into(), pending.into()],
-        )?;
+/// A history record returned by the accounting: a VM running on a host during a time range
+#[derive(Clone)]
+pub struct HistoryRecord {
+    resource: ResourcePool,
+}
 
-        let id = self.controller.parse_id_resp(resp_txt)?;
+impl XMLDocGetters for HistoryRecord {
+    fn get_internal(&self) -> (&Document, &Element) {
+        (&self.resource.document, &self.resource.root)
+    }
+}
 
-        Ok(id)
+impl Display for HistoryRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.resource.document.write_str().unwrap())
     }
+}
 
-    pub fn info(&self) -> Result<Vec<VirtualMachinePool>, Errors> {
-        let resp_txt = self.controller.client.call(
-            "one.vmpool.info",
-            vec![(-1).into(), (-1).into(), (-1).into(), (-1).into()],
-        )?;
-
-        let body = self.controller.parse_body_resp(resp_txt)?;
-
-        let mut vms = Vec::new();
-
-        match build_pool(body.as_str(), "VM") {
-            Ok(elements) => {
-                for vm in elements {
-                    vms.push(VirtualMachinePool {
-                        resource: ResourcePool {
-                            document: vm.document,
-                            root: vm.root,
-                        },
-                    })
-                }
-                Ok(vms)
-            }
-            Err(e) => Err(Errors::Roca(format!(
-                "Failed to parse the resource pool: {}",
-                e
-            ))),
-        }
+impl HistoryRecord {
+    pub fn vm_id(&self) -> Result<VmId, Errors> {
+        self.get_id("OID")
+    }
+
+    /// The sequence number of the record in the VM history
+    pub fn seq(&self) -> Result<i64, Errors> {
+        self.get_i64("SEQ")
+    }
+
+    pub fn host_id(&self) -> Result<HostId, Errors> {
+        self.get_id("HID")
     }
 
+    pub fn hostname(&self) -> Result<String, Errors> {
+        self.get("HOSTNAME")
+    }
+
+    /// Start time, as an epoch
+    pub fn start_time(&self) -> Result<i64, Errors> {
+        self.get_i64("STIME")
+    }
+
+    /// End time, as an epoch, 0 if the VM is still on the host
+    pub fn end_time(&self) -> Result<i64, Errors> {
+        self.get_i64("ETIME")
+    }
+}
+
+rpc_methods! {
+    VirtualMachinesController, AsyncVirtualMachinesController;
+
+    fn allocate(&self, template: template::Builder, pending: bool) -> VmId
+        => call(self.controller, "one.vm.allocate", [template.to_string(), pending]);
+
+    fn info(&self) -> Vec<VirtualMachinePool>
+        => call(self.controller, "one.vmpool.info", [-1, -1, -1, -1]) | build_vm_pool;
+
+    /// Returns the accounting records
+    /// * filter: the owners of the VMs
+    /// * start_time: start of the time range as an epoch, -1 for no limit
+    /// * end_time: end of the time range as an epoch, -1 for no limit
+    fn accounting(&self, filter: PoolFilter, start_time: i32, end_time: i32) -> Vec<HistoryRecord>
+        => call(self.controller, "one.vmpool.accounting", [filter, start_time, end_time])
+            | build_history_records;
+
     /*
 
         Missing methods:
@@ -88,20 +124,55 @@ impl<'a, C: RPCCaller> VirtualMachinesController<'a, C> {
         vmpool.infoextended
         vmpool.infoset
         vmpool.monitoring
-        vmpool.accounting
         vmpool.showback
         vmpool.calculateshowback
     */
 }
 
+fn build_history_records(body: &str) -> Result<Vec<HistoryRecord>, Errors> {
+    match build_pool(body, "HISTORY") {
+        Ok(elements) => Ok(elements
+            .into_iter()
+            .map(|resource| HistoryRecord { resource })
+            .collect()),
+        Err(e) => Err(Errors::Roca(format!(
+            "Failed to parse the accounting records: {}",
+            e
+        ))),
+    }
+}
+
+fn build_vm_pool(body: &str) -> Result<Vec<VirtualMachinePool>, Errors> {
+    let mut vms = Vec::new();
+
+    match build_pool(body, "VM") {
+        Ok(elements) => {
+            for vm in elements {
+                vms.push(VirtualMachinePool {
+                    resource: ResourcePool {
+                        document: vm.document,
+                        root: vm.root,
+                    },
+                })
+            }
+            Ok(vms)
+        }
+        Err(e) => Err(Errors::Roca(format!(
+            "Failed to parse the resource pool: {}",
+            e
+        ))),
+    }
+}
+
 #[cfg(test)]
 mod test {
 
     use super::*;
-    use crate::prelude::*;
+    use crate::common::ids::GroupId;
+    use crate::testing::cassette::Replayer;
     use crate::vm::{Action, VirtualMachineController};
 
-    fn create_vm(controller: &Controller<ClientXMLRPC>, name: &str) -> i32 {
+    fn create_vm(controller: &Controller<Replayer>, name: &str) -> VmId {
         let mut tpl = template::Builder::new();
         tpl.put_str("NAME", name);
         tpl.put_str("CPU", "1");
@@ -116,12 +187,12 @@ mod test {
         println!("{:?}", allocate_response);
         assert!(allocate_response.is_ok());
         let vm_id = allocate_response.unwrap();
-        assert!(vm_id > 0);
+        assert!(vm_id.0 > 0);
 
         vm_id
     }
 
-    fn destroy_vm(vm_controller: VirtualMachineController<ClientXMLRPC>) {
+    fn destroy_vm(vm_controller: VirtualMachineController<Replayer>) {
         // Terminate the virtual_machine
         let terminate_response = vm_controller.action(Action::TerminateHard);
         println!("{:?}", terminate_response);
@@ -131,10 +202,14 @@ mod test {
 
     #[test]
     fn virtual_machine_pool() {
-        let client = ClientXMLRPC::new(
-            String::from("oneadmin:pDi4mFBHue"),
-            String::from("http://192.168.33.10:2633/RPC2"),
-        );
+        // the allocate arguments depend on the XML serialization of the template,
+        // only the order of the methods is checked
+        let client = Replayer::from_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/cassettes/vm_pool.json"
+        ))
+        .unwrap()
+        .ignore_args();
 
         // Create the virtual_machine
         let controller = Controller::new(client);
@@ -156,10 +231,10 @@ mod test {
             }
 
             assert!(vm.id().is_ok());
-            assert!(vm.id().unwrap() > 0);
+            assert!(vm.id().unwrap().0 > 0);
 
             assert!(vm.gid().is_ok());
-            assert_eq!(vm.gid().unwrap(), 0);
+            assert_eq!(vm.gid().unwrap(), GroupId(0));
 
             assert!(vm.groupname().is_ok());
             assert_eq!(vm.groupname().unwrap(), "oneadmin");
@@ -172,6 +247,7 @@ mod test {
             assert_eq!(memory.unwrap(), 32);
         }
 
-        destroy_vm(vm_controller)
+        destroy_vm(vm_controller);
+        assert_eq!(controller.client.remaining(), 0);
     }
 }
@@ -0,0 +1,130 @@
+//! The zone module allows to interact with OpenNebula zones
+
+use std::fmt::Display;
+
+use crate::client::failover::{parse_raft_state, RaftState};
+use crate::common::ids::ZoneId;
+#[cfg(feature = "async")]
+use crate::common::operations::{AsyncDelete, AsyncInfo, AsyncRename, AsyncUpdate};
+use crate::common::operations::{Delete, Info, Rename, ResourceKind, Update};
+use crate::common::Errors;
+#[cfg(feature = "async")]
+use crate::controller::{AsyncController, AsyncRPCCaller};
+use crate::controller::{Controller, RPCCaller};
+
+use crate::common::xml::resource_pool::{build_pool, ResourcePool};
+use crate::common::xml::shared_getters::BaseGetters;
+use crate::{common::xml::resource::Resource, define_resource, resource_controller, rpc_methods};
+
+#[derive(Debug)]
+pub struct ZoneController<C: RPCCaller> {
+    pub controller: Controller<C>,
+    pub id: ZoneId,
+}
+
+#[derive(Debug)]
+pub struct ZonesController<C: RPCCaller> {
+    pub controller: Controller<C>,
+}
+
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncZoneController<C: AsyncRPCCaller> {
+    pub controller: AsyncController<C>,
+    pub id: ZoneId,
+}
+
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncZonesController<C: AsyncRPCCaller> {
+    pub controller: AsyncController<C>,
+}
+
+define_resource!(Zone, ZoneId);
+
+resource_controller!(ZoneController, ResourceKind::Zone);
+
+impl<C: RPCCaller> Info for ZoneController<C> {
+    type Output = Zone;
+}
+impl<C: RPCCaller> Rename for ZoneController<C> {}
+impl<C: RPCCaller> Delete for ZoneController<C> {}
+impl<C: RPCCaller> Update for ZoneController<C> {}
+
+#[cfg(feature = "async")]
+resource_controller!(async AsyncZoneController, ResourceKind::Zone);
+
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncInfo for AsyncZoneController<C> {
+    type Output = Zone;
+}
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncRename for AsyncZoneController<C> {}
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncDelete for AsyncZoneController<C> {}
+#[cfg(feature = "async")]
+impl<C: AsyncRPCCaller> AsyncUpdate for AsyncZoneController<C> {}
+
+impl Zone {
+    /// The XML-RPC endpoint of the zone
+    pub fn endpoint(&self) -> Result<String, Errors> {
+        self.template().get("ENDPOINT")
+    }
+}
+
+/// A zone of the zone pool
+#[derive(Clone)]
+pub struct ZonePool {
+    resource: ResourcePool,
+}
+
+impl XMLDocGetters for ZonePool {
+    fn get_internal(&self) -> (&Document, &Element) {
+        (&self.resource.document, &self.resource.root)
+    }
+}
+
+impl ResourceGetters for ZonePool {
+    type Id = ZoneId;
+}
+
+impl Display for ZonePool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.resource.document.write_str().unwrap())
+    }
+}
+
+impl ZonePool {
+    /// The XML-RPC endpoint of the zone
+    pub fn endpoint(&self) -> Result<String, Errors> {
+        self.template().get("ENDPOINT")
+    }
+}
+
+rpc_methods! {
+    ZoneController, AsyncZoneController;
+
+    /// Returns the raft state of the frontend answering the call
+    fn raftstatus(&self) -> RaftState
+        => call(self.controller, "one.zone.raftstatus", []) | parse_raft_state;
+}
+
+rpc_methods! {
+    ZonesController, AsyncZonesController;
+
+    fn info(&self) -> Vec<ZonePool>
+        => call(self.controller, "one.zonepool.info", []) | build_zone_pool;
+}
+
+fn build_zone_pool(body: &str) -> Result<Vec<ZonePool>, Errors> {
+    match build_pool(body, "ZONE") {
+        Ok(elements) => Ok(elements
+            .into_iter()
+            .map(|resource| ZonePool { resource })
+            .collect()),
+        Err(e) => Err(Errors::Roca(format!(
+            "Failed to parse the resource pool: {}",
+            e
+        ))),
+    }
+}